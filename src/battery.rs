@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+/// Reading sysfs is cheap, but there is no need to do it on every tick.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Tracks whether the laptop is running on battery, for `battery-save`.
+pub struct BatteryMonitor {
+    dir: PathBuf,
+    on_battery: bool,
+    last_check: Option<Instant>,
+}
+
+impl BatteryMonitor {
+    pub fn new() -> Self {
+        Self::with_dir(PathBuf::from(POWER_SUPPLY_DIR))
+    }
+
+    fn with_dir(dir: PathBuf) -> Self {
+        BatteryMonitor { dir, on_battery: false, last_check: None }
+    }
+
+    /// Whether the battery is neither charging nor full, re-read at most every 30 seconds.
+    /// Machines without a battery are never on battery.
+    pub fn is_on_battery(&mut self) -> bool {
+        if self.last_check.is_none_or(|last| last.elapsed() >= CHECK_INTERVAL) {
+            self.on_battery = self.read_status().is_some_and(|status| !matches!(status.as_str(), "Charging" | "Full"));
+            self.last_check = Some(Instant::now());
+        }
+        self.on_battery
+    }
+
+    /// The `status` of BAT0, or of the first other `BAT*` supply if there is no BAT0.
+    fn read_status(&self) -> Option<String> {
+        let read = |name: &str| fs::read_to_string(self.dir.join(name).join("status")).ok();
+        let status = read("BAT0").or_else(|| {
+            let mut names: Vec<String> = fs::read_dir(&self.dir)
+                .ok()?
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| name.starts_with("BAT"))
+                .collect();
+            names.sort();
+            names.iter().find_map(|name| read(name))
+        })?;
+        Some(status.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_battery_status_with_fallback() {
+        let dir = std::env::temp_dir().join(format!("polybar-now-playing-test-{}-power", std::process::id()));
+        fs::create_dir_all(dir.join("AC")).unwrap();
+        assert!(!BatteryMonitor::with_dir(dir.clone()).is_on_battery());
+        fs::create_dir_all(dir.join("BAT1")).unwrap();
+        fs::write(dir.join("BAT1/status"), "Discharging\n").unwrap();
+        assert!(BatteryMonitor::with_dir(dir.clone()).is_on_battery());
+        fs::create_dir_all(dir.join("BAT0")).unwrap();
+        fs::write(dir.join("BAT0/status"), "Charging\n").unwrap();
+        let mut monitor = BatteryMonitor::with_dir(dir.clone());
+        assert!(!monitor.is_on_battery());
+        fs::write(dir.join("BAT0/status"), "Discharging\n").unwrap();
+        // Not re-read until the check interval has passed.
+        assert!(!monitor.is_on_battery());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+//! `polybar-now-playing-cmd`: sends one MPRIS command to a player over D-Bus and exits, for
+//! button actions and keyboard shortcuts without `playerctl`.
+//!
+//! ```text
+//! polybar-now-playing-cmd [--player <bus name>] <command> [argument]
+//! ```
+
+#[cfg(not(any(feature = "dbus", feature = "zbus")))]
+compile_error!("one of the dbus and zbus features is needed to talk to D-Bus");
+
+#[path = "../mpris.rs"]
+mod mpris;
+
+use std::error::Error;
+
+#[cfg(not(feature = "zbus"))]
+use dbus::{
+    arg::Variant, blocking::stdintf::org_freedesktop_dbus::Properties, blocking::Connection,
+};
+#[cfg(feature = "zbus")]
+use zbus::{blocking::connection, blocking::fdo::DBusProxy, blocking::Connection, zvariant::Value};
+
+use mpris::{MPRIS_PATH, MPRIS_PREFIX, PLAYERCTLD_BUS_NAME, PLAYER_INTERFACE, TIMEOUT};
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// An argument-less `Player` method such as `Next`.
+    Method(&'static str),
+    /// Move the position by this many microseconds, backwards if negative.
+    Seek(i64),
+    SetVolume(f64),
+    SetShuffle(bool),
+    SetLoop(&'static str),
+}
+
+/// Parses the arguments after the program name into the player's bus name, if given, and
+/// the command.
+fn parse(args: &[String]) -> Result<(Option<String>, Command), String> {
+    let (player, rest) = match args {
+        [flag, player, rest @ ..] if flag == "--player" => (Some(player.clone()), rest),
+        [flag] if flag == "--player" => return Err("--player requires a bus name".to_string()),
+        rest => (None, rest),
+    };
+    let words: Vec<&str> = rest.iter().map(String::as_str).collect();
+    let command = match words.as_slice() {
+        ["play"] => Command::Method("Play"),
+        ["pause"] => Command::Method("Pause"),
+        ["play-pause"] => Command::Method("PlayPause"),
+        ["next"] => Command::Method("Next"),
+        ["previous"] => Command::Method("Previous"),
+        ["stop"] => Command::Method("Stop"),
+        ["seek", delta] => Command::Seek(
+            delta
+                .parse()
+                .map_err(|_| format!("invalid offset: {}", delta))?,
+        ),
+        ["set-volume", volume] => {
+            let volume = volume
+                .parse()
+                .ok()
+                .filter(|volume| (0.0..=1.0).contains(volume))
+                .ok_or_else(|| format!("invalid volume: {}", volume))?;
+            Command::SetVolume(volume)
+        }
+        ["set-shuffle", shuffle] => Command::SetShuffle(
+            shuffle
+                .parse()
+                .map_err(|_| format!("invalid shuffle: {}", shuffle))?,
+        ),
+        ["set-loop", "None"] => Command::SetLoop("None"),
+        ["set-loop", "Track"] => Command::SetLoop("Track"),
+        ["set-loop", "Playlist"] => Command::SetLoop("Playlist"),
+        ["set-loop", status] => return Err(format!("invalid loop status: {}", status)),
+        [] => return Err("missing command".to_string()),
+        _ => return Err(format!("unknown command: {}", words.join(" "))),
+    };
+    Ok((player, command))
+}
+
+/// The bus name of the player called `name`, which may leave out the
+/// `org.mpris.MediaPlayer2.` prefix, among those on the bus.
+fn find_player(names: &[String], name: &str) -> Option<String> {
+    let full = if name.starts_with(MPRIS_PREFIX) {
+        name.to_string()
+    } else {
+        format!("{}{}", MPRIS_PREFIX, name)
+    };
+    names.iter().find(|candidate| **candidate == full).cloned()
+}
+
+#[cfg(not(feature = "zbus"))]
+fn run(player: Option<&str>, command: &Command) -> Result<(), Box<dyn Error>> {
+    let connection = Connection::new_session()?;
+    let dbus = connection.with_proxy("org.freedesktop.DBus", "/", TIMEOUT);
+    let (names,): (Vec<String>,) = dbus.method_call("org.freedesktop.DBus", "ListNames", ())?;
+    // Without --player the command goes to playerctld's active player.
+    let name = player.unwrap_or(PLAYERCTLD_BUS_NAME);
+    let bus_name = find_player(&names, name).ok_or_else(|| format!("no such player: {}", name))?;
+    let proxy = connection.with_proxy(bus_name, MPRIS_PATH, TIMEOUT);
+    match *command {
+        Command::Method(method) => proxy.method_call(PLAYER_INTERFACE, method, ())?,
+        Command::Seek(delta) => proxy.method_call(PLAYER_INTERFACE, "Seek", (delta,))?,
+        Command::SetVolume(volume) => proxy.set(PLAYER_INTERFACE, "Volume", Variant(volume))?,
+        Command::SetShuffle(shuffle) => proxy.set(PLAYER_INTERFACE, "Shuffle", Variant(shuffle))?,
+        Command::SetLoop(status) => proxy.set(PLAYER_INTERFACE, "LoopStatus", Variant(status))?,
+    }
+    Ok(())
+}
+
+#[cfg(feature = "zbus")]
+fn run(player: Option<&str>, command: &Command) -> Result<(), Box<dyn Error>> {
+    let connection = connection::Builder::session()?
+        .method_timeout(TIMEOUT)
+        .build()?;
+    let names: Vec<String> = DBusProxy::new(&connection)?
+        .list_names()?
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    // Without --player the command goes to playerctld's active player.
+    let name = player.unwrap_or(PLAYERCTLD_BUS_NAME);
+    let bus_name = find_player(&names, name).ok_or_else(|| format!("no such player: {}", name))?;
+    let properties = "org.freedesktop.DBus.Properties";
+    match *command {
+        Command::Method(method) => call(&connection, &bus_name, PLAYER_INTERFACE, method, &())?,
+        Command::Seek(delta) => call(&connection, &bus_name, PLAYER_INTERFACE, "Seek", &(delta,))?,
+        Command::SetVolume(volume) => {
+            let body = (PLAYER_INTERFACE, "Volume", Value::from(volume));
+            call(&connection, &bus_name, properties, "Set", &body)?
+        }
+        Command::SetShuffle(shuffle) => {
+            let body = (PLAYER_INTERFACE, "Shuffle", Value::from(shuffle));
+            call(&connection, &bus_name, properties, "Set", &body)?
+        }
+        Command::SetLoop(status) => {
+            let body = (PLAYER_INTERFACE, "LoopStatus", Value::from(status));
+            call(&connection, &bus_name, properties, "Set", &body)?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "zbus")]
+fn call<B>(
+    connection: &Connection,
+    bus_name: &str,
+    interface: &str,
+    method: &str,
+    body: &B,
+) -> zbus::Result<()>
+where
+    B: serde::Serialize + zbus::zvariant::DynamicType,
+{
+    connection.call_method(Some(bus_name), MPRIS_PATH, Some(interface), method, body)?;
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = parse(&args)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|(player, command)| run(player.as_deref(), &command));
+    if let Err(e) = result {
+        eprintln!("polybar-now-playing-cmd: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_words(line: &str) -> Result<(Option<String>, Command), String> {
+        parse(
+            &line
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse_words("--player spotify next"),
+            Ok((Some("spotify".to_string()), Command::Method("Next")))
+        );
+        assert_eq!(
+            parse_words("seek -5000000"),
+            Ok((None, Command::Seek(-5_000_000)))
+        );
+        assert_eq!(
+            parse_words("set-volume 0.5"),
+            Ok((None, Command::SetVolume(0.5)))
+        );
+        assert_eq!(
+            parse_words("set-shuffle true"),
+            Ok((None, Command::SetShuffle(true)))
+        );
+        assert_eq!(
+            parse_words("set-loop Playlist"),
+            Ok((None, Command::SetLoop("Playlist")))
+        );
+        assert!(parse_words("set-volume 1.5").is_err());
+        assert!(parse_words("set-loop track").is_err());
+        assert!(parse_words("--player").is_err());
+        assert!(parse_words("rewind").is_err());
+    }
+
+    #[test]
+    fn finds_players_with_or_without_the_mpris_prefix() {
+        let names = [
+            "org.freedesktop.DBus".to_string(),
+            "org.mpris.MediaPlayer2.spotify".to_string(),
+        ];
+        assert_eq!(
+            find_player(&names, "spotify").as_deref(),
+            Some("org.mpris.MediaPlayer2.spotify")
+        );
+        assert_eq!(
+            find_player(&names, "org.mpris.MediaPlayer2.spotify").as_deref(),
+            Some("org.mpris.MediaPlayer2.spotify")
+        );
+        assert_eq!(find_player(&names, "vlc"), None);
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+#[cfg(not(feature = "zbus"))]
+use std::time::Duration;
+
+#[cfg(not(feature = "zbus"))]
+use dbus::{blocking::Connection, message::MatchRule};
+#[cfg(feature = "zbus")]
+use zbus::{blocking::fdo::DBusProxy, blocking::Connection, blocking::MessageIterator, message::Type, MatchRule};
+
+use crate::refresh;
+use crate::source::{MPRIS_PATH, MPRIS_PREFIX, PLAYER_INTERFACE};
+
+/// Set once the signals can't be followed, after which the main loop polls as usual.
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Wakes the main loop whenever a player changes, for `event-driven`: its properties change,
+/// it seeks, or it joins or leaves the bus. Runs on a background thread with a connection of
+/// its own.
+pub fn spawn() {
+    thread::spawn(|| {
+        if let Err(e) = watch() {
+            warn!("player change signals disabled, polling instead: {}", e);
+            FAILED.store(true, Ordering::Relaxed);
+            // The main loop may be in a long sleep that counted on the signals.
+            refresh::wake();
+        }
+    });
+}
+
+/// Whether player changes are being followed, so the main loop can sleep between them.
+pub fn watching() -> bool {
+    !FAILED.load(Ordering::Relaxed)
+}
+
+#[cfg(not(feature = "zbus"))]
+fn watch() -> Result<(), dbus::Error> {
+    let connection = Connection::new_session()?;
+    // One rule on the MPRIS path covers every player, including ones that start later.
+    let properties = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged").with_path(MPRIS_PATH);
+    connection.add_match(properties, |(): (), _, _| {
+        refresh::wake();
+        true
+    })?;
+    let seeked = MatchRule::new_signal(PLAYER_INTERFACE, "Seeked").with_path(MPRIS_PATH);
+    connection.add_match(seeked, |(): (), _, _| {
+        refresh::wake();
+        true
+    })?;
+    let owners = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+    connection.add_match(owners, |(name,): (String,), _, _| {
+        if name.starts_with(MPRIS_PREFIX) {
+            refresh::wake();
+        }
+        true
+    })?;
+    loop {
+        connection.process(Duration::from_secs(60))?;
+    }
+}
+
+#[cfg(feature = "zbus")]
+fn watch() -> Result<(), zbus::Error> {
+    let connection = Connection::session()?;
+    let dbus = DBusProxy::new(&connection)?;
+    let signal = |interface, member| MatchRule::builder().msg_type(Type::Signal).interface(interface)?.member(member);
+    // One rule on the MPRIS path covers every player, including ones that start later.
+    let properties = signal("org.freedesktop.DBus.Properties", "PropertiesChanged")?.path(MPRIS_PATH)?.build();
+    let seeked = signal(PLAYER_INTERFACE, "Seeked")?.path(MPRIS_PATH)?.build();
+    let owners = signal("org.freedesktop.DBus", "NameOwnerChanged")?.build();
+    for rule in [&properties, &seeked, &owners] {
+        dbus.add_match_rule(rule.clone())?;
+    }
+    for message in MessageIterator::from(&connection) {
+        let message = message?;
+        let changed = if owners.matches(&message)? {
+            message.body().deserialize::<(&str, &str, &str)>().is_ok_and(|(name, _, _)| name.starts_with(MPRIS_PREFIX))
+        } else {
+            properties.matches(&message)? || seeked.matches(&message)?
+        };
+        if changed {
+            refresh::wake();
+        }
+    }
+    Err(zbus::Error::Failure("the session bus connection closed".to_string()))
+}
@@ -0,0 +1,217 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use toml::{Table, Value};
+
+/// How a flag that mirrors a config key takes its value.
+enum Kind {
+    /// `--flag` sets the key to `true`.
+    Switch,
+    /// `--flag <text>`.
+    Text,
+    /// `--flag <integer>`.
+    Number,
+    /// `--flag <value>`, appended to the key's array; may be repeated.
+    List,
+}
+
+/// Flags that override the config key of the same name.
+const CONFIG_FLAGS: [(&str, Kind); 48] = [
+    ("truncate-feat", Kind::Switch),
+    ("truncate-remaster", Kind::Switch),
+    ("title-strip-pattern", Kind::List),
+    ("last-fm-user", Kind::Text),
+    ("last-fm-api-key", Kind::Text),
+    ("musicbrainz-lookup", Kind::Switch),
+    ("spotify-client-id", Kind::Text),
+    ("spotify-client-secret", Kind::Text),
+    ("battery-save", Kind::Switch),
+    ("idle-after-ms", Kind::Number),
+    ("state-restore", Kind::Switch),
+    ("field", Kind::Text),
+    ("startup-delay-ms", Kind::Number),
+    ("wait-for-player", Kind::Text),
+    ("wait-timeout-ms", Kind::Number),
+    ("mqtt-broker", Kind::Text),
+    ("mqtt-topic", Kind::Text),
+    ("mqtt-client-id", Kind::Text),
+    ("mqtt-will-topic", Kind::Text),
+    ("mqtt-will-payload", Kind::Text),
+    ("osc-target", Kind::Text),
+    ("osc-position-interval-ms", Kind::Number),
+    ("screen-lock-pause", Kind::Switch),
+    ("session-unlock-resume", Kind::Switch),
+    ("resume-delay-ms", Kind::Number),
+    ("use-dbus-direct", Kind::Switch),
+    ("static-when-short", Kind::Switch),
+    ("format-static", Kind::Text),
+    ("scroll-volume-on-icon", Kind::Switch),
+    ("volume-step", Kind::Number),
+    ("seek-on-scroll", Kind::Switch),
+    ("seek-scroll-step-seconds", Kind::Number),
+    ("progress-in-text", Kind::Switch),
+    ("summary", Kind::Switch),
+    ("time-format", Kind::Text),
+    ("min-display-width", Kind::Number),
+    ("show-comment", Kind::Switch),
+    ("comment-max-len", Kind::Number),
+    ("event-driven", Kind::Switch),
+    ("show-url", Kind::Switch),
+    ("url-in-suffix", Kind::Switch),
+    ("raw-status", Kind::Switch),
+    ("scroll-when-paused", Kind::Switch),
+    ("smart-mode", Kind::Switch),
+    ("scroll-mode", Kind::Text),
+    ("inhibit-sleep", Kind::Switch),
+    ("text-separator", Kind::Text),
+    ("textless-separator", Kind::Text),
+];
+
+/// Command-line arguments.
+#[derive(Debug, Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub verbose: bool,
+    /// Print the version and what the environment provides, then exit.
+    pub version: bool,
+    /// Read the player's state from this JSON file instead of the session bus.
+    pub dbus_mock_file: Option<PathBuf>,
+    /// Print a single update and exit.
+    pub one_shot: bool,
+    /// Print a single update for a made-up track, to try out formatting options.
+    pub test_output: bool,
+    /// Switch `detail-on-click` between the terse and the detailed text, then exit.
+    pub toggle_detail: bool,
+    /// Config keys set on the command line, applied on top of the config file.
+    pub overrides: Table,
+    /// `--control <action>`: perform a playback action on a player and exit.
+    pub control: Option<String>,
+    /// `--player <bus name>` for `--control`; defaults to the first player found.
+    pub player: Option<String>,
+    /// `ctl <command...>`: send the command to the running instance instead of starting one.
+    pub ctl: Option<String>,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, Box<dyn Error>> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = args.next().ok_or("--config requires a path")?;
+                    parsed.config = Some(PathBuf::from(path));
+                }
+                "-v" | "--verbose" => parsed.verbose = true,
+                "-V" | "--version" => parsed.version = true,
+                "--dbus-mock-file" => {
+                    let path = args.next().ok_or("--dbus-mock-file requires a path")?;
+                    parsed.dbus_mock_file = Some(PathBuf::from(path));
+                }
+                "--one-shot" => parsed.one_shot = true,
+                "--test-output" => parsed.test_output = true,
+                "--toggle-detail" => parsed.toggle_detail = true,
+                "--control" => parsed.control = Some(args.next().ok_or("--control requires an action")?),
+                "--player" => parsed.player = Some(args.next().ok_or("--player requires a bus name")?),
+                "ctl" => {
+                    let command: Vec<String> = args.by_ref().collect();
+                    parsed.ctl = Some(command.join(" "));
+                }
+                _ => {
+                    let key = arg.strip_prefix("--").unwrap_or_default();
+                    let (_, kind) = CONFIG_FLAGS.iter().find(|(flag, _)| *flag == key).ok_or_else(|| format!("unknown argument: {}", arg))?;
+                    let mut value = || args.next().ok_or_else(|| format!("{} requires a value", arg));
+                    match kind {
+                        Kind::Switch => {
+                            parsed.overrides.insert(key.to_string(), Value::Boolean(true));
+                        }
+                        Kind::Text => {
+                            parsed.overrides.insert(key.to_string(), Value::String(value()?));
+                        }
+                        Kind::Number => {
+                            let value = value()?;
+                            let number = match (value.parse(), value.parse()) {
+                                (Ok(integer), _) => Value::Integer(integer),
+                                (_, Ok(float)) => Value::Float(float),
+                                _ => return Err(format!("{} requires a number, got {}", arg, value).into()),
+                            };
+                            parsed.overrides.insert(key.to_string(), number);
+                        }
+                        Kind::List => {
+                            let value = value()?;
+                            let entry = parsed.overrides.entry(key).or_insert_with(|| Value::Array(Vec::new()));
+                            if let Value::Array(values) = entry {
+                                values.push(Value::String(value));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, Box<dyn Error>> {
+        Args::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_config_and_verbose() {
+        let args = parse(&["--verbose", "--config", "/tmp/np.toml"]).unwrap();
+        assert!(args.verbose);
+        assert!(parse(&["--version"]).unwrap().version);
+        assert_eq!(args.config, Some(PathBuf::from("/tmp/np.toml")));
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+
+    #[test]
+    fn maps_flags_to_config_overrides() {
+        let args = parse(&["--truncate-feat", "--title-strip-pattern", "a", "--title-strip-pattern", "b"]).unwrap();
+        assert_eq!(args.overrides.get("truncate-feat"), Some(&Value::Boolean(true)));
+        let patterns = Value::Array(vec![Value::String("a".into()), Value::String("b".into())]);
+        assert_eq!(args.overrides.get("title-strip-pattern"), Some(&patterns));
+        assert!(parse(&["--title-strip-pattern"]).is_err());
+        let args = parse(&["--last-fm-user", "rj"]).unwrap();
+        assert_eq!(args.overrides.get("last-fm-user"), Some(&Value::String("rj".into())));
+        let args = parse(&["--idle-after-ms", "60000"]).unwrap();
+        assert_eq!(args.overrides.get("idle-after-ms"), Some(&Value::Integer(60000)));
+        assert!(parse(&["--idle-after-ms", "soon"]).is_err());
+        let args = parse(&["--volume-step", "0.02"]).unwrap();
+        assert_eq!(args.overrides.get("volume-step"), Some(&Value::Float(0.02)));
+        let args = parse(&["--scroll-mode", "once"]).unwrap();
+        assert_eq!(args.overrides.get("scroll-mode"), Some(&Value::String("once".into())));
+    }
+
+    #[test]
+    fn parses_mock_file_and_one_shot() {
+        let args = parse(&["--dbus-mock-file", "tests/fixtures/playing.json", "--one-shot"]).unwrap();
+        assert_eq!(args.dbus_mock_file, Some(PathBuf::from("tests/fixtures/playing.json")));
+        assert!(args.one_shot);
+        assert!(parse(&["--dbus-mock-file"]).is_err());
+        assert!(parse(&["--test-output"]).unwrap().test_output);
+    }
+
+    #[test]
+    fn parses_control_action() {
+        let args = parse(&["--control", "next", "--player", "org.mpris.MediaPlayer2.mpv"]).unwrap();
+        assert_eq!(args.control.as_deref(), Some("next"));
+        assert_eq!(args.player.as_deref(), Some("org.mpris.MediaPlayer2.mpv"));
+        assert!(parse(&["--control"]).is_err());
+    }
+
+    #[test]
+    fn collects_ctl_command() {
+        let args = parse(&["ctl", "set-width", "30", "--persist"]).unwrap();
+        assert_eq!(args.ctl.as_deref(), Some("set-width 30 --persist"));
+    }
+}
@@ -0,0 +1,593 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::normalize::{ArtistNormConfig, CleanupRules, StationPatterns, TitleNormConfig};
+
+/// Where text shorter than the display width sits within it.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// How far the marquee moves per step.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrollUnit {
+    #[default]
+    Cell,
+    Word,
+}
+
+/// Whether the marquee keeps going or stops after showing the whole text once.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrollMode {
+    #[default]
+    Loop,
+    /// Scroll through once per track, then hold the beginning with an ellipsis.
+    Once,
+}
+
+/// What the display width and the `*-max` limits are counted in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthUnit {
+    /// Terminal columns: CJK and other wide characters count twice. Keeps the module a
+    /// constant width in cells, with a monospace font.
+    #[default]
+    Columns,
+    /// One per character (grapheme cluster), however wide it is drawn. Keeps a constant
+    /// character count, which suits fonts where every glyph has the same advance; with
+    /// ordinary fonts the module then grows and shrinks around wide text.
+    Chars,
+}
+
+/// When to treat the text as right-to-left.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rtl {
+    /// Right-to-left when most of its letters are from RTL scripts.
+    #[default]
+    Auto,
+    Force,
+    Off,
+}
+
+/// Which players the text is about.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Selection {
+    /// One player at a time.
+    #[default]
+    Single,
+    /// Every playing player's icon and title in one string, when more than one is playing.
+    Combined,
+}
+
+/// What to show for a track without a title.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TitleFallback {
+    /// A name derived from `xesam:url`, such as the file name.
+    #[default]
+    Url,
+    /// The missing-field text.
+    None,
+}
+
+/// How `detail-on-click` writes positions and lengths. `m:ss` and `mm:ss` add hours once the
+/// track is an hour or longer, for audiobooks.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub enum TimeFormat {
+    #[default]
+    #[serde(rename = "m:ss")]
+    Minutes,
+    #[serde(rename = "mm:ss")]
+    PaddedMinutes,
+    /// Hours even for short tracks, as `0:03:07`.
+    #[serde(rename = "h:mm:ss")]
+    Hours,
+}
+
+/// Where the player icon before the text comes from.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrefixSource {
+    /// The built-in glyphs for known players.
+    #[default]
+    Config,
+    /// The `Icon=` name from the player's `.desktop` file, e.g. `spotify-client`, for an
+    /// image module beside this one. Players without one get their glyph.
+    DesktopEntry,
+}
+
+/// A change of letter case for `transforms`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Case {
+    Uppercase,
+    Lowercase,
+    /// The first letter of every whitespace-separated word upper-cased, the rest kept as is.
+    Titlecase,
+}
+
+/// A `[remap.<player>]` table, for players that put metadata in the wrong fields. The
+/// `*-from` fields are copied first, then `split-title` is applied.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FieldRemap {
+    /// Split titles like `Artist - Title` at the first occurrence of this separator into
+    /// artist and title. Titles without it are left alone.
+    pub split_title: Option<String>,
+    /// MPRIS key to take the title from instead, e.g. `xesam:album`.
+    pub title_from: Option<String>,
+    pub artist_from: Option<String>,
+    pub album_from: Option<String>,
+}
+
+/// The `[podcast]` table: a `format` of its own for tracks that look like podcast episodes.
+/// A track counts as one if any of the rules matches.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PodcastConfig {
+    /// Template for episodes, e.g. `"{album}: {title}"` for show and episode. Without it
+    /// podcasts are shown like any other track.
+    pub format: Option<String>,
+    /// Words whose appearance in the genre marks a podcast, in any case. Defaults to
+    /// `["podcast"]`.
+    pub genres: Option<Vec<String>>,
+    /// Parts of the names of podcast players, matched like `remap`, e.g. `["gpodder", "kasts"]`.
+    pub players: Vec<String>,
+    /// Tracks at least this many minutes long count as episodes.
+    pub min_length_minutes: Option<u64>,
+}
+
+/// What `transliterate` does with characters it can't spell in ASCII.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Unmapped {
+    /// Show `?` in their place.
+    #[default]
+    Replace,
+    Drop,
+}
+
+/// A `[transforms.<field>]` table: literal `replace` pairs applied in order, then `case`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FieldTransform {
+    pub case: Option<Case>,
+    pub replace: Vec<(String, String)>,
+}
+
+/// One `[[cleanup-rules]]` entry: matches of `pattern` are replaced with `replace`, which may
+/// refer to capture groups as `$1`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CleanupRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub replace: String,
+}
+
+/// User settings read from `config.toml`. Every key is optional and falls back to the
+/// built-in default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Defaults to `left`, or `right` for right-to-left text.
+    pub align: Option<Align>,
+    pub selection: Selection,
+    /// Between the players in `selection = "combined"`. Defaults to `" | "`.
+    pub combined_separator: Option<String>,
+    /// Leave the controls out in `selection = "combined"`; otherwise they control the first
+    /// playing player.
+    pub combined_hide_controls: bool,
+    /// Right-to-left text scrolls the other way and is right-aligned by default.
+    pub rtl: Rtl,
+    pub scroll_unit: ScrollUnit,
+    pub length_unit: LengthUnit,
+    /// `loop` (the default) or `once`. Setting it also keeps `smart-mode` showing the text
+    /// scrolling, rather than the bar or the centred text.
+    pub scroll_mode: Option<ScrollMode>,
+    /// Keep scrolling while the player is paused, to finish reading a long title.
+    pub scroll_when_paused: bool,
+    /// Append the playback rate (e.g. `1.5×`) when it isn't 1.0.
+    pub show_rate: bool,
+    /// Show where the track comes from after the text: the host of a stream's `xesam:url`,
+    /// or the file name of a local file.
+    pub show_url: bool,
+    /// With `show-url`, show it after the controls instead, where it doesn't scroll.
+    pub url_in_suffix: bool,
+    /// Show the track's `xesam:comment`, such as a movement name or episode notes, after the
+    /// controls. It is cut to `comment-max-len` rather than scrolled.
+    pub show_comment: bool,
+    /// Most cells the comment may take. Defaults to 40.
+    pub comment_max_len: Option<usize>,
+    /// Append the position and length, e.g. ` [1:23/4:56]`, while toggled on with
+    /// `--toggle-detail` (bind it to a click on the module).
+    pub detail_on_click: bool,
+    /// Show a bar of the track's progress, `█████░░░░░`, as wide as the display in place of
+    /// the text, for players that report their position and the track's length.
+    pub progress_in_text: bool,
+    /// Choose the display per track: the `progress-in-text` bar when the position and length
+    /// are known, otherwise the text, centred when it fits and scrolled when it doesn't. A
+    /// set `align` still wins over the centring, and a set `scroll-mode` over the choice.
+    pub smart_mode: bool,
+    /// `m:ss` (the default), `mm:ss` or `h:mm:ss`.
+    pub time_format: TimeFormat,
+    /// For tracks without a title, e.g. untagged local files in mpv. Defaults to `url`.
+    pub title_fallback: TitleFallback,
+    /// Shown in place of any field the player doesn't report.
+    pub missing_text: Option<String>,
+    /// Per-field overrides of `missing-text`, keyed by field name (`title`, `artist`).
+    pub missing_field_text: HashMap<String, String>,
+    /// Decode HTML entities such as `&amp;` and `&#39;` that browsers pass through from page
+    /// titles. Defaults to `true`.
+    pub decode_html_entities: Option<bool>,
+    /// Drop featuring credits from the displayed artist.
+    pub truncate_feat: bool,
+    /// Also treat ` & ` as the start of a featuring credit.
+    pub truncate_feat_ampersand: bool,
+    /// Markers that start a featuring credit, replacing the built-in list.
+    pub feat_patterns: Option<Vec<String>>,
+    /// Drop remaster/edition suffixes such as ` (2011 Remaster)` from the displayed title.
+    pub truncate_remaster: bool,
+    /// Extra regexes whose matches are removed from the displayed title.
+    pub title_strip_pattern: Vec<String>,
+    /// Regex replacements applied in order to the title and artist, after the built-in ones.
+    pub cleanup_rules: Vec<CleanupRule>,
+    /// Apply the built-in rules that strip `(Official Video)`, `[HD]`, `| Lyrics`, ` - Topic`
+    /// and the like. Defaults to `true`.
+    pub builtin_cleanup: Option<bool>,
+    /// Clean up internet radio titles, like `CoolFM 101.1 | Artist - Title | www.coolfm.example`,
+    /// for tracks streamed over http(s) without a length. Defaults to `true`.
+    pub stream_cleanup: Option<bool>,
+    /// Regexes for the station clutter `stream-cleanup` drops from stream titles, replacing the
+    /// built-in ones for web addresses, frequencies and `FM`/`Radio` names.
+    pub station_patterns: Option<Vec<String>>,
+    /// Folders left off the front of local file paths shown as `{url}`, e.g.
+    /// `["/home/me/Music/"]`.
+    pub library_roots: Vec<String>,
+    /// Ticks per scroll step for particular players, keyed by a part of the player's name as
+    /// `remap` is, with the longest matching key applying, e.g. `podcast = 2` to scroll long
+    /// episode titles at half speed.
+    pub player_scroll_speed: HashMap<String, usize>,
+    /// Field corrections for particular players, keyed by a part of the player's name as
+    /// the icons are (e.g. `firefox`), applied as soon as the metadata is read. If several
+    /// keys match, the longest applies.
+    pub remap: HashMap<String, FieldRemap>,
+    /// Per-field text changes, keyed by field name (`title`, `artist`), applied after the
+    /// cleanup rules and before truncation, e.g. `transforms.title.replace = [["_", " "]]`.
+    pub transforms: HashMap<String, FieldTransform>,
+    /// Spell the fields in ASCII, for fonts without CJK or Cyrillic glyphs. Applied after
+    /// `transforms`, so everything is single-width by the time widths are measured.
+    pub transliterate: bool,
+    /// What `transliterate` does with characters it has no spelling for. Defaults to `replace`.
+    pub transliterate_unmapped: Unmapped,
+    /// Most cells the title may take before it is cut with an ellipsis.
+    pub title_max: Option<usize>,
+    /// Most cells the artist may take before it is cut with an ellipsis.
+    pub artist_max: Option<usize>,
+    /// Share of the display width per field name (default 1), used when the joined text
+    /// still doesn't fit after the per-field maximums.
+    pub field_ratios: HashMap<String, u32>,
+    /// Polybar font slot (`%{T<n>}`) for the player icon, for icon fonts kept apart from the
+    /// text font. By default the icon is left in the bar's default font.
+    pub prefix_font_index: Option<u32>,
+    /// Polybar font slot for the control buttons, like `prefix-font-index`.
+    pub control_font_index: Option<u32>,
+    /// `config` (the default) or `desktop_entry`.
+    pub prefix_source: PrefixSource,
+    /// Pad the player icon with spaces to this many cells, so icons of different widths
+    /// don't shift the text.
+    pub prefix_width: Option<usize>,
+    /// Replaces the plain join of title and artist, e.g. `"{title}{? - {artist}}"`. Fields
+    /// are `{title}`, `{artist}` and any other `xesam:` key by its short name, such as
+    /// `{album}`, plus `{year}` from `xesam:contentCreated`; `{genre}` is the first genre.
+    /// `{player}`, `{status}`, `{position}` and `{length}` come from the player, as do
+    /// `{progress}`, a bar like `████░░░░░░`, and `{rate}`, e.g. `1.5×`, which is empty at
+    /// the normal rate. `{position}` and `{progress}` are empty for players that don't
+    /// report their position. An unknown field is an
+    /// error. A `{? ...}` group is left out unless all of its fields have a value, and `{{`
+    /// is a literal `{`.
+    pub format: Option<String>,
+    /// A template like `format` shown before the text in full and never scrolled, e.g.
+    /// `"{artist}: "` with `format = "{title}"` so only the title scrolls.
+    pub fixed_format: Option<String>,
+    /// A separate `format` for podcast episodes and the rules that spot them. Their
+    /// `detail-on-click` position counts down the time left.
+    pub podcast: PodcastConfig,
+    /// When the text fits the width and so wouldn't scroll, show `format-static` instead
+    /// (if that fits too), e.g. a terser or differently arranged line for short titles.
+    pub static_when_short: bool,
+    /// A template like `format` for `static-when-short`.
+    pub format_static: Option<String>,
+    /// Pad every line with spaces on the right to at least this many cells, so switching to
+    /// a shorter title doesn't shift the modules after this one.
+    pub min_display_width: Option<usize>,
+    /// Print only the selected player's status, `Playing`, `Paused` or `Stopped`, or `None`
+    /// without a player, e.g. to drive the colours of another module.
+    pub raw_status: bool,
+    /// Print only how many players there are and whether any is playing, e.g. `♫3`, for a
+    /// small indicator module next to the main one.
+    pub summary: bool,
+    /// Print only this one placeholder of `format`, e.g. `artist`, without the player icon or
+    /// controls. Run one instance per field to stack them on a tall bar.
+    pub field: Option<String>,
+    /// Placed between artist and title as is, so include any spacing, e.g. `" · "`.
+    /// Defaults to `" - "`.
+    pub metadata_separator: Option<String>,
+    /// Joins the artists of tracks that list several. Defaults to `", "`.
+    pub artist_separator: Option<String>,
+    /// Placed between the text and the controls. Defaults to `" | "`.
+    pub text_separator: Option<String>,
+    /// Joins the prefix and the controls when there is no text to show. Defaults to `" "`.
+    pub textless_separator: Option<String>,
+    /// Show only the first this many artists, followed by `+k more` for the rest.
+    pub max_artists: Option<usize>,
+    /// Follow playerctld's active player when it is running, instead of picking one ourselves.
+    pub use_playerctld: bool,
+    /// When the selected player drops off the bus, keep showing it as paused for this long
+    /// before moving on, in case it comes right back as Spotify often does.
+    pub sticky_timeout_ms: Option<u64>,
+    /// How many times to re-read an empty player list at startup before showing
+    /// "No player available". Defaults to 3.
+    pub player_settle_retries: Option<u32>,
+    /// Treat a player that reports "Playing" while its position doesn't move as paused, and
+    /// prefer another player that is really playing, e.g. over a browser tab whose video has
+    /// ended. Costs a `Position` read per playing player per update.
+    pub stale_check: bool,
+    /// Sleep this many ms before looking for players at all. Defaults to 0.
+    pub startup_delay_ms: Option<u64>,
+    /// At startup, wait until a player whose bus name contains this appears, for autostart
+    /// setups where the bar comes up before the music daemon.
+    pub wait_for_player: Option<String>,
+    /// Give up on `wait-for-player` after this many ms and start with whatever players
+    /// there are. Defaults to 10000.
+    pub wait_timeout_ms: Option<u64>,
+    /// Last.fm user whose play count for the current track is shown as `♫N`.
+    pub last_fm_user: Option<String>,
+    /// Last.fm API key, required along with `last-fm-user`.
+    pub last_fm_api_key: Option<String>,
+    /// Look up missing album and release date on MusicBrainz.
+    pub musicbrainz_lookup: bool,
+    /// Spotify Web API client id, used to fetch popularity and explicit flags for tracks
+    /// playing in Spotify.
+    pub spotify_client_id: Option<String>,
+    /// Spotify client secret, required along with `spotify-client-id`.
+    pub spotify_client_secret: Option<String>,
+    /// Named pipe that receives a JSON line for every track change.
+    pub event_fifo: Option<PathBuf>,
+    /// Poll three times less often while the laptop runs on battery.
+    pub battery_save: bool,
+    /// Update when players signal a change rather than polling them every tick, with a
+    /// check every 10 s for players that don't. Ticks only continue while the text scrolls.
+    pub event_driven: bool,
+    /// Print an empty line and stop polling players once the user has been idle this long,
+    /// per `xprintidle`.
+    pub idle_after_ms: Option<u64>,
+    /// Repeats of the same `--control` action within this many ms are ignored, so a fast
+    /// double-click doesn't skip two tracks. Defaults to 400.
+    pub control_debounce_ms: Option<u64>,
+    /// Save the scroll position on SIGTERM and pick it up again on the next start if the
+    /// same player is still on the same track, so a polybar reload doesn't restart the text.
+    pub state_restore: bool,
+    /// Shown instead of the metadata while Spotify plays an ad. Defaults to `Advertisement`.
+    pub ad_text: Option<String>,
+    /// Leave the controls out during ads, which can't be skipped anyway.
+    pub ad_hide_controls: bool,
+    /// `host:port` of an MQTT broker to publish each track change to, as a retained JSON
+    /// object, the same one `event-fifo` gets, with the string fields `player` (its bus name),
+    /// `title`, `artist`, `album` and `status` (`Playing`, `Paused` or `Stopped`), e.g.
+    /// `{"player":"org.mpris.MediaPlayer2.spotify","title":"Innuendo","artist":"Queen","album":"Innuendo","status":"Playing"}`.
+    /// Missing fields are empty strings.
+    pub mqtt_broker: Option<String>,
+    /// Defaults to `polybar-now-playing`.
+    pub mqtt_topic: Option<String>,
+    /// Defaults to `polybar-now-playing-<pid>`.
+    pub mqtt_client_id: Option<String>,
+    /// The broker publishes `mqtt-will-payload` here if the connection drops uncleanly.
+    pub mqtt_will_topic: Option<String>,
+    pub mqtt_will_payload: Option<String>,
+    /// `host:port` to send `/nowplaying/title`, `artist`, `status` and `position` OSC
+    /// messages to over UDP on every track change.
+    pub osc_target: Option<String>,
+    /// Also send `/nowplaying/position` this often between track changes.
+    pub osc_position_interval_ms: Option<u64>,
+    /// Pause the player when the screen locks, if it is playing.
+    pub screen_lock_pause: bool,
+    /// With `screen-lock-pause`, resume the player on unlock if the lock paused it.
+    pub session_unlock_resume: bool,
+    /// How long after the unlock to resume. Defaults to 500.
+    pub resume_delay_ms: Option<u64>,
+    /// Keep the system from going idle or suspending while the player is playing, with a logind
+    /// lock that is released when it pauses or stops.
+    pub inhibit_sleep: bool,
+    /// Show a desktop notification when the track changes, replacing the previous one.
+    pub notify: bool,
+    /// Download `http(s)` cover art for notifications into a cache, so daemons that only
+    /// accept local icons can show it.
+    pub notify_download_art: bool,
+    /// Show a mute toggle button for players that expose `Volume`.
+    pub show_mute: bool,
+    /// Change the player's volume by scrolling over its icon.
+    pub scroll_volume_on_icon: bool,
+    /// How much one scroll step changes the volume, out of 1. Defaults to 0.05.
+    pub volume_step: Option<f64>,
+    /// Seek through the track by scrolling over the text, with `polybar-now-playing-cmd`.
+    pub seek_on_scroll: bool,
+    /// How far one scroll step seeks. Defaults to 10.
+    pub seek_scroll_step_seconds: Option<u64>,
+    /// Run the control buttons' MPRIS calls with `polybar-now-playing-cmd` instead of this
+    /// program's `--control`, skipping its config load and double-click debounce.
+    pub use_dbus_direct: bool,
+}
+
+impl Config {
+    /// Loads the config file chosen by `resolve_path()` and applies the command-line
+    /// `overrides` on top. An explicitly requested file must exist; a missing file in the
+    /// default locations just means default settings.
+    pub fn load(cli_path: Option<&Path>, overrides: &Table) -> Result<Self, Box<dyn Error>> {
+        let mut table = Self::read_table(cli_path)?;
+        for (key, value) in overrides {
+            match (table.get_mut(key), value) {
+                (Some(Value::Array(values)), Value::Array(extra)) => values.extend(extra.iter().cloned()),
+                _ => {
+                    table.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Ok(Value::Table(table).try_into()?)
+    }
+
+    fn read_table(cli_path: Option<&Path>) -> Result<Table, Box<dyn Error>> {
+        let resolved = resolve_path(
+            std::env::var_os("POLYBAR_NP_CONFIG").map(PathBuf::from),
+            cli_path.map(Path::to_path_buf),
+            std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from),
+            std::env::var_os("HOME").map(PathBuf::from),
+        );
+        let (path, explicit) = match resolved {
+            Some(resolved) => resolved,
+            None => {
+                verbose!("no config location available, using defaults");
+                return Ok(Table::new());
+            }
+        };
+        if !explicit && !path.exists() {
+            verbose!("config {} not found, using defaults", path.display());
+            return Ok(Table::new());
+        }
+        verbose!("loading config from {}", path.display());
+        let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    pub fn artist_norm(&self) -> ArtistNormConfig {
+        ArtistNormConfig::new(self.truncate_feat, self.truncate_feat_ampersand, self.feat_patterns.clone())
+    }
+
+    pub fn title_norm(&self) -> Result<TitleNormConfig, regex::Error> {
+        TitleNormConfig::new(self.truncate_remaster, &self.title_strip_pattern)
+    }
+
+    pub fn cleanup_rules(&self) -> Result<CleanupRules, regex::Error> {
+        let extra: Vec<_> = self.cleanup_rules.iter().map(|rule| (rule.pattern.clone(), rule.replace.clone())).collect();
+        CleanupRules::new(self.builtin_cleanup.unwrap_or(true), &extra)
+    }
+
+    /// The station patterns for `stream-cleanup`, or `None` with it off.
+    pub fn station_patterns(&self) -> Result<Option<StationPatterns>, regex::Error> {
+        match self.stream_cleanup.unwrap_or(true) {
+            true => StationPatterns::new(self.station_patterns.as_deref()).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    pub fn field_max(&self, field: &str) -> Option<usize> {
+        match field {
+            "title" => self.title_max,
+            "artist" => self.artist_max,
+            _ => None,
+        }
+    }
+
+    /// The text to show for a missing `field`: its own override, then the global one, then
+    /// the built-in `default`.
+    pub fn missing_text<'a>(&'a self, field: &str, default: &'a str) -> &'a str {
+        self.missing_field_text
+            .get(field)
+            .or(self.missing_text.as_ref())
+            .map_or(default, String::as_str)
+    }
+}
+
+/// Picks the config file in order of precedence: `$POLYBAR_NP_CONFIG`, `--config`,
+/// `$XDG_CONFIG_HOME/polybar-now-playing/config.toml`, then `~/.config/...`. The flag is
+/// true when the path was given explicitly rather than derived from a default location.
+fn resolve_path(
+    env_path: Option<PathBuf>,
+    cli_path: Option<PathBuf>,
+    xdg_config_home: Option<PathBuf>,
+    home: Option<PathBuf>,
+) -> Option<(PathBuf, bool)> {
+    if let Some(path) = env_path.filter(|p| !p.as_os_str().is_empty()).or(cli_path) {
+        return Some((path, true));
+    }
+    xdg_config_home
+        .filter(|p| p.is_absolute())
+        .or_else(|| home.map(|home| home.join(".config")))
+        .map(|dir| (dir.join("polybar-now-playing/config.toml"), false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(path: &str) -> Option<PathBuf> {
+        Some(PathBuf::from(path))
+    }
+
+    #[test]
+    fn missing_text_prefers_field_then_global_then_default() {
+        let mut config: Config = toml::from_str("missing-text = \"—\"\n[missing-field-text]\nartist = \"\"").unwrap();
+        assert_eq!(config.missing_text("artist", "No artist"), "");
+        assert_eq!(config.missing_text("title", "No title"), "—");
+        config.missing_text = None;
+        assert_eq!(config.missing_text("title", "No title"), "No title");
+    }
+
+    #[test]
+    fn parses_field_remaps() {
+        let config: Config = toml::from_str("[remap.radio]\nsplit-title = \" - \"\n[remap.cozy]\ntitle-from = \"xesam:album\"").unwrap();
+        assert_eq!(config.remap["radio"].split_title.as_deref(), Some(" - "));
+        assert_eq!(config.remap["cozy"].title_from.as_deref(), Some("xesam:album"));
+        assert!(toml::from_str::<Config>("[remap.radio]\nsplit = \" - \"").is_err());
+    }
+
+    #[test]
+    fn parses_field_transforms() {
+        let config: Config = toml::from_str("[transforms.title]\ncase = \"titlecase\"\nreplace = [[\"_\", \" \"]]").unwrap();
+        let title = &config.transforms["title"];
+        assert_eq!(title.case, Some(Case::Titlecase));
+        assert_eq!(title.replace, [("_".to_string(), " ".to_string())]);
+        assert!(toml::from_str::<Config>("[transforms.title]\ncase = \"shout\"").is_err());
+    }
+
+    #[test]
+    fn parses_podcast_rules() {
+        let config: Config = toml::from_str("[podcast]\nformat = \"{album}: {title}\"\nplayers = [\"kasts\"]\nmin-length-minutes = 45").unwrap();
+        assert_eq!(config.podcast.format.as_deref(), Some("{album}: {title}"));
+        assert_eq!(config.podcast.players, ["kasts"]);
+        assert_eq!(config.podcast.min_length_minutes, Some(45));
+        assert!(toml::from_str::<Config>("[podcast]\nshow = true").is_err());
+    }
+
+    #[test]
+    fn parses_time_formats() {
+        assert_eq!(Config::default().time_format, TimeFormat::Minutes);
+        let config: Config = toml::from_str("time-format = \"h:mm:ss\"").unwrap();
+        assert_eq!(config.time_format, TimeFormat::Hours);
+        assert!(toml::from_str::<Config>("time-format = \"hh:mm\"").is_err());
+    }
+
+    #[test]
+    fn resolves_config_path_by_precedence() {
+        let resolve = |env, cli, xdg, home| resolve_path(env, cli, xdg, home).map(|(path, explicit)| (path.to_string_lossy().into_owned(), explicit));
+        assert_eq!(resolve(p("/env.toml"), p("/cli.toml"), p("/xdg"), p("/home")), Some(("/env.toml".into(), true)));
+        assert_eq!(resolve(None, p("/cli.toml"), p("/xdg"), p("/home")), Some(("/cli.toml".into(), true)));
+        assert_eq!(resolve(None, None, p("/xdg"), p("/home")), Some(("/xdg/polybar-now-playing/config.toml".into(), false)));
+        assert_eq!(resolve(None, None, p("relative"), p("/home")), Some(("/home/.config/polybar-now-playing/config.toml".into(), false)));
+        assert_eq!(resolve(None, None, None, None), None);
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::state;
+
+/// How long a repeated `--control` action is ignored when no `control-debounce-ms` is set.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(400);
+
+/// A playback action for `--control`, sent to the player as an MPRIS method call.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Play,
+    Pause,
+    PlayPause,
+    Next,
+    Previous,
+    Stop,
+}
+
+impl Action {
+    /// The `org.mpris.MediaPlayer2.Player` method that performs the action.
+    pub fn method(self) -> &'static str {
+        match self {
+            Action::Play => "Play",
+            Action::Pause => "Pause",
+            Action::PlayPause => "PlayPause",
+            Action::Next => "Next",
+            Action::Previous => "Previous",
+            Action::Stop => "Stop",
+        }
+    }
+
+    /// The action as written on the command line, which is also `playerctl`'s name for it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Play => "play",
+            Action::Pause => "pause",
+            Action::PlayPause => "play-pause",
+            Action::Next => "next",
+            Action::Previous => "previous",
+            Action::Stop => "stop",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "play" => Ok(Action::Play),
+            "pause" => Ok(Action::Pause),
+            "play-pause" => Ok(Action::PlayPause),
+            "next" => Ok(Action::Next),
+            "previous" => Ok(Action::Previous),
+            "stop" => Ok(Action::Stop),
+            _ => Err(format!("unknown action: {}", action)),
+        }
+    }
+}
+
+/// Where the last action and its time are kept between `--control` invocations.
+pub fn state_path() -> PathBuf {
+    state::runtime_dir().join("polybar-now-playing-control")
+}
+
+/// Records `key` as done now, unless the same key was recorded less than `window` ago, as
+/// when polybar delivers both clicks of a fast double-click. Returns whether to go ahead.
+/// The file is locked while it is read and rewritten, so two clicks handled at once can't
+/// both go ahead. When it can't be opened the action goes ahead without a record.
+pub fn debounce(path: &Path, key: &str, window: Duration, now: SystemTime) -> bool {
+    let now_ms = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let mut file = match OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            verbose!("could not record control action in {}: {}", path.display(), e);
+            return true;
+        }
+    };
+    // Released when the file is closed.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        verbose!("could not lock {}: {}", path.display(), io::Error::last_os_error());
+    }
+    let mut contents = String::new();
+    if file.read_to_string(&mut contents).is_ok() {
+        if let Some((last_key, last_ms)) = contents.trim_end().rsplit_once(' ') {
+            let elapsed = last_ms.parse().map_or(u128::MAX, |last_ms: u128| now_ms.saturating_sub(last_ms));
+            if last_key == key && elapsed < window.as_millis() {
+                return false;
+            }
+        }
+    }
+    let written = file.set_len(0).and_then(|_| file.seek(SeekFrom::Start(0))).and_then(|_| writeln!(file, "{} {}", key, now_ms));
+    if let Err(e) = written {
+        verbose!("could not record control action in {}: {}", path.display(), e);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn parses_actions() {
+        assert_eq!("next".parse(), Ok(Action::Next));
+        assert_eq!("play-pause".parse::<Action>().map(Action::method), Ok("PlayPause"));
+        assert!("rewind".parse::<Action>().is_err());
+        assert_eq!("play-pause".parse::<Action>().map(Action::name), Ok("play-pause"));
+    }
+
+    #[test]
+    fn ignores_repeats_within_the_window() {
+        let path = std::env::temp_dir().join(format!("polybar-now-playing-test-{}-control", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let window = Duration::from_millis(400);
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert!(debounce(&path, "Next spotify", window, start));
+        assert!(!debounce(&path, "Next spotify", window, start + Duration::from_millis(150)));
+        assert!(debounce(&path, "Previous spotify", window, start + Duration::from_millis(200)));
+        assert!(debounce(&path, "Previous spotify", window, start + Duration::from_millis(700)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lets_only_one_of_simultaneous_repeats_through() {
+        let path = std::env::temp_dir().join(format!("polybar-now-playing-test-{}-control-race", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let now = SystemTime::now();
+        let clicks: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || debounce(&path, "Next spotify", DEFAULT_DEBOUNCE, now))
+            })
+            .collect();
+        let went_ahead = clicks.into_iter().map(|click| click.join().unwrap()).filter(|&ahead| ahead).count();
+        assert_eq!(went_ahead, 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::state;
+
+/// How long `peek` shows the full text when no duration is given.
+const DEFAULT_PEEK: Duration = Duration::from_secs(5);
+
+/// A command sent to the running instance with `polybar-now-playing ctl <command>`.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Change the display width. With `persist` the width is also used on the next start.
+    SetWidth { width: usize, persist: bool },
+    /// Mute the player with this bus name, or restore its volume if it is muted.
+    ToggleMute { player: String },
+    /// Change the volume of the player with this bus name by `delta`, staying within 0 to 1.
+    ChangeVolume { player: String, delta: f64 },
+    /// Restart scrolling from the beginning, replaying a finished one-shot reveal.
+    Rescroll,
+    /// Show the full text, ignoring the width, for this long or until the track changes.
+    Peek { duration: Duration },
+    /// Report diagnostics: how many ticks printed a line and how many had nothing new.
+    Status,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["set-width", width, flags @ ..] => {
+                let width = width.parse().ok().filter(|&w| w > 0).ok_or_else(|| format!("invalid width: {}", width))?;
+                let persist = match flags {
+                    [] => false,
+                    ["--persist"] => true,
+                    _ => return Err(format!("unexpected arguments: {}", flags.join(" "))),
+                };
+                Ok(Command::SetWidth { width, persist })
+            }
+            ["toggle-mute", player] => Ok(Command::ToggleMute { player: player.to_string() }),
+            ["volume", player, delta] => {
+                let delta = delta.parse().ok().filter(|delta: &f64| delta.is_finite()).ok_or_else(|| format!("invalid volume change: {}", delta))?;
+                Ok(Command::ChangeVolume { player: player.to_string(), delta })
+            }
+            ["rescroll"] => Ok(Command::Rescroll),
+            ["peek"] => Ok(Command::Peek { duration: DEFAULT_PEEK }),
+            ["peek", seconds] => {
+                let seconds = seconds.parse().ok().filter(|&s| s > 0).ok_or_else(|| format!("invalid duration: {}", seconds))?;
+                Ok(Command::Peek { duration: Duration::from_secs(seconds) })
+            }
+            ["status"] => Ok(Command::Status),
+            [] => Err("missing command".to_string()),
+            _ => Err(format!("unknown command: {}", line.trim())),
+        }
+    }
+}
+
+/// `polybar-now-playing.sock` in the `runtime_dir()`.
+pub fn socket_path() -> PathBuf {
+    state::runtime_dir().join("polybar-now-playing.sock")
+}
+
+/// Sends one command line to the running instance and returns its reply.
+pub fn send(command: &str) -> Result<String, Box<dyn Error>> {
+    let path = socket_path();
+    let mut stream = UnixStream::connect(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    writeln!(stream, "{}", command)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply.trim_end().to_string())
+}
+
+/// The listening side of the control socket, polled once per loop iteration.
+pub struct ControlServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlServer {
+    /// Binds the control socket, replacing a stale one left by a previous instance.
+    pub fn bind() -> io::Result<Self> {
+        let path = socket_path();
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use by another instance", path.display())));
+        }
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        Ok(ControlServer { listener, path })
+    }
+
+    /// Returns the next pending request without blocking.
+    pub fn next_request(&self) -> Option<Request> {
+        let (stream, _) = self.listener.accept().ok()?;
+        stream.set_nonblocking(false).ok()?;
+        stream.set_read_timeout(Some(Duration::from_millis(100))).ok()?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).ok()?;
+        Some(Request { command: line.parse(), stream })
+    }
+}
+
+impl AsRawFd for ControlServer {
+    /// The listening socket, readable while a request waits to be accepted.
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub struct Request {
+    pub command: Result<Command, String>,
+    stream: UnixStream,
+}
+
+impl Request {
+    pub fn reply(mut self, reply: &str) {
+        let _ = writeln!(self.stream, "{}", reply);
+    }
+}
+
+/// Present while `--toggle-detail` has switched `detail-on-click` to the detailed text. Kept
+/// in the runtime dir, so the terse text is back after logging in again.
+fn detail_flag_path() -> PathBuf {
+    state::runtime_dir().join("polybar-now-playing-detail")
+}
+
+pub fn detail_shown() -> bool {
+    detail_flag_path().exists()
+}
+
+/// Switches between the terse and the detailed text, for a polybar click action.
+pub fn toggle_detail() -> io::Result<()> {
+    let path = detail_flag_path();
+    if path.exists() {
+        fs::remove_file(path)
+    } else {
+        fs::write(path, "")
+    }
+}
+
+/// Where `set-width --persist` stores the width between runs.
+fn width_state_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+    Some(dir.join("polybar-now-playing/width"))
+}
+
+pub fn load_persisted_width() -> Option<usize> {
+    let contents = fs::read_to_string(width_state_path()?).ok()?;
+    contents.trim().parse().ok().filter(|&width| width > 0)
+}
+
+pub fn persist_width(width: usize) -> io::Result<()> {
+    let path = width_state_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, format!("{}\n", width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!("set-width 30".parse(), Ok(Command::SetWidth { width: 30, persist: false }));
+        assert_eq!("set-width 12 --persist\n".parse(), Ok(Command::SetWidth { width: 12, persist: true }));
+        assert!("set-width 0".parse::<Command>().is_err());
+        assert!("set-width wide".parse::<Command>().is_err());
+        assert!("set-width 10 --forever".parse::<Command>().is_err());
+        assert!("rewind".parse::<Command>().is_err());
+        assert_eq!("rescroll".parse(), Ok(Command::Rescroll));
+        assert_eq!("status".parse(), Ok(Command::Status));
+        assert_eq!("peek".parse(), Ok(Command::Peek { duration: Duration::from_secs(5) }));
+        assert_eq!("peek 12".parse(), Ok(Command::Peek { duration: Duration::from_secs(12) }));
+        assert!("peek 0".parse::<Command>().is_err());
+        assert_eq!("toggle-mute org.mpris.MediaPlayer2.mpv".parse(), Ok(Command::ToggleMute { player: "org.mpris.MediaPlayer2.mpv".to_string() }));
+        assert_eq!("volume mpv -0.05".parse(), Ok(Command::ChangeVolume { player: "mpv".to_string(), delta: -0.05 }));
+        assert!("volume mpv loud".parse::<Command>().is_err());
+    }
+}
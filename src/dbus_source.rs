@@ -0,0 +1,224 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dbus::arg::{PropMap, RefArg};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::{Connection, Proxy};
+use dbus::channel::MatchingReceiver;
+use dbus::message::MatchRule;
+
+use crate::source::{
+    update_names, BusNames, MediaSource, MetadataValue, MprisMetadata, PlaybackStatus, PlayerCache, PlayerInfo, MPRIS_PATH,
+    MPRIS_PREFIX, PLAYERCTLD_BUS_NAME, PLAYER_INTERFACE, TIMEOUT, UNSUPPORTED_ERRORS,
+};
+
+/// MPRIS players on the session bus, read through libdbus. Built with the `zbus` feature it
+/// is only compiled for the tests, to compare `ZbusMediaSource` against.
+pub struct DbusMediaSource {
+    connection: Connection,
+    /// The MPRIS players' bus names and owners, listed once at startup and from then on kept
+    /// up to date from `NameOwnerChanged`, rather than listing every name on the bus each tick.
+    names: Arc<Mutex<BusNames>>,
+    use_playerctld: bool,
+    cache: RefCell<PlayerCache>,
+}
+
+impl DbusMediaSource {
+    pub fn new(use_playerctld: bool) -> Result<Self, Box<dyn Error>> {
+        let connection = Connection::new_session()?;
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+        connection.add_match_no_cb(&format!("{},arg0namespace='org.mpris.MediaPlayer2'", rule.match_str()))?;
+        let tracked = Arc::clone(&names);
+        connection.start_receive(
+            rule,
+            Box::new(move |message, _| {
+                if let Ok((name, _, new_owner)) = message.read3::<String, String, String>() {
+                    update_names(&mut tracked.lock().unwrap(), name, &new_owner);
+                }
+                true
+            }),
+        );
+        // Listed after subscribing, so a player that starts in between isn't missed.
+        let dbus = connection.with_proxy("org.freedesktop.DBus", "/", TIMEOUT);
+        let (listed,): (Vec<String>,) = dbus.method_call("org.freedesktop.DBus", "ListNames", ())?;
+        for name in listed.into_iter().filter(|name| name.starts_with(MPRIS_PREFIX)) {
+            // A player that left in between has no owner and is skipped.
+            let owner: Result<(String,), _> = dbus.method_call("org.freedesktop.DBus", "GetNameOwner", (&name,));
+            if let Ok((owner,)) = owner {
+                update_names(&mut names.lock().unwrap(), name, &owner);
+            }
+        }
+        Ok(DbusMediaSource { connection, names, use_playerctld, cache: RefCell::default() })
+    }
+
+    /// A proxy for one call. `PlayerInfo` keeps only the owned bus name, so players can be
+    /// cached and cloned without borrowing the connection.
+    fn proxy<'a>(&'a self, bus_name: &'a str) -> Proxy<'a, &'a Connection> {
+        self.connection.with_proxy(bus_name, MPRIS_PATH, TIMEOUT)
+    }
+
+    /// Reads the capabilities and identity of a player new to the bus. One that doesn't
+    /// answer is assumed to be controllable, as most are.
+    fn discover(&self, name: &str) -> PlayerInfo {
+        let mut player = PlayerInfo::new(name.to_string());
+        player.can_control = self.proxy(name).get(PLAYER_INTERFACE, "CanControl").unwrap_or(true);
+        player.identity = self.proxy(name).get("org.mpris.MediaPlayer2", "Identity").unwrap_or_default();
+        player
+    }
+}
+
+impl MediaSource for DbusMediaSource {
+    /// Lists the MPRIS players on the bus. With `use_playerctld` and playerctld running, it is
+    /// the only player, since it already follows whichever player was used last.
+    fn players(&self) -> Result<Vec<PlayerInfo>, Box<dyn Error>> {
+        // Handles the `NameOwnerChanged` signals that came in since the last call.
+        while self.connection.process(Duration::ZERO)? {}
+        let names = self.names.lock().unwrap().clone();
+        let mut cache = self.cache.borrow_mut();
+        cache.forget_stale(&names);
+
+        if self.use_playerctld && names.iter().any(|(name, _)| name == PLAYERCTLD_BUS_NAME) {
+            let mut player = PlayerInfo::new(PLAYERCTLD_BUS_NAME.to_string());
+            let active: Result<Vec<String>, _> = self.proxy(PLAYERCTLD_BUS_NAME).get("com.github.altdesktop.playerctld", "PlayerNames");
+            if let Some(app) = active.ok().and_then(|names| names.into_iter().next()) {
+                player.app = app;
+            }
+            return Ok(vec![player]);
+        }
+
+        let players = names.iter().filter(|(name, _)| name != PLAYERCTLD_BUS_NAME);
+        Ok(players.map(|(name, owner)| cache.player(name, owner, |name| self.discover(name))).collect())
+    }
+
+    fn status(&self, player: &PlayerInfo) -> Result<PlaybackStatus, Box<dyn Error>> {
+        let status: String = self.proxy(&player.name).get(PLAYER_INTERFACE, "PlaybackStatus")?;
+        Ok(status.parse()?)
+    }
+
+    fn metadata(&self, player: &PlayerInfo) -> Result<MprisMetadata, Box<dyn Error>> {
+        let props: PropMap = self.proxy(&player.name).get(PLAYER_INTERFACE, "Metadata")?;
+        Ok(metadata_from_props(&props))
+    }
+
+    fn rate(&self, player: &PlayerInfo) -> Result<f64, Box<dyn Error>> {
+        Ok(self.proxy(&player.name).get(PLAYER_INTERFACE, "Rate")?)
+    }
+
+    /// Many players don't implement `Position`. The first time one says so it is logged and
+    /// remembered, and from then on reading it fails without calling the player.
+    fn position(&self, player: &PlayerInfo) -> Result<i64, Box<dyn Error>> {
+        if self.cache.borrow().lacks_position(player) {
+            return Err("Position is not supported".into());
+        }
+        self.proxy(&player.name).get(PLAYER_INTERFACE, "Position").map_err(|e| {
+            if is_unsupported(&e) {
+                verbose!("{} doesn't support Position, so its position and progress stay blank: {}", player.name, e);
+                self.cache.borrow_mut().set_lacks_position(player, &self.names.lock().unwrap());
+            }
+            e.into()
+        })
+    }
+
+    fn volume(&self, player: &PlayerInfo) -> Result<f64, Box<dyn Error>> {
+        Ok(self.proxy(&player.name).get(PLAYER_INTERFACE, "Volume")?)
+    }
+
+    fn set_volume(&self, player: &PlayerInfo, volume: f64) -> Result<(), Box<dyn Error>> {
+        Ok(self.proxy(&player.name).set(PLAYER_INTERFACE, "Volume", volume)?)
+    }
+
+    /// Asked of the application itself, as playerctld doesn't pass on the root interface.
+    fn desktop_entry(&self, player: &PlayerInfo) -> Result<String, Box<dyn Error>> {
+        Ok(self.proxy(&player.app).get("org.mpris.MediaPlayer2", "DesktopEntry")?)
+    }
+
+    fn call(&self, player: &PlayerInfo, method: &str) -> Result<(), Box<dyn Error>> {
+        Ok(self.proxy(&player.name).method_call(PLAYER_INTERFACE, method, ())?)
+    }
+}
+
+fn metadata_from_props(props: &PropMap) -> MprisMetadata {
+    props.iter().filter_map(|(key, value)| Some((key.clone(), metadata_value(&value.0)?))).collect()
+}
+
+/// Reads a variant, or `None` for types no MPRIS field uses, like nested dicts.
+fn metadata_value(value: &dyn RefArg) -> Option<MetadataValue> {
+    if let Some(text) = value.as_str() {
+        return Some(MetadataValue::Text(text.to_string()));
+    }
+    if let Some(items) = value.as_iter() {
+        return Some(MetadataValue::List(items.filter_map(|item| item.as_str().map(str::to_string)).collect()));
+    }
+    value
+        .as_i64()
+        .or_else(|| value.as_u64().and_then(|number| i64::try_from(number).ok()))
+        .map(MetadataValue::Int)
+        .or_else(|| value.as_f64().map(MetadataValue::Float))
+}
+
+fn is_unsupported(e: &dbus::Error) -> bool {
+    e.name().is_some_and(|name| UNSUPPORTED_ERRORS.contains(&name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_unsupported_properties_from_failed_calls() {
+        assert!(is_unsupported(&dbus::Error::new_custom("org.freedesktop.DBus.Error.UnknownProperty", "no Position")));
+        assert!(!is_unsupported(&dbus::Error::new_custom("org.freedesktop.DBus.Error.NoReply", "timed out")));
+    }
+
+    fn props(entries: Vec<(&str, Box<dyn RefArg>)>) -> PropMap {
+        entries.into_iter().map(|(key, value)| (key.to_string(), dbus::arg::Variant(value))).collect()
+    }
+
+    fn text(value: &str) -> MetadataValue {
+        MetadataValue::Text(value.to_string())
+    }
+
+    #[test]
+    fn reads_spotify_metadata() {
+        let metadata = metadata_from_props(&props(vec![
+            ("mpris:trackid", Box::new(dbus::Path::from("/com/spotify/track/4u7EnebtmKWzUH433cf5Qv"))),
+            ("mpris:length", Box::new(354_320_000u64)),
+            ("mpris:artUrl", Box::new("https://i.scdn.co/image/ab67616d0000b273".to_string())),
+            ("xesam:album", Box::new("A Night at the Opera".to_string())),
+            ("xesam:albumArtist", Box::new(vec!["Queen".to_string()])),
+            ("xesam:artist", Box::new(vec!["Queen".to_string()])),
+            ("xesam:autoRating", Box::new(0.83f64)),
+            ("xesam:discNumber", Box::new(1i32)),
+            ("xesam:title", Box::new("Bohemian Rhapsody - Remastered 2011".to_string())),
+        ]));
+        assert_eq!(metadata["mpris:trackid"], text("/com/spotify/track/4u7EnebtmKWzUH433cf5Qv"));
+        assert_eq!(metadata["mpris:length"], MetadataValue::Int(354_320_000));
+        assert_eq!(metadata["xesam:artist"].to_text(", "), "Queen");
+        assert_eq!(metadata["xesam:autoRating"], MetadataValue::Float(0.83));
+        assert_eq!(metadata["xesam:discNumber"], MetadataValue::Int(1));
+    }
+
+    #[test]
+    fn reads_firefox_and_mpv_metadata() {
+        let firefox = metadata_from_props(&props(vec![
+            ("mpris:trackid", Box::new(dbus::Path::from("/org/mpris/MediaPlayer2/firefox"))),
+            ("xesam:title", Box::new("Lofi beats to study to".to_string())),
+            ("xesam:artist", Box::new(vec!["Lofi Girl".to_string()])),
+            ("xesam:album", Box::new(String::new())),
+            ("mpris:artUrl", Box::new("file:///tmp/firefox-mpris/1_5.png".to_string())),
+        ]));
+        assert_eq!(firefox["xesam:artist"].to_text(", "), "Lofi Girl");
+        assert_eq!(firefox["xesam:album"], text(""));
+        let mpv = metadata_from_props(&props(vec![
+            ("mpris:trackid", Box::new(dbus::Path::from("/io/mpv/tracklist/1"))),
+            ("mpris:length", Box::new(213_000_000i64)),
+            ("xesam:title", Box::new("Under Pressure".to_string())),
+            ("xesam:artist", Box::new(vec!["Queen".to_string(), "David Bowie".to_string()])),
+        ]));
+        assert_eq!(mpv["xesam:artist"].to_text(" & "), "Queen & David Bowie");
+        assert_eq!(mpv["mpris:length"].to_text(", "), "213000000");
+    }
+}
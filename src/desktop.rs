@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::PathBuf;
+
+/// Where `.desktop` files live, most specific first: `$XDG_DATA_HOME` (or `~/.local/share`),
+/// then `$XDG_DATA_DIRS` (or `/usr/local/share:/usr/share`), each with `applications/`,
+/// and the Flatpak exports.
+fn application_dirs() -> Vec<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let data_home = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| home.as_ref().map(|home| home.join(".local/share")));
+    let data_dirs = std::env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".to_string());
+    let flatpak = [home.map(|home| home.join(".local/share/flatpak/exports/share")), Some(PathBuf::from("/var/lib/flatpak/exports/share"))];
+    data_home
+        .into_iter()
+        .chain(data_dirs.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .chain(flatpak.into_iter().flatten())
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// The `Icon=` name of the application whose MPRIS `DesktopEntry` is `entry`, e.g.
+/// `spotify-client` for `spotify`, for `prefix-source = "desktop_entry"`.
+pub fn icon_name(entry: &str) -> Option<String> {
+    icon_name_in(&application_dirs(), entry)
+}
+
+fn icon_name_in(dirs: &[PathBuf], entry: &str) -> Option<String> {
+    // The spec leaves the suffix out, but some players send it anyway.
+    let file = format!("{}.desktop", entry.strip_suffix(".desktop").unwrap_or(entry));
+    let contents = dirs.iter().find_map(|dir| fs::read_to_string(dir.join(&file)).ok())?;
+    parse_icon(&contents)
+}
+
+/// The `Icon` key of the `[Desktop Entry]` group, ignoring those of actions and translations.
+fn parse_icon(contents: &str) -> Option<String> {
+    let mut in_entry = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if let Some(icon) = line.strip_prefix("Icon").filter(|_| in_entry).and_then(|rest| rest.trim_start().strip_prefix('=')) {
+            let icon = icon.trim();
+            return (!icon.is_empty()).then(|| icon.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_icon_of_the_desktop_entry_group() {
+        let contents = "[Desktop Action play]\nIcon=media-play\n\n[Desktop Entry]\nName=Spotify\nIcon[de]=spotify-de\nIcon = spotify-client\n";
+        assert_eq!(parse_icon(contents).as_deref(), Some("spotify-client"));
+        assert_eq!(parse_icon("[Desktop Entry]\nIcon=\n"), None);
+        assert_eq!(parse_icon("Icon=stray\n[Desktop Entry]\nName=x"), None);
+    }
+
+    #[test]
+    fn finds_entries_in_the_first_directory_that_has_them() {
+        let root = std::env::temp_dir().join(format!("polybar-now-playing-desktop-{}", std::process::id()));
+        let (user, system) = (root.join("user"), root.join("system"));
+        fs::create_dir_all(&user).unwrap();
+        fs::create_dir_all(&system).unwrap();
+        fs::write(system.join("vlc.desktop"), "[Desktop Entry]\nIcon=vlc\n").unwrap();
+        fs::write(user.join("vlc.desktop"), "[Desktop Entry]\nIcon=vlc-custom\n").unwrap();
+        let dirs = [user, system];
+        assert_eq!(icon_name_in(&dirs, "vlc").as_deref(), Some("vlc-custom"));
+        assert_eq!(icon_name_in(&dirs, "vlc.desktop").as_deref(), Some("vlc-custom"));
+        assert_eq!(icon_name_in(&dirs, "mpv"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
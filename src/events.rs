@@ -0,0 +1,50 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// Written as one JSON line to the `event-fifo` whenever the track changes.
+#[derive(Debug, Serialize)]
+pub struct TrackEvent<'a> {
+    pub player: &'a str,
+    pub title: &'a str,
+    pub artist: &'a str,
+    pub album: &'a str,
+    pub status: &'a str,
+}
+
+/// Writes the event to the FIFO without ever blocking: if nobody has the FIFO open for
+/// reading, or its buffer is full, the event is dropped.
+pub fn write_event(path: &Path, event: &TrackEvent) -> io::Result<()> {
+    let mut line = serde_json::to_string(event)?;
+    line.push('\n');
+    let mut fifo = OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(path)?;
+    fifo.write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_track_event_as_json() {
+        let event = TrackEvent { player: "spotify", title: "Song \"1\"", artist: "Artist", album: "", status: "Playing" };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"player":"spotify","title":"Song \"1\"","artist":"Artist","album":"","status":"Playing"}"#
+        );
+    }
+
+    #[test]
+    fn skips_fifo_without_reader() {
+        let path = std::env::temp_dir().join(format!("polybar-now-playing-test-{}.fifo", std::process::id()));
+        let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let event = TrackEvent { player: "mpv", title: "", artist: "", album: "", status: "Paused" };
+        let result = write_event(&path, &event);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::ENXIO));
+    }
+}
@@ -0,0 +1,65 @@
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watches how long the user has been idle, using `xprintidle`, on a background thread.
+/// When activity resumes the thread that created the monitor is unparked, so a loop
+/// waiting in `thread::park_timeout()` can re-render straight away.
+pub struct IdleMonitor {
+    idle_flag: Arc<AtomicBool>,
+}
+
+impl IdleMonitor {
+    pub fn spawn(idle_after: Duration) -> Self {
+        let idle_flag = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&idle_flag);
+        let owner = thread::current();
+        thread::spawn(move || loop {
+            let idle = match idle_time() {
+                Ok(idle_time) => idle_time >= idle_after,
+                Err(e) => {
+                    verbose!("idle detection disabled: {}", e);
+                    flag.store(false, Ordering::Relaxed);
+                    owner.unpark();
+                    return;
+                }
+            };
+            if flag.swap(idle, Ordering::Relaxed) && !idle {
+                owner.unpark();
+            }
+            thread::sleep(CHECK_INTERVAL);
+        });
+        IdleMonitor { idle_flag }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle_flag.load(Ordering::Relaxed)
+    }
+}
+
+fn idle_time() -> Result<Duration, String> {
+    let output = Command::new("xprintidle").output().map_err(|e| format!("xprintidle: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("xprintidle exited with {}", output.status));
+    }
+    parse_idle_ms(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| "unexpected xprintidle output".to_string())
+}
+
+fn parse_idle_ms(output: &str) -> Option<Duration> {
+    output.trim().parse().ok().map(Duration::from_millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_xprintidle_output() {
+        assert_eq!(parse_idle_ms("123456\n"), Some(Duration::from_millis(123456)));
+        assert_eq!(parse_idle_ms("couldn't open display"), None);
+    }
+}
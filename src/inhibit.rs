@@ -0,0 +1,73 @@
+use std::os::fd::OwnedFd;
+use std::time::Duration;
+
+#[cfg(not(feature = "zbus"))]
+use dbus::blocking::Connection;
+#[cfg(feature = "zbus")]
+use zbus::blocking::Connection;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const INHIBIT_ARGS: (&str, &str, &str, &str) = ("idle:sleep", "polybar-now-playing", "Music is playing", "block");
+
+/// Keeps the system from going idle or suspending while music plays, for `inhibit-sleep`,
+/// with a logind inhibitor lock. The lock is a file descriptor that logind hands out and
+/// releases once it is closed, which dropping it does. It is a `block` lock, as logind only
+/// allows `delay` ones for sleep and shutdown, not for idle.
+pub struct SleepInhibitor {
+    connection: Connection,
+    inhibitor_fd: Option<OwnedFd>,
+    /// Set when logind refuses, so it isn't asked again on every tick until playback pauses
+    /// and starts again.
+    refused: bool,
+}
+
+impl SleepInhibitor {
+    /// Takes the lock when playback starts and releases it when it pauses or stops.
+    pub fn set_playing(&mut self, playing: bool) {
+        if !playing {
+            self.inhibitor_fd = None;
+            self.refused = false;
+        } else if self.inhibitor_fd.is_none() && !self.refused {
+            match self.inhibit() {
+                Ok(fd) => self.inhibitor_fd = Some(fd),
+                Err(e) => {
+                    warn!("could not inhibit sleep: {}", e);
+                    self.refused = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "zbus"))]
+impl SleepInhibitor {
+    pub fn new() -> Result<Self, dbus::Error> {
+        Ok(SleepInhibitor { connection: Connection::new_system()?, inhibitor_fd: None, refused: false })
+    }
+
+    fn inhibit(&self) -> Result<OwnedFd, dbus::Error> {
+        let proxy = self.connection.with_proxy("org.freedesktop.login1", "/org/freedesktop/login1", TIMEOUT);
+        let (fd,): (OwnedFd,) = proxy.method_call("org.freedesktop.login1.Manager", "Inhibit", INHIBIT_ARGS)?;
+        Ok(fd)
+    }
+}
+
+#[cfg(feature = "zbus")]
+impl SleepInhibitor {
+    pub fn new() -> Result<Self, zbus::Error> {
+        let connection = zbus::blocking::connection::Builder::system()?.method_timeout(TIMEOUT).build()?;
+        Ok(SleepInhibitor { connection, inhibitor_fd: None, refused: false })
+    }
+
+    fn inhibit(&self) -> Result<OwnedFd, zbus::Error> {
+        let reply = self.connection.call_method(
+            Some("org.freedesktop.login1"),
+            "/org/freedesktop/login1",
+            Some("org.freedesktop.login1.Manager"),
+            "Inhibit",
+            &INHIBIT_ARGS,
+        )?;
+        let fd: zbus::zvariant::OwnedFd = reply.body().deserialize()?;
+        Ok(fd.into())
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+const API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+type Track = (String, String);
+
+/// Looks up how often a Last.fm user has scrobbled the current track. Lookups run on a
+/// background thread; until one resolves the previous track's count keeps being shown.
+pub struct LastFm {
+    user: String,
+    api_key: String,
+    cache: HashMap<Track, u32>,
+    current: Option<Track>,
+    pending: Option<(Track, Receiver<Option<u32>>)>,
+    play_count: Option<u32>,
+}
+
+impl LastFm {
+    pub fn new(user: String, api_key: String) -> Self {
+        LastFm { user, api_key, cache: HashMap::new(), current: None, pending: None, play_count: None }
+    }
+
+    /// Notes the track now playing, starting a lookup if it changed and isn't cached.
+    pub fn set_track(&mut self, artist: &str, title: &str) {
+        let track = (artist.to_string(), title.to_string());
+        if self.current.as_ref() == Some(&track) {
+            return;
+        }
+        self.current = Some(track.clone());
+        if let Some(&count) = self.cache.get(&track) {
+            self.play_count = Some(count);
+            self.pending = None;
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let (user, api_key) = (self.user.clone(), self.api_key.clone());
+        let (artist, title) = track.clone();
+        thread::spawn(move || {
+            let count = fetch_play_count(&user, &api_key, &artist, &title)
+                .map_err(|e| verbose!("last.fm lookup for {} - {} failed: {}", artist, title, e))
+                .ok();
+            let _ = sender.send(count);
+        });
+        self.pending = Some((track, receiver));
+    }
+
+    /// The play count to show: the current track's once resolved, the previous one until then.
+    pub fn play_count(&mut self) -> Option<u32> {
+        if let Some((track, receiver)) = &self.pending {
+            match receiver.try_recv() {
+                Ok(count) => {
+                    if let Some(count) = count {
+                        self.cache.insert(track.clone(), count);
+                    }
+                    self.play_count = count;
+                    self.pending = None;
+                }
+                Err(TryRecvError::Disconnected) => self.pending = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        self.play_count
+    }
+}
+
+fn fetch_play_count(user: &str, api_key: &str, artist: &str, title: &str) -> Result<u32, Box<dyn Error>> {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let response: Value = agent
+        .get(API_URL)
+        .query("method", "track.getInfo")
+        .query("format", "json")
+        .query("api_key", api_key)
+        .query("username", user)
+        .query("artist", artist)
+        .query("track", title)
+        .call()?
+        .into_json()?;
+    parse_play_count(&response).ok_or_else(|| "no userplaycount in response".into())
+}
+
+/// Last.fm reports `userplaycount` as a string, but accept a number too.
+fn parse_play_count(response: &Value) -> Option<u32> {
+    match &response["track"]["userplaycount"] {
+        Value::String(count) => count.parse().ok(),
+        Value::Number(count) => count.as_u64().and_then(|count| u32::try_from(count).ok()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_user_play_count() {
+        let response = serde_json::json!({ "track": { "name": "Song", "userplaycount": "42" } });
+        assert_eq!(parse_play_count(&response), Some(42));
+        assert_eq!(parse_play_count(&serde_json::json!({ "track": { "userplaycount": 7 } })), Some(7));
+        assert_eq!(parse_play_count(&serde_json::json!({ "error": 6, "message": "Track not found" })), None);
+    }
+}
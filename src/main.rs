@@ -1,17 +1,99 @@
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Prints a diagnostic to stderr when `--verbose` is given. Stdout belongs to polybar.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if crate::VERBOSE.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Prints a warning to stderr even without `--verbose`, for problems the user should know
+/// about, like an output that stopped working.
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        eprintln!("warning: {}", format_args!($($arg)*))
+    };
+}
+
+#[cfg(not(any(feature = "dbus", feature = "zbus")))]
+compile_error!("one of the dbus and zbus features is needed to talk to D-Bus");
+
+mod battery;
+mod changes;
+mod cli;
+mod config;
+mod control;
+mod ctl;
+#[cfg(all(feature = "dbus", any(not(feature = "zbus"), test)))]
+mod dbus_source;
+mod desktop;
+mod events;
+mod idle;
+mod inhibit;
+mod lastfm;
+mod mpris;
+mod mqtt;
+mod musicbrainz;
+mod normalize;
+mod notify;
+mod osc;
+mod refresh;
+mod sanitize;
+mod screenlock;
+mod scroll;
+mod source;
+mod spotify;
+mod stale;
+mod state;
+mod template;
+mod text;
+mod translit;
+#[cfg(feature = "zbus")]
+mod zbus_source;
+
 use std::collections::HashMap;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::sleep;
-use std::time::Duration;
-use std::sync::Arc;
-use dbus::blocking::Connection;
-use dbus::message::Message;
-use dbus::tree::{MethodErr, MethodResult};
-use dbus::channel::MatchingReceiver;
-use unicode_width::UnicodeWidthStr;
+use std::time::{Duration, Instant};
+
+use battery::BatteryMonitor;
+use cli::Args;
+use config::{Align, Config, PrefixSource, Rtl, ScrollMode, Selection, TimeFormat, TitleFallback};
+use control::Action;
+use ctl::{Command, ControlServer};
+use events::TrackEvent;
+use idle::IdleMonitor;
+use inhibit::SleepInhibitor;
+use lastfm::LastFm;
+use mqtt::{MqttOptions, MqttPublisher};
+use musicbrainz::MusicBrainz;
+use normalize::{ArtistNormConfig, CleanupRules, StationPatterns, TitleNormConfig};
+use notify::Notifier;
+use osc::OscSender;
+use screenlock::ScreenLockMonitor;
+use scroll::Scroller;
+use source::{MediaSource, MetadataValue, MockMediaSource, PlaybackStatus, PlayerInfo, SessionMediaSource, MPRIS_PREFIX, PLAYERCTLD_BUS_NAME};
+use spotify::Spotify;
+use stale::StaleTracker;
+use state::SavedState;
+use template::Template;
 
 const MESSAGE_DISPLAY_LEN: usize = 20;
 const FONT_INDEX: u32 = 1;
 const UPDATE_DELAY: u64 = 300;
+/// How much slower `battery-save` polls while on battery.
+const BATTERY_DELAY_FACTOR: u64 = 3;
+/// Longest wait between checks while idle; the idle monitor cuts it short on activity.
+const IDLE_DELAY: u64 = 5000;
+/// With `event-driven`, how often players are asked anyway, for those that don't signal
+/// their changes.
+const EVENT_FALLBACK_DELAY: u64 = 10_000;
 const CONTROL_CHARS: [&str; 4] = ["", "", "", ""];
+const MUTE_CHARS: [&str; 2] = ["", ""];
 
 const DISPLAY_PLAYER_PREFIX: [(&str, &str); 3] = [
     ("spotify", ""),
@@ -19,206 +101,2352 @@ const DISPLAY_PLAYER_PREFIX: [(&str, &str); 3] = [
     ("default", ""),
 ];
 
-const METADATA_FIELDS: [&str; 2] = ["xesam:title", "xesam:artist"];
-const METADATA_SEPARATOR: char = '-';
+/// MPRIS key, config name and the text shown when the player doesn't report it.
+const METADATA_FIELDS: [(&str, &str, &str); 2] = [
+    ("xesam:title", "title", "No title"),
+    ("xesam:artist", "artist", "No artist"),
+];
+/// Every placeholder the templates may use: the `METADATA_FIELDS`, `year`, what the player
+/// itself reports, and the other `xesam:` fields by name. Anything else fails at startup.
+const PLACEHOLDERS: [&str; 25] = [
+    "title", "artist", "year", "player", "status", "position", "length", "progress", "rate", "album", "albumArtist",
+    "trackNumber", "discNumber", "genre", "comment", "composer", "lyricist", "url", "contentCreated", "asText",
+    "audioBPM", "autoRating", "userRating", "useCount", "lastUsed",
+];
+/// The `mpris:trackid` of players that have no current track.
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+const METADATA_SEPARATOR: &str = " - ";
+const ARTIST_SEPARATOR: &str = ", ";
 const HIDE_OUTPUT: bool = false;
+/// Re-reads of an empty bus name list at startup, `PLAYER_SETTLE_DELAY` ms apart, before
+/// concluding there are no players.
+const PLAYER_SETTLE_RETRIES: u32 = 3;
+const PLAYER_SETTLE_DELAY: u64 = 200;
+/// How long `wait-for-player` waits for a matching player, and how often it looks.
+const WAIT_TIMEOUT: u64 = 10000;
+const WAIT_POLL_INTERVAL: u64 = 500;
+const TEXT_SEPARATOR: &str = " | ";
+const TEXTLESS_SEPARATOR: &str = " ";
+const NO_PLAYER_TEXT: &str = "No player available";
+const AD_TEXT: &str = "Advertisement";
+const COMBINED_SEPARATOR: &str = " | ";
+/// Wait after an unlock before `session-unlock-resume` resumes, as audio can take a moment
+/// to come back.
+const RESUME_DELAY: u64 = 500;
+const MQTT_TOPIC: &str = "polybar-now-playing";
+const VOLUME_STEP: f64 = 0.05;
+const COMMENT_MAX_LEN: usize = 40;
+const SEEK_STEP_SECONDS: u64 = 10;
+const PROGRESS_CHARS: (char, char) = ('█', '░');
+/// Cells of the bar the `{progress}` placeholder renders.
+const PROGRESS_PLACEHOLDER_LEN: usize = 10;
+/// `summary` glyphs for when some player is playing and when none is.
+const SUMMARY_PLAYING: &str = "♫";
+const SUMMARY_PAUSED: &str = "⏸";
+/// Genre words that mark a podcast episode, unless `podcast.genres` says otherwise.
+const PODCAST_GENRES: [&str; 1] = ["podcast"];
 
-struct PlayerInfo {
-    name: String,
-    player: dbus::blocking::Proxy<&'static str>,
+/// The track on display, to detect track changes.
+#[derive(Clone, Debug)]
+struct Track {
+    player: String,
+    /// `mpris:trackid`, if the player reports a real one.
+    id: Option<String>,
+    title: String,
+    artist: String,
 }
 
-impl PlayerInfo {
-    fn new(name: String, player: dbus::blocking::Proxy<&'static str>) -> Self {
-        PlayerInfo { name, player }
+impl Track {
+    /// Tracks are told apart by ID when both have one, so two songs of the same name in a row
+    /// still count as different, and by title and artist otherwise.
+    fn is_same(&self, other: &Track) -> bool {
+        self.player == other.player
+            && match (&self.id, &other.id) {
+                (Some(id), Some(other_id)) => id == other_id,
+                _ => (&self.title, &self.artist) == (&other.title, &other.artist),
+            }
     }
 }
 
+/// What the scroller's text was last built from, so a tick that fetches the same again can
+/// skip rebuilding it.
+#[derive(PartialEq)]
+struct Fetch {
+    player: String,
+    /// The metadata with the looked-up supplement, which includes the track ID, title and artist.
+    metadata: HashMap<String, String>,
+    width: usize,
+    /// The formatted `show-rate` suffix, empty at the normal rate.
+    rate: String,
+    /// The track looks like a podcast episode, so `podcast.format` applies.
+    podcast: bool,
+    /// `{player}`, `{status}`, `{length}` and, only when a template shows them, `{position}`,
+    /// which changes the text every second, and `{rate}`.
+    placeholders: Vec<(&'static str, String)>,
+}
+
+/// What `choose_display_mode()` goes by besides the track's metadata.
+struct DisplayConfig {
+    /// The player's position, if it reports one.
+    position: Option<i64>,
+    text_length: usize,
+    display_len: usize,
+    /// A `scroll-mode` the user set, which forces the scrolling text.
+    scroll_mode: Option<ScrollMode>,
+}
+
+/// What `smart-mode` shows for a track.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DisplayMode {
+    /// The `progress-in-text` bar.
+    Progress,
+    /// The text, centred, as it fits.
+    Static,
+    /// The text, scrolling.
+    Scroll,
+}
+
 struct PolybarNowPlaying {
-    connection: Connection,
+    config: Config,
+    artist_norm: ArtistNormConfig,
+    title_norm: TitleNormConfig,
+    cleanup: CleanupRules,
+    /// Set with `stream-cleanup`: what in a radio stream's title names the station.
+    stations: Option<StationPatterns>,
+    /// The parsed `format`, replacing the plain `title - artist` join when set.
+    format: Option<Template>,
+    source: Box<dyn MediaSource>,
+    control: Option<ControlServer>,
+    battery: Option<BatteryMonitor>,
+    idle: Option<IdleMonitor>,
+    screen_lock: Option<ScreenLockMonitor>,
+    /// Set with `inhibit-sleep`: holds off suspend while the player is playing.
+    inhibitor: Option<SleepInhibitor>,
+    /// The player was paused by `screen-lock-pause` rather than by the user, so
+    /// `session-unlock-resume` may resume it.
+    auto_paused: bool,
+    /// When `session-unlock-resume` is due to resume the player after an unlock.
+    resume_at: Option<Instant>,
+    lastfm: Option<LastFm>,
+    musicbrainz: Option<MusicBrainz>,
+    spotify: Option<Spotify>,
+    mqtt: Option<MqttPublisher>,
+    osc: Option<OscSender>,
+    notifier: Option<Notifier>,
+    /// Fields looked up for the current track that its player doesn't report.
+    metadata_supplement: HashMap<String, String>,
+    last_track: Option<Track>,
+    /// The last fetch and the text built from it.
+    last_fetch: Option<(Fetch, String)>,
+    /// The last line printed, so an identical line isn't printed again.
+    last_output: Option<String>,
+    /// Ticks that printed a line and ticks that had nothing new to print, for `ctl status`.
+    rendered_ticks: u64,
+    skipped_ticks: u64,
     players: Vec<PlayerInfo>,
+    /// The selected player and when it dropped off the bus, while `sticky-timeout-ms` keeps
+    /// it on display.
+    vanished: Option<(PlayerInfo, Instant)>,
+    /// Set with `stale-check`: players stuck on "Playing" count as paused.
+    stale: Option<StaleTracker>,
     current_player: usize,
     display_prefix: String,
     display_suffix: String,
+    scroller: Scroller,
+    display_len: usize,
     status_paused: bool,
+    /// Counts scroll ticks, so a player with a `player-scroll-speed` of n steps every nth.
+    tick_counter: usize,
+    scroll_ticks_per_step: usize,
+    /// The application `scroll_ticks_per_step` was looked up for.
+    scroll_speed_app: Option<String>,
+    /// Set by `ctl peek`: until then the full text is shown and scrolling holds still.
+    peek_until: Option<Instant>,
+    /// `Icon=` names from `.desktop` files by application bus name, for
+    /// `prefix-source = "desktop_entry"`, so each is only looked up once.
+    desktop_icons: HashMap<String, Option<String>>,
+    /// Volume each player had before `ctl toggle-mute` muted it, keyed by bus name.
+    pre_mute_volume: HashMap<String, f64>,
+    /// The parsed `fixed-format`, shown before the scrolling text without scrolling itself.
+    fixed_format: Option<Template>,
+    /// The parsed `format-static`, used with `static-when-short` for text that fits.
+    format_static: Option<Template>,
+    /// The parsed `podcast.format`, used in place of `format` for podcast episodes.
+    podcast_format: Option<Template>,
+    /// `fixed-format` rendered for the current track.
+    fixed_text: String,
+    /// The `progress-in-text` bar, shown in place of the text when known.
+    progress: Option<String>,
+    /// What `smart-mode` chose for the current track, `None` without it.
+    display_mode: Option<DisplayMode>,
+    /// Commands run by scrolling up and down over the text, with `seek-on-scroll`.
+    seek_commands: Option<(String, String)>,
+    /// ` [position/length]` shown after the text while `detail-on-click` is toggled on.
+    detail: String,
+    /// An ad is on, shown as `ad-text` without scrolling.
+    showing_ad: bool,
+    /// `mpris:trackid` of the track on display, saved on exit for `state-restore`.
+    track_id: Option<String>,
+    /// State saved by the previous instance, applied on the first update if it is still
+    /// showing the same track.
+    pending_restore: Option<SavedState>,
 }
 
 impl PolybarNowPlaying {
-    fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let connection = Connection::new_session()?;
-        let players = PolybarNowPlaying::get_players(&connection)?;
-        let mut instance = PolybarNowPlaying {
-            connection,
+    fn new(config: Config, source: Box<dyn MediaSource>) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(ms) = config.startup_delay_ms {
+            sleep(Duration::from_millis(ms));
+        }
+        let mut players = match &config.wait_for_player {
+            Some(pattern) => {
+                let timeout = Duration::from_millis(config.wait_timeout_ms.unwrap_or(WAIT_TIMEOUT));
+                wait_for_player(source.as_ref(), pattern, timeout)?
+            }
+            None => source.players()?,
+        };
+        // Right after login the bus can briefly list none of the players that are starting up.
+        for _ in 0..config.player_settle_retries.unwrap_or(PLAYER_SETTLE_RETRIES) {
+            if !players.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(PLAYER_SETTLE_DELAY));
+            players = source.players()?;
+        }
+        let lastfm = match (&config.last_fm_user, &config.last_fm_api_key) {
+            (Some(user), Some(api_key)) => Some(LastFm::new(user.clone(), api_key.clone())),
+            _ => None,
+        };
+        let musicbrainz = config.musicbrainz_lookup.then(MusicBrainz::default);
+        let spotify = match (&config.spotify_client_id, &config.spotify_client_secret) {
+            (Some(id), Some(secret)) => Some(Spotify::new(id.clone(), secret.clone())),
+            _ => None,
+        };
+        let mqtt = config
+            .mqtt_broker
+            .as_ref()
+            .map(|broker| {
+                MqttPublisher::spawn(MqttOptions {
+                    broker: broker.clone(),
+                    topic: config.mqtt_topic.clone().unwrap_or_else(|| MQTT_TOPIC.to_string()),
+                    client_id: config.mqtt_client_id.clone().unwrap_or_else(|| format!("polybar-now-playing-{}", std::process::id())),
+                    will: config.mqtt_will_topic.clone().map(|topic| (topic, config.mqtt_will_payload.clone().unwrap_or_default())),
+                })
+            })
+            .transpose()?;
+        let osc = config.osc_target.as_deref().and_then(|target| {
+            let interval = config.osc_position_interval_ms.map(Duration::from_millis);
+            OscSender::new(target, interval).map_err(|e| verbose!("osc output disabled: {}", e)).ok()
+        });
+        let screen_lock = config.screen_lock_pause.then(ScreenLockMonitor::spawn);
+        let inhibitor = match config.inhibit_sleep {
+            true => SleepInhibitor::new().map_err(|e| warn!("sleep inhibiting disabled, logind is unavailable: {}", e)).ok(),
+            false => None,
+        };
+        let notifier = config.notify.then(|| Notifier::spawn(config.notify_download_art));
+        let fixed_format = config.fixed_format.as_deref().map(parse_format).transpose()?;
+        let format_static = config.format_static.as_deref().map(parse_format).transpose()?;
+        // A `field` output is one placeholder wherever the track came from.
+        let podcast_format = match config.field {
+            Some(_) => None,
+            None => config.podcast.format.as_deref().map(parse_format).transpose()?,
+        };
+        let stale = config.stale_check.then(StaleTracker::default);
+        let scroller = Scroller::new(config.scroll_unit, config.scroll_mode.unwrap_or_default(), config.length_unit);
+        let battery = config.battery_save.then(BatteryMonitor::new);
+        let idle = config.idle_after_ms.map(|ms| IdleMonitor::spawn(Duration::from_millis(ms)));
+        let pending_restore = if config.state_restore { state::load(&state::path()) } else { None };
+        Ok(PolybarNowPlaying {
+            artist_norm: config.artist_norm(),
+            title_norm: config.title_norm()?,
+            cleanup: config.cleanup_rules()?,
+            stations: config.station_patterns()?,
+            format: match &config.field {
+                Some(field) => Some(check_placeholders(Template::field(field))?),
+                None => config.format.as_deref().map(parse_format).transpose()?,
+            },
+            config,
+            source,
+            control: None,
+            battery,
+            idle,
+            screen_lock,
+            inhibitor,
+            auto_paused: false,
+            resume_at: None,
+            lastfm,
+            musicbrainz,
+            spotify,
+            mqtt,
+            osc,
+            notifier,
+            metadata_supplement: HashMap::new(),
+            last_track: None,
+            last_fetch: None,
+            last_output: None,
+            rendered_ticks: 0,
+            skipped_ticks: 0,
             players,
+            vanished: None,
+            stale,
             current_player: 0,
+            tick_counter: 0,
+            scroll_ticks_per_step: 1,
+            scroll_speed_app: None,
             display_prefix: String::new(),
             display_suffix: String::new(),
+            scroller,
+            display_len: MESSAGE_DISPLAY_LEN,
             status_paused: false,
-        };
-        instance.update_players()?;
-        Ok(instance)
+            peek_until: None,
+            desktop_icons: HashMap::new(),
+            pre_mute_volume: HashMap::new(),
+            fixed_format,
+            format_static,
+            podcast_format,
+            fixed_text: String::new(),
+            progress: None,
+            display_mode: None,
+            seek_commands: None,
+            detail: String::new(),
+            showing_ad: false,
+            track_id: None,
+            pending_restore,
+        })
     }
 
-    fn get_players(connection: &Connection) -> Result<Vec<PlayerInfo>, Box<dyn std::error::Error>> {
-        let names = connection.list_names()?;
-        let mut players = Vec::new();
-
-        for name in names {
-            if name.starts_with("org.mpris.MediaPlayer2.") {
-                let proxy = connection.with_proxy(&name, "/org/mpris/MediaPlayer2", Duration::from_millis(5000));
-                players.push(PlayerInfo::new(name.to_string(), proxy));
+    fn update_players(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let selected = self.players.get(self.current_player).cloned();
+        self.players = self.source.players()?;
+        if let Some(timeout) = self.config.sticky_timeout_ms.map(Duration::from_millis) {
+            let selected = self.vanished.as_ref().map(|(player, _)| player.clone()).or(selected);
+            match selected {
+                Some(selected) => match self.players.iter().position(|player| player.name == selected.name) {
+                    Some(index) => {
+                        self.current_player = index;
+                        self.vanished = None;
+                    }
+                    None => {
+                        let since = self.vanished.as_ref().map_or_else(Instant::now, |&(_, since)| since);
+                        self.vanished = (since.elapsed() < timeout).then_some((selected, since));
+                    }
+                },
+                None => self.vanished = None,
             }
         }
-
-        Ok(players)
-    }
-
-    fn update_players(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.players = PolybarNowPlaying::get_players(&self.connection)?;
         if self.current_player >= self.players.len() {
             self.current_player = 0;
         }
+        self.check_staleness();
         Ok(())
     }
 
-    fn get_status(&self, player: &PlayerInfo) -> Result<String, Box<dyn std::error::Error>> {
-        let status: String = player.player.method_call("org.freedesktop.DBus.Properties", "Get",
-            ("org.mpris.MediaPlayer2.Player", "PlaybackStatus"))?.read1()?;
-        Ok(status)
+    /// Reads the position of every "Playing" player for `stale-check`, and moves off the
+    /// current player if it is stale and another one is really playing.
+    fn check_staleness(&mut self) {
+        let Some(stale) = &mut self.stale else { return };
+        let mut playing = Vec::new();
+        for (index, player) in self.players.iter().enumerate() {
+            let position = match self.source.status(player) {
+                Ok(PlaybackStatus::Playing) => self.source.position(player).ok(),
+                _ => None,
+            };
+            match position {
+                Some(position) if !stale.observe(&player.name, position) => playing.push(index),
+                Some(_) => {}
+                None => stale.reset(&player.name),
+            }
+        }
+        let current_stale = self.players.get(self.current_player).is_some_and(|player| stale.is_stale(&player.name));
+        if let (true, Some(&index)) = (current_stale, playing.first()) {
+            verbose!("{} looks stale, switching to {}", self.players[self.current_player].name, self.players[index].name);
+            self.current_player = index;
+        }
     }
 
+    /// Reads the `Rate` property. Players that don't implement it are treated as playing at 1.0.
+    fn get_rate(&self, player: &PlayerInfo) -> f64 {
+        self.source.rate(player).unwrap_or(1.0)
+    }
+
+    /// Reads the `Volume` property, or `None` for players without volume control.
+    fn get_volume(&self, player: &PlayerInfo) -> Option<f64> {
+        self.source.volume(player).ok()
+    }
+
+    /// Mutes the player, remembering its volume, or restores the remembered volume if it is
+    /// already muted.
+    fn toggle_mute(&mut self, player_name: &str) -> Result<(), String> {
+        let player = self.players.iter().find(|player| player.name == player_name)
+            .ok_or_else(|| format!("no such player: {}", player_name))?;
+        let volume = self.get_volume(player).ok_or("player has no volume control")?;
+        let new_volume = if volume > 0.0 {
+            self.pre_mute_volume.insert(player.name.clone(), volume);
+            0.0
+        } else {
+            self.pre_mute_volume.remove(&player.name).unwrap_or(1.0)
+        };
+        self.source.set_volume(player, new_volume).map_err(|e| e.to_string())
+    }
+
+    fn change_volume(&mut self, player_name: &str, delta: f64) -> Result<(), String> {
+        let player = self.players.iter().find(|player| player.name == player_name)
+            .ok_or_else(|| format!("no such player: {}", player_name))?;
+        let volume = self.get_volume(player).ok_or("player has no volume control")?;
+        self.source.set_volume(player, (volume + delta).clamp(0.0, 1.0)).map_err(|e| e.to_string())
+    }
+
+    /// Reads the metadata as display strings, with multiple artists joined by
+    /// `artist-separator` (the album artist standing in if there are none), the player's
+    /// `remap` corrections and, with `title-fallback = "url"`, a title made up from the URL
+    /// for tracks without one.
     fn get_metadata(&self, player: &PlayerInfo) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-        let metadata: HashMap<String, String> = player.player.method_call("org.freedesktop.DBus.Properties", "Get",
-            ("org.mpris.MediaPlayer2.Player", "Metadata"))?.read1()?;
+        let separator = self.config.artist_separator.as_deref().unwrap_or(ARTIST_SEPARATOR);
+        let raw = self.source.metadata(player)?;
+        let decode_html = self.config.decode_html_entities.unwrap_or(true);
+        // Blank values count as missing, as some players send `""` or `[]` rather than
+        // leaving a field out.
+        let mut metadata: HashMap<String, String> = raw
+            .iter()
+            .map(|(key, value)| (key.clone(), sanitize::clean_metadata(&value.to_text(separator), decode_html)))
+            .filter(|(_, value)| !value.trim().is_empty())
+            .collect();
+        let items = |key| raw.get(key).map_or_else(Vec::new, MetadataValue::items);
+        let artists = Some(items("xesam:artist")).filter(|artists| !artists.is_empty()).unwrap_or_else(|| items("xesam:albumArtist"));
+        if !artists.is_empty() {
+            let artist = normalize::join_artists(&artists, separator, self.config.max_artists);
+            metadata.insert("xesam:artist".to_string(), sanitize::clean_metadata(&artist, decode_html));
+        }
+        // `{genre}` is the main genre rather than all of them.
+        if let Some(genre) = items("xesam:genre").first() {
+            metadata.insert("xesam:genre".to_string(), sanitize::clean_metadata(genre, decode_html));
+        }
+        if metadata.get("mpris:trackid").is_some_and(|id| id == NO_TRACK) {
+            metadata.remove("mpris:trackid");
+        }
+        let app = player.app.to_lowercase();
+        let stream = metadata.get("xesam:url").is_some_and(|url| url.starts_with("http://") || url.starts_with("https://"))
+            && metadata.get("mpris:length").is_none_or(|length| length == "0");
+        if let (true, Some(stations), Some(title)) = (stream, &self.stations, metadata.get("xesam:title")) {
+            // A player that reports an artist of its own only gets it replaced by a `|` title.
+            let keep_artist = metadata.contains_key("xesam:artist") && !title.contains('|');
+            match normalize::clean_stream_title(title, stations) {
+                Some((Some(artist), title)) if !keep_artist => {
+                    metadata.insert("xesam:artist".to_string(), artist);
+                    metadata.insert("xesam:title".to_string(), title);
+                }
+                Some((_, cleaned)) if title.contains('|') => {
+                    metadata.insert("xesam:title".to_string(), cleaned);
+                }
+                _ => {}
+            }
+        }
+        if let Some(remap) = for_player(&self.config.remap, &app) {
+            normalize::remap(&mut metadata, remap);
+        }
+        if self.config.title_fallback == TitleFallback::Url && metadata.get("xesam:title").is_none_or(String::is_empty) {
+            if let Some(title) = metadata.get("xesam:url").and_then(|url| normalize::title_from_url(url)) {
+                metadata.insert("xesam:title".to_string(), sanitize::clean_metadata(&title, false));
+            }
+        }
         Ok(metadata)
     }
 
-    fn update_prefix_suffix(&mut self, player_name: &str, status: &str) {
-        let player_option = if player_name.is_empty() { "".to_string() } else { format!("-p {}", player_name) };
-        let prev_button = format!("%%{{A:playerctl {} previous :}}{}%%{{A}}", player_option, CONTROL_CHARS[0]);
-        let play_button = format!("%%{{A:playerctl {} play :}}{}%%{{A}}", player_option, CONTROL_CHARS[1]);
-        let pause_button = format!("%%{{A:playerctl {} pause :}}{}%%{{A}}", player_option, CONTROL_CHARS[2]);
-        let next_button = format!("%%{{A:playerctl {} next :}}{}%%{{A}}", player_option, CONTROL_CHARS[3]);
+    /// The shell command a control button runs: this program's `--control`, which calls the
+    /// MPRIS method itself and ignores the second click of a double-click polybar passes on.
+    /// With `use-dbus-direct` it is `polybar-now-playing-cmd`, which makes the call without
+    /// loading the config or debouncing.
+    fn control_command(&self, player: Option<&PlayerInfo>, action: Action) -> String {
+        let exe = std::env::current_exe().unwrap_or_default();
+        // playerctld implements the Player interface too, forwarding to its active player.
+        let destination = player.map_or(PLAYERCTLD_BUS_NAME, |player| player.name.as_str());
+        if self.config.use_dbus_direct {
+            let cmd = exe.with_file_name("polybar-now-playing-cmd");
+            return format!("{} --player {} {}", cmd.display(), destination, action.name());
+        }
+        format!("{} --control {} --player {}", exe.display(), action.name(), destination)
+    }
+
+    /// The `Icon=` name from the `.desktop` file of the player's application.
+    fn desktop_icon(&mut self, player: &PlayerInfo) -> Option<String> {
+        let source = &self.source;
+        let icon = self.desktop_icons.entry(player.app.clone()).or_insert_with(|| {
+            let entry = source.desktop_entry(player).map_err(|e| verbose!("{} has no desktop entry: {}", player.app, e)).ok()?;
+            desktop::icon_name(&entry)
+        });
+        icon.clone()
+    }
+
+    /// `muted` is `Some` when the mute button should be shown.
+    fn update_prefix_suffix(&mut self, player: Option<&PlayerInfo>, status: PlaybackStatus, muted: Option<bool>) {
+        let player_name = player.map_or("", |player| player.name.as_str());
+        let command = |action: Action| self.control_command(player, action);
+        let prev_button = polybar_action(1, &command(Action::Previous), CONTROL_CHARS[0]);
+        let play_button = polybar_action(1, &command(Action::Play), CONTROL_CHARS[1]);
+        let pause_button = polybar_action(1, &command(Action::Pause), CONTROL_CHARS[2]);
+        let next_button = polybar_action(1, &command(Action::Next), CONTROL_CHARS[3]);
 
-        let mut suffix = format!("| {}", prev_button);
-        if status == "Playing" {
-            suffix += &format!(" {}", pause_button);
+        let toggle_button = if status == PlaybackStatus::Playing {
             self.status_paused = false;
+            pause_button
         } else {
-            suffix += &format!(" {}", play_button);
             self.status_paused = true;
+            play_button
+        };
+        let mut buttons = vec![prev_button, toggle_button, next_button];
+        if let Some(muted) = muted {
+            let exe = std::env::current_exe().map(|exe| exe.display().to_string()).unwrap_or_default();
+            buttons.push(polybar_action(1, &format!("{} ctl toggle-mute {}", exe, player_name), MUTE_CHARS[muted as usize]));
+        }
+        if player.is_some_and(|player| !player.can_control) {
+            buttons.clear();
+        }
+        self.display_suffix = with_font(&buttons.join(" "), self.config.control_font_index);
+        if let Some(count) = self.lastfm.as_mut().and_then(LastFm::play_count) {
+            self.display_suffix += &format!(" ♫{}", count);
         }
-        suffix += &format!(" {}", next_button);
-        self.display_suffix = suffix;
 
-        for (key, value) in &DISPLAY_PLAYER_PREFIX {
-            if player_name.to_lowercase().contains(key) {
-                self.display_prefix = value.to_string();
-                break;
+        let prefix = match player {
+            Some(player) if self.config.prefix_source == PrefixSource::DesktopEntry => {
+                self.desktop_icon(player).unwrap_or_else(|| player_icon(player).to_string())
             }
+            Some(player) => player_icon(player).to_string(),
+            None => DISPLAY_PLAYER_PREFIX.last().unwrap().1.to_string(),
+        };
+        let unit = self.config.length_unit;
+        let prefix = match self.config.prefix_width {
+            Some(width) if text::length(&prefix, unit) < width => format!("{}{}", prefix, " ".repeat(width - text::length(&prefix, unit))),
+            _ => prefix,
+        };
+        self.display_prefix = with_font(&prefix, self.config.prefix_font_index);
+        self.seek_commands = player.filter(|_| self.config.seek_on_scroll).map(|player| {
+            let exe = std::env::current_exe().unwrap_or_default().with_file_name("polybar-now-playing-cmd");
+            let step = self.config.seek_scroll_step_seconds.unwrap_or(SEEK_STEP_SECONDS) as i64 * 1_000_000;
+            let seek = |delta: i64| format!("{} --player {} seek {}", exe.display(), player.name, delta);
+            (seek(step), seek(-step))
+        });
+        if let Some(player) = player.filter(|_| self.config.scroll_volume_on_icon) {
+            let exe = std::env::current_exe().map(|exe| exe.display().to_string()).unwrap_or_default();
+            let step = self.config.volume_step.unwrap_or(VOLUME_STEP);
+            let volume = |delta: f64| format!("{} ctl volume {} {}", exe, player.name, delta);
+            self.display_prefix = polybar_action(4, &volume(step), &polybar_action(5, &volume(-step), &self.display_prefix));
         }
-        if self.display_prefix.is_empty() {
-            self.display_prefix = DISPLAY_PLAYER_PREFIX.last().unwrap().1.to_string();
+    }
+
+    fn on_track_change(&mut self, player: &str, metadata: &HashMap<String, String>, status: PlaybackStatus) {
+        self.scroller.restart();
+        self.peek_until = None;
+        let field = |key| metadata.get(key).map_or("", String::as_str);
+        if let Some(notifier) = &self.notifier {
+            let art_url = metadata.get("mpris:artUrl").cloned();
+            notifier.track_changed(field("xesam:title").to_string(), field("xesam:artist").to_string(), art_url);
+        }
+        let event = TrackEvent {
+            player,
+            title: field("xesam:title"),
+            artist: field("xesam:artist"),
+            album: field("xesam:album"),
+            status: status.as_str(),
+        };
+        if let Some(path) = &self.config.event_fifo {
+            if let Err(e) = events::write_event(path, &event) {
+                verbose!("skipped track event for {}: {}", path.display(), e);
+            }
+        }
+        if let Some(mqtt) = &self.mqtt {
+            match serde_json::to_string(&event) {
+                Ok(payload) => mqtt.publish(payload),
+                Err(e) => verbose!("could not serialize track event: {}", e),
+            }
         }
     }
 
-    fn update_message(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.players.is_empty() {
-            self.display_prefix = String::new();
-            self.display_suffix = String::new();
-            self.display_suffix = "No player available".to_string();
-            self.update_prefix_suffix("", "");
+    /// Spells `text` in ASCII with `transliterate`, or returns it as is.
+    fn transliterate(&self, text: String) -> String {
+        if self.config.transliterate { translit::to_ascii(&text, self.config.transliterate_unmapped) } else { text }
+    }
+
+    /// Applies the per-field width budgets when the joined fields wouldn't fit the display:
+    /// first each field's own `<field>-max`, then the proportional `field-ratios` split.
+    fn fit_fields(&self, values: &mut [String], separator_width: usize) {
+        let unit = self.config.length_unit;
+        let separators = separator_width * values.len().saturating_sub(1);
+        let fits = |values: &[String]| values.iter().map(|value| text::length(value, unit)).sum::<usize>() + separators <= self.display_len;
+        if fits(values) {
+            return;
+        }
+        let names = METADATA_FIELDS.iter().map(|(_, name, _)| *name);
+        for (value, name) in values.iter_mut().zip(names.clone()) {
+            if let Some(max) = self.config.field_max(name) {
+                *value = text::truncate_with_ellipsis(value, max, unit);
+            }
+        }
+        if fits(values) || self.config.field_ratios.is_empty() {
+            return;
+        }
+        let widths: Vec<usize> = values.iter().map(|value| text::length(value, unit)).collect();
+        let ratios: Vec<u32> = names.map(|name| self.config.field_ratios.get(name).copied().unwrap_or(1)).collect();
+        let budget = self.display_len.saturating_sub(separators);
+        for (value, max) in values.iter_mut().zip(text::allocate_widths(&widths, &ratios, budget)) {
+            *value = text::truncate_with_ellipsis(value, max, unit);
+        }
+    }
+
+    /// Reads the current player and rebuilds the prefix, controls and the scroller's source
+    /// text from it.
+    fn update_state(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some((player, _)) = self.vanished.clone() {
+            // Its last text stays as it was, with the controls of a paused player.
+            self.update_prefix_suffix(Some(&player), PlaybackStatus::Paused, None);
+        } else if self.players.is_empty() {
+            self.scroller.set_text(self.idle_text());
+            self.detail.clear();
+            self.progress = None;
+            self.display_mode = None;
+            self.fixed_text.clear();
+            self.update_prefix_suffix(None, PlaybackStatus::Stopped, None);
         } else {
-            let player_info = &self.players[self.current_player];
-            let player_name = &player_info.name;
-            let status = self.get_status(player_info)?;
-            let metadata = self.get_metadata(player_info)?;
-
-            let mut metadata_string_list = Vec::new();
-            for field in &METADATA_FIELDS {
-                if let Some(result) = metadata.get(*field) {
-                    metadata_string_list.push(result.to_string());
-                } else {
-                    metadata_string_list.push(format!("No {}", field.split(":").last().unwrap()));
+            if self.config.selection == Selection::Combined && self.show_combined()? {
+                return Ok(());
+            }
+            let player = self.players[self.current_player].clone();
+            let status = match self.source.status(&player)? {
+                PlaybackStatus::Playing if self.stale.as_ref().is_some_and(|stale| stale.is_stale(&player.name)) => PlaybackStatus::Paused,
+                status => status,
+            };
+            let mut metadata = self.get_metadata(&player)?;
+            self.showing_ad = spotify::is_ad(&metadata);
+            if self.showing_ad {
+                self.show_ad(&player, status);
+                return Ok(());
+            }
+            let field = |key| metadata.get(key).map_or(String::new(), String::clone);
+            let (artist, title) = (field("xesam:artist"), field("xesam:title"));
+            let missing = ["xesam:album", "xesam:contentCreated"].iter().any(|key| !metadata.contains_key(*key));
+            self.metadata_supplement = match &mut self.musicbrainz {
+                Some(musicbrainz) if missing => {
+                    musicbrainz.set_track(&artist, &title);
+                    musicbrainz.supplement()
+                }
+                _ => HashMap::new(),
+            };
+            let spotify_id = metadata.get("mpris:trackid").and_then(|id| spotify::track_id(id));
+            if let (Some(spotify), Some(id)) = (&mut self.spotify, spotify_id) {
+                spotify.set_track(id);
+                self.metadata_supplement.extend(spotify.supplement());
+            }
+            for (key, value) in &self.metadata_supplement {
+                metadata.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+            let track = Track { player: player.name.clone(), id: metadata.get("mpris:trackid").cloned(), title: title.clone(), artist: artist.clone() };
+            // Players can briefly report nothing between tracks; that isn't a track of its own.
+            let no_track = track.id.is_none() && title.is_empty() && artist.is_empty();
+            let track_changed = !no_track && !self.last_track.as_ref().is_some_and(|last| last.is_same(&track));
+            if track_changed {
+                self.last_track = Some(track);
+                self.on_track_change(&player.name, &metadata, status);
+            }
+            self.send_osc(&player, &title, &artist, status, track_changed);
+            if let Some(lastfm) = &mut self.lastfm {
+                lastfm.set_track(&artist, &title);
+            }
+            let muted = if self.config.show_mute { self.get_volume(&player).map(|volume| volume <= 0.0) } else { None };
+            let rate = if self.config.show_rate { format_rate(self.get_rate(&player)) } else { String::new() };
+            // Kept out of the scroller's text, which would otherwise change, and so restart
+            // scrolling, every second.
+            self.detail = match self.config.detail_on_click && ctl::detail_shown() {
+                true => self.position_detail(&player, &metadata).map_or(String::new(), |detail| format!(" [{}]", detail)),
+                false => String::new(),
+            };
+            let position = match self.config.progress_in_text || self.config.smart_mode {
+                true => self.source.position(&player).ok(),
+                false => None,
+            };
+            self.progress = match self.config.progress_in_text {
+                true => self.text_progress(position, &metadata),
+                false => None,
+            };
+            let podcast = self.is_podcast(&player, &metadata);
+            let comment = metadata.get("xesam:comment").filter(|_| self.config.show_comment).map(|comment| {
+                let comment = self.transliterate(comment.clone());
+                text::truncate_with_ellipsis(&comment, self.config.comment_max_len.unwrap_or(COMMENT_MAX_LEN), self.config.length_unit)
+            });
+            let url_label = match self.config.show_url && self.config.url_in_suffix {
+                true => metadata.get("xesam:url").and_then(|url| normalize::url_label(url)).map(|label| self.transliterate(label)),
+                false => None,
+            };
+            let placeholders = self.placeholders(&player, &metadata, status);
+            let fetch = Fetch { player: player.name.clone(), metadata, width: self.display_len, rate, podcast, placeholders };
+            // Status changes only reach the controls, which are rebuilt below either way.
+            let unchanged = self.last_fetch.as_ref().is_some_and(|(last, text)| *last == fetch && text == self.display_text());
+            if !unchanged {
+                let text = self.build_text(&fetch);
+                self.last_fetch = Some((fetch, text));
+            }
+            if let Some((fetch, text)) = self.last_fetch.as_ref().filter(|_| self.config.smart_mode) {
+                let display = DisplayConfig {
+                    position,
+                    text_length: text::length(text, self.config.length_unit),
+                    display_len: self.display_len,
+                    scroll_mode: self.config.scroll_mode,
+                };
+                let mode = choose_display_mode(&fetch.metadata, &display);
+                self.progress = if mode == DisplayMode::Progress { self.text_progress(position, &fetch.metadata) } else { None };
+                self.display_mode = Some(mode);
+            }
+            self.update_prefix_suffix(Some(&player), status, muted);
+            for extra in [url_label, comment].into_iter().flatten().filter(|extra| !extra.is_empty()) {
+                self.display_suffix = [self.display_suffix.as_str(), extra.as_str()].join(" ").trim_start().to_string();
+            }
+        }
+        Ok(())
+    }
+
+    /// The placeholders that come from the player rather than the metadata. The position and
+    /// rate are only asked for when a template shows them.
+    fn placeholders(&self, player: &PlayerInfo, metadata: &HashMap<String, String>, status: PlaybackStatus) -> Vec<(&'static str, String)> {
+        let app = player.app.strip_prefix(MPRIS_PREFIX).unwrap_or(&player.app);
+        let mut placeholders = vec![("player", app.split('.').next().unwrap_or(app).to_string()), ("status", status.as_str().to_string())];
+        let length = metadata.get("mpris:length").and_then(|length| length.parse().ok()).filter(|&length| length > 0);
+        if let Some(length) = length {
+            placeholders.push(("length", format_time(length, length, self.config.time_format)));
+        }
+        let templates = [&self.format, &self.podcast_format, &self.fixed_format, &self.format_static];
+        let shown = |name| templates.into_iter().flatten().any(|template| template.placeholders().contains(&name));
+        if shown("position") || shown("progress") {
+            // Left out, and so empty, for players that don't support Position.
+            if let Ok(position) = self.source.position(player) {
+                placeholders.push(("position", format_time(position, length.unwrap_or(0), self.config.time_format)));
+                if let Some(length) = length {
+                    let (position, length) = (u64::try_from(position).unwrap_or(0), u64::try_from(length).unwrap_or(0));
+                    let bar = text::render_text_progress(position, length, PROGRESS_PLACEHOLDER_LEN, PROGRESS_CHARS.0, PROGRESS_CHARS.1);
+                    placeholders.push(("progress", bar));
                 }
             }
-            let metadata_string = metadata_string_list.join(&format!(" {} ", METADATA_SEPARATOR));
-            let metadata_display_len = self.visual_length(&metadata_string);
-            if metadata_display_len > MESSAGE_DISPLAY_LEN {
-                self.update_prefix_suffix(player_name, &status);
-                let metadata_string = format!(" {} ", METADATA_SEPARATOR) + &metadata_string + " |";
-                self.display_suffix = self.make_visual_length(&metadata_string, MESSAGE_DISPLAY_LEN);
+        }
+        if shown("rate") {
+            placeholders.push(("rate", format_rate(self.get_rate(player))));
+        }
+        placeholders
+    }
+
+    /// Rebuilds the scroller's text, `fixed-format` and RTL direction from a fetch, returning
+    /// the text. Restores the saved scroll offset if this is the track the last instance left on.
+    fn build_text(&mut self, fetch: &Fetch) -> String {
+        let metadata = &fetch.metadata;
+        let mut metadata_string_list = Vec::new();
+        for (key, name, missing) in &METADATA_FIELDS {
+            if let Some(result) = metadata.get(*key) {
+                let result = normalize::apply_cleanup(result, &self.cleanup);
+                let result = match *name {
+                    "artist" => normalize::normalize_artist(&result, &self.artist_norm),
+                    "title" => normalize::normalize_title(&result, &self.title_norm),
+                    _ => result,
+                };
+                let result = match self.config.transforms.get(*name) {
+                    Some(transform) => normalize::transform(&result, transform),
+                    None => result,
+                };
+                metadata_string_list.push(self.transliterate(result));
+            } else if self.format.is_some() || fetch.podcast {
+                // Left empty so the format's conditional groups can tell it is missing.
+                metadata_string_list.push(String::new());
             } else {
-                self.display_suffix = String::new();
-                self.update_prefix_suffix(player_name, &status);
+                metadata_string_list.push(self.config.missing_text(name, missing).to_string());
+            }
+        }
+        let separator = self.config.metadata_separator.clone().unwrap_or_else(|| METADATA_SEPARATOR.to_string());
+        self.fit_fields(&mut metadata_string_list, text::length(&separator, self.config.length_unit));
+        let other_fields: HashMap<&str, String> = metadata
+            .iter()
+            .map(|(key, value)| match key.as_str() {
+                "xesam:url" => (key.as_str(), self.transliterate(normalize::display_url(value, &self.config.library_roots))),
+                _ => (key.as_str(), self.transliterate(value.clone())),
+            })
+            .collect();
+        let lookup = |name: &str| match METADATA_FIELDS.iter().position(|(_, field, _)| *field == name) {
+            Some(index) => Some(metadata_string_list[index].clone()),
+            None if name == "year" => other_fields.get("xesam:contentCreated").and_then(|date| normalize::year_from_date(date)),
+            None => match fetch.placeholders.iter().find(|(placeholder, _)| *placeholder == name) {
+                Some((_, value)) => Some(value.clone()),
+                None => other_fields.get(format!("xesam:{}", name).as_str()).cloned(),
+            },
+        };
+        self.fixed_text = self.fixed_format.as_ref().and_then(|fixed| fixed.render(lookup)).unwrap_or_default();
+        let format = if fetch.podcast { &self.podcast_format } else { &self.format };
+        let mut metadata_string = match format {
+            Some(format) => format.render(lookup).unwrap_or_else(|| self.idle_text().to_string()),
+            // Fields whose missing text is set to "" are left out along with their separator.
+            None => metadata_string_list.iter().filter(|value| !value.is_empty()).cloned().collect::<Vec<_>>().join(&separator),
+        };
+        let url = match self.config.show_url && !self.config.url_in_suffix {
+            true => metadata.get("xesam:url").and_then(|url| normalize::url_label(url)).map(|label| format!("({})", self.transliterate(label))),
+            false => None,
+        };
+        // Shown after the text, and after a `format-static` one too.
+        let tail = [Some(fetch.rate.clone()), url].into_iter().flatten().filter(|part| !part.is_empty()).collect::<Vec<_>>().join(" ");
+        if !tail.is_empty() {
+            metadata_string = format!("{} {}", metadata_string, tail);
+        }
+        let fits = |text: &str| text::length(text, self.config.length_unit) <= self.display_len;
+        if self.config.static_when_short && fits(&metadata_string) {
+            let short = self.format_static.as_ref().and_then(|format| format.render(lookup));
+            if let Some(short) = short.map(|short| if tail.is_empty() { short } else { format!("{} {}", short, tail) }).filter(|short| fits(short)) {
+                metadata_string = short;
+            }
+        }
+        // Text that scrolls gets a trailing separator so the end doesn't run into the start.
+        if text::length(&metadata_string, self.config.length_unit) > self.display_len {
+            metadata_string += &separator;
+        }
+        self.scroller.set_text(&metadata_string);
+        self.scroller.set_reverse(match self.config.rtl {
+            Rtl::Auto => text::is_predominantly_rtl(&metadata_string),
+            Rtl::Force => true,
+            Rtl::Off => false,
+        });
+        self.track_id = metadata.get("mpris:trackid").cloned();
+        if let Some(saved) = self.pending_restore.take() {
+            if saved.player == fetch.player && self.track_id.as_ref() == Some(&saved.track_id) {
+                self.scroller.set_offset(saved.offset);
             }
         }
+        metadata_string
+    }
+
+    /// Sends the track to the `osc-target` when it changes, and the position in between when
+    /// `osc-position-interval-ms` is due.
+    fn send_osc(&mut self, player: &PlayerInfo, title: &str, artist: &str, status: PlaybackStatus, track_changed: bool) {
+        let Some(osc) = &mut self.osc else { return };
+        if !track_changed && !osc.position_due() {
+            return;
+        }
+        let position = self.source.position(player).ok().map(|position| position as f32 / 1e6);
+        match (track_changed, position) {
+            (true, _) => osc.send_track(title, artist, status.as_str(), position),
+            (false, Some(position)) => osc.send_position(position),
+            (false, None) => {}
+        }
+    }
+
+    /// The `progress-in-text` bar, or `None` if the player doesn't report its position or the
+    /// track's length.
+    fn text_progress(&self, position: Option<i64>, metadata: &HashMap<String, String>) -> Option<String> {
+        let position = u64::try_from(position?).unwrap_or(0);
+        let length = metadata.get("mpris:length")?.parse().ok().filter(|&length| length > 0)?;
+        Some(text::render_text_progress(position, length, self.display_len, PROGRESS_CHARS.0, PROGRESS_CHARS.1))
+    }
+
+    /// `position/length` as e.g. `1:23/4:56`, or just the position for streams without a
+    /// length. `None` if the player doesn't report its position.
+    fn position_detail(&self, player: &PlayerInfo, metadata: &HashMap<String, String>) -> Option<String> {
+        let position = self.source.position(player).ok()?;
+        let format = self.config.time_format;
+        match metadata.get("mpris:length").and_then(|length| length.parse().ok()) {
+            // What's left matters more than what's done an hour into an episode.
+            Some(length) if self.is_podcast(player, metadata) => {
+                Some(format!("-{}/{}", format_time(length - position, length, format), format_time(length, length, format)))
+            }
+            Some(length) => Some(format!("{}/{}", format_time(position, length, format), format_time(length, length, format))),
+            None => Some(format_time(position, position, format)),
+        }
+    }
+
+    /// Whether `podcast.format` is set and the track looks like an episode: its genre names
+    /// one of `podcast.genres`, it plays in one of `podcast.players`, or it is at least
+    /// `podcast.min-length-minutes` long.
+    fn is_podcast(&self, player: &PlayerInfo, metadata: &HashMap<String, String>) -> bool {
+        if self.podcast_format.is_none() {
+            return false;
+        }
+        let rules = &self.config.podcast;
+        let genre = metadata.get("xesam:genre").map_or_else(String::new, |genre| genre.to_lowercase());
+        let in_genre = |word: &str| genre.contains(&word.to_lowercase());
+        let by_genre = match &rules.genres {
+            Some(words) => words.iter().any(|word| in_genre(word)),
+            None => PODCAST_GENRES.iter().any(|word| in_genre(word)),
+        };
+        let app = player.app.to_lowercase();
+        let length = metadata.get("mpris:length").and_then(|length| length.parse::<u64>().ok());
+        by_genre
+            || rules.players.iter().any(|name| app.contains(&name.to_lowercase()))
+            || rules.min_length_minutes.zip(length).is_some_and(|(minutes, length)| length >= minutes * 60_000_000)
+    }
+
+    /// For `selection = "combined"`: if more than one player is playing, shows each one's
+    /// icon and title joined by `combined-separator` and returns `true`. Otherwise leaves the
+    /// display to the single-player path.
+    fn show_combined(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut playing = Vec::new();
+        for player in &self.players {
+            // A player that left the bus since the list was read is just left out.
+            if !self.source.status(player).is_ok_and(|status| status == PlaybackStatus::Playing) {
+                continue;
+            }
+            let Ok(metadata) = self.get_metadata(player) else { continue };
+            let title = metadata.get("xesam:title").filter(|title| !title.is_empty());
+            let title = title.map_or_else(
+                || self.config.missing_text("title", METADATA_FIELDS[0].2).to_string(),
+                |title| normalize::normalize_title(&normalize::apply_cleanup(title, &self.cleanup), &self.title_norm),
+            );
+            let title = match self.config.transforms.get("title") {
+                Some(transform) => normalize::transform(&title, transform),
+                None => title,
+            };
+            playing.push((player.clone(), format!("{} {}", player_icon(player), self.transliterate(title))));
+        }
+        if playing.len() < 2 {
+            return Ok(false);
+        }
+        let separator = self.config.combined_separator.as_deref().unwrap_or(COMBINED_SEPARATOR);
+        let mut combined = playing.iter().map(|(_, text)| text.as_str()).collect::<Vec<_>>().join(separator);
+        if text::length(&combined, self.config.length_unit) > self.display_len {
+            combined += separator;
+        }
+        self.last_track = None;
+        self.track_id = None;
+        self.detail.clear();
+        self.progress = None;
+        self.display_mode = None;
+        self.fixed_text.clear();
+        self.scroller.set_text(&combined);
+        self.scroller.set_reverse(false);
+        self.update_prefix_suffix(Some(&playing[0].0), PlaybackStatus::Playing, None);
+        self.display_prefix.clear();
+        if self.config.combined_hide_controls {
+            self.display_suffix.clear();
+        }
+        Ok(true)
+    }
+
+    /// Replaces the display with `ad-text`. The track is forgotten, so whatever plays after
+    /// the ad counts as a track change and starts scrolling from the beginning.
+    fn show_ad(&mut self, player: &PlayerInfo, status: PlaybackStatus) {
+        self.last_track = None;
+        self.track_id = None;
+        self.detail.clear();
+        self.progress = None;
+        self.display_mode = None;
+        self.fixed_text.clear();
+        self.scroller.set_text(self.config.ad_text.as_deref().unwrap_or(AD_TEXT));
+        self.scroller.set_reverse(false);
+        self.update_prefix_suffix(Some(player), status, None);
+        if self.config.ad_hide_controls {
+            self.display_suffix.clear();
+        }
+    }
+
+    /// Renders the line for this tick, then advances the marquee for the next one.
+    fn render(&mut self) -> String {
+        let align = self.config.align.unwrap_or(match self.display_mode {
+            Some(DisplayMode::Static) => Align::Center,
+            _ if self.scroller.is_reversed() => Align::Right,
+            _ => Align::Left,
+        });
+        self.scroller.render(self.display_len, align);
+        let output = self.compose_output();
+        self.scroll();
+        output
+    }
 
-        if HIDE_OUTPUT && self.players.is_empty() {
-            println!("");
+    fn update_message(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let output = if self.config.summary {
+            self.summary()
+        } else if self.config.raw_status {
+            self.raw_status().to_string()
         } else {
-            self.scroll();
-            let display_text = format!("{} %{{T{}}}{}%{{T-}}{}", self.display_prefix, FONT_INDEX, self.display_text(), self.display_suffix);
-            print!("{}", display_text);
-            std::io::stdout().flush()?;
+            self.update_state()?;
+            if HIDE_OUTPUT && self.players.is_empty() { String::new() } else { self.render() }
+        };
+        self.print_line(output)
+    }
+
+    /// Between `event-driven` updates: moves the marquee on without asking the players
+    /// anything.
+    fn scroll_tick(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.config.summary || self.config.raw_status || (HIDE_OUTPUT && self.players.is_empty()) {
+            return Ok(());
         }
+        let output = self.render();
+        self.print_line(output)
+    }
 
+    fn print_line(&mut self, output: String) -> Result<(), Box<dyn std::error::Error>> {
+        let output = self.pad_to_min_width(output);
+        if self.last_output.as_ref() == Some(&output) {
+            self.skipped_ticks += 1;
+            return Ok(());
+        }
+        println!("{}", output);
+        std::io::stdout().flush()?;
+        self.last_output = Some(output);
+        self.rendered_ticks += 1;
         Ok(())
     }
 
-    fn scroll(&mut self) {
-        if !self.status_paused {
-            if self.display_text().width() > MESSAGE_DISPLAY_LEN {
-                self.display_text = self.display_text()[1..].to_string() + &self.display_text()[0..1];
-            } else if self.display_text().width() < MESSAGE_DISPLAY_LEN {
-                self.display_text += &" ".repeat(MESSAGE_DISPLAY_LEN - self.display_text().width());
-            }
+    /// Pads a line to `min-display-width` cells. An empty line is left empty, so the module
+    /// still disappears.
+    fn pad_to_min_width(&self, output: String) -> String {
+        let visible = text::visual_width_of_polybar_string(&output);
+        match self.config.min_display_width {
+            Some(min) if !output.is_empty() && visible < min => output + &" ".repeat(min - visible),
+            _ => output,
         }
     }
 
-    fn visual_length(&self, text: &str) -> usize {
-        text.width()
+    /// The `raw-status` line: the selected player's `PlaybackStatus`, or `None` without one.
+    fn raw_status(&self) -> &'static str {
+        let status = self.players.get(self.current_player).map(|player| self.source.status(player));
+        status.and_then(Result::ok).map_or("None", PlaybackStatus::as_str)
     }
 
-    fn make_visual_length(&self, text: &str, visual_desired_length: usize) -> String {
-        let mut visual_length = 0;
-        let mut altered_text = String::new();
+    /// The `summary` line: a glyph for whether any player is playing, then how many players
+    /// there are. Empty without players, so the module disappears.
+    fn summary(&self) -> String {
+        if self.players.is_empty() {
+            return String::new();
+        }
+        let playing = self.players.iter().any(|player| self.source.status(player).is_ok_and(|status| status == PlaybackStatus::Playing));
+        format!("{}{}", if playing { SUMMARY_PLAYING } else { SUMMARY_PAUSED }, self.players.len())
+    }
 
-        for ch in text.chars() {
-            let width = if ch.width() == Some(2) { 2 } else { 1 };
-            if visual_length + width <= visual_desired_length {
-                visual_length += width;
-                altered_text.push(ch);
-            } else {
-                break;
+    /// Shown when there is nothing to display. A `field` output is one line of a stack, so
+    /// it stays blank rather than repeating the message on every line.
+    fn idle_text(&self) -> &'static str {
+        if self.config.field.is_some() { "" } else { NO_PLAYER_TEXT }
+    }
+
+    /// Joins prefix, text and controls. When there is no text to show the prefix and controls
+    /// are joined by the `textless-separator` alone, so no stray padding or `|` is left behind.
+    /// A `field` output is the text alone.
+    fn compose_output(&self) -> String {
+        let text = match &self.progress {
+            Some(progress) => progress,
+            None if self.peeking() => self.display_text(),
+            None => self.scroller.window(),
+        };
+        let text = format!("{}{}{}", self.fixed_text, text, self.detail);
+        if self.config.field.is_some() {
+            if self.display_text().is_empty() { String::new() } else { format!("%{{T{}}}{}%{{T-}}", FONT_INDEX, text) }
+        } else if self.display_text().trim().is_empty() {
+            [self.display_prefix.as_str(), self.display_suffix.as_str()]
+                .iter()
+                .filter(|part| !part.is_empty())
+                .copied()
+                .collect::<Vec<_>>()
+                .join(self.config.textless_separator.as_deref().unwrap_or(TEXTLESS_SEPARATOR))
+        } else {
+            let mut output = format!("%{{T{}}}{}%{{T-}}", FONT_INDEX, text);
+            if let Some((forward, back)) = &self.seek_commands {
+                output = polybar_action(4, forward, &polybar_action(5, back, &output));
+            }
+            if !self.display_prefix.is_empty() {
+                output = format!("{} {}", self.display_prefix, output);
+            }
+            if !self.display_suffix.is_empty() {
+                let separator = self.config.text_separator.as_deref().unwrap_or(TEXT_SEPARATOR);
+                output = format!("{}{}{}", output, separator, self.display_suffix);
             }
+            output
         }
+    }
+
+    /// Whether the text is moving, so ticks are needed even without news from the players.
+    fn is_scrolling(&self) -> bool {
+        (!self.status_paused || self.config.scroll_when_paused) && !self.showing_ad && text::length(self.display_text(), self.config.length_unit) > self.display_len
+    }
 
-        if visual_length == visual_desired_length + 1 {
-            altered_text.pop();
-            altered_text.push(' ');
-        } else if visual_length < visual_desired_length {
-            altered_text.push_str(&" ".repeat(visual_desired_length - visual_length));
+    /// Advances the marquee. Text that fits is left alone; `Scroller::render()` pads it to the
+    /// current width, so a width change takes effect on the next render.
+    fn scroll(&mut self) {
+        let app = self.players.get(self.current_player).map_or("", |player| player.app.as_str());
+        if self.scroll_speed_app.as_deref() != Some(app) {
+            let speed = for_player(&self.config.player_scroll_speed, &app.to_lowercase());
+            self.scroll_ticks_per_step = speed.map_or(1, |&ticks| ticks.max(1));
+            self.scroll_speed_app = Some(app.to_string());
         }
+        self.tick_counter = self.tick_counter.wrapping_add(1);
+        if !self.tick_counter.is_multiple_of(self.scroll_ticks_per_step) {
+            return;
+        }
+        if !self.peeking() && self.is_scrolling() {
+            self.scroller.advance(self.display_len);
+        }
+    }
 
-        altered_text
+    fn peeking(&self) -> bool {
+        self.peek_until.is_some_and(|until| Instant::now() < until)
     }
 
     fn display_text(&self) -> &str {
-        &self.display_text
+        self.scroller.text()
+    }
+
+    fn handle_commands(&mut self) {
+        while let Some(request) = self.control.as_ref().and_then(ControlServer::next_request) {
+            let reply = match request.command {
+                Ok(ref command) => self.handle_command(command),
+                Err(ref e) => Err(e.clone()),
+            };
+            request.reply(&reply.unwrap_or_else(|e| format!("error: {}", e)));
+        }
+    }
+
+    /// Carries out a `ctl` command, returning the reply.
+    fn handle_command(&mut self, command: &Command) -> Result<String, String> {
+        match *command {
+            Command::SetWidth { width, persist } => {
+                self.display_len = width;
+                if persist {
+                    ctl::persist_width(width).map_err(|e| format!("could not persist width: {}", e))?;
+                }
+            }
+            Command::ToggleMute { ref player } => self.toggle_mute(player)?,
+            Command::ChangeVolume { ref player, delta } => self.change_volume(player, delta)?,
+            Command::Rescroll => self.scroller.restart(),
+            Command::Peek { duration } => self.peek_until = Some(Instant::now() + duration),
+            Command::Status => return Ok(format!("rendered {} skipped {}", self.rendered_ticks, self.skipped_ticks)),
+        }
+        Ok("ok".to_string())
+    }
+
+    fn tick(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.update_players()?;
+        self.handle_commands();
+        if let Some(locked) = self.screen_lock.as_ref().map(ScreenLockMonitor::is_locked) {
+            self.apply_screen_lock(locked);
+        }
+        self.update_message()?;
+        // Updated along with the controls, which show a pause button only while playing.
+        if let Some(inhibitor) = &mut self.inhibitor {
+            inhibitor.set_playing(!self.status_paused);
+        }
+        Ok(())
+    }
+
+    /// Pauses the current player when the screen locks while it is playing. On unlock, with
+    /// `session-unlock-resume`, resumes it after `resume-delay-ms` if it was paused that way.
+    fn apply_screen_lock(&mut self, locked: bool) {
+        let Some(player) = self.players.get(self.current_player) else { return };
+        if locked {
+            self.resume_at = None;
+            if !self.auto_paused && self.source.status(player).is_ok_and(|status| status == PlaybackStatus::Playing) {
+                match self.source.call(player, "Pause") {
+                    Ok(()) => self.auto_paused = true,
+                    Err(e) => verbose!("could not pause {}: {}", player.name, e),
+                }
+            }
+            return;
+        }
+        if !self.auto_paused {
+            return;
+        }
+        if !self.config.session_unlock_resume {
+            self.auto_paused = false;
+            return;
+        }
+        let delay = Duration::from_millis(self.config.resume_delay_ms.unwrap_or(RESUME_DELAY));
+        let resume_at = *self.resume_at.get_or_insert_with(|| Instant::now() + delay);
+        if Instant::now() < resume_at {
+            return;
+        }
+        self.resume_at = None;
+        self.auto_paused = false;
+        if let Err(e) = self.source.call(player, "Play") {
+            verbose!("could not resume {}: {}", player.name, e);
+        }
+    }
+
+    /// Records the player, track and scroll offset for the next instance's `state-restore`.
+    fn save_state(&self) {
+        let (Some(player), Some(track_id)) = (self.players.get(self.current_player), &self.track_id) else {
+            return;
+        };
+        let path = state::path();
+        let saved = SavedState { player: player.name.clone(), track_id: track_id.clone(), offset: self.scroller.offset() };
+        if let Err(e) = state::save(&path, &saved) {
+            verbose!("could not save state to {}: {}", path.display(), e);
+        }
     }
 
     fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.config.state_restore {
+            state::watch_sigterm();
+        }
+        if self.config.event_driven {
+            changes::spawn();
+        }
+        let control_fd = self.control.as_ref().map(ControlServer::as_raw_fd);
+        // Polls every UPDATE_DELAY again if following the signals fails.
+        let event_driven = self.config.event_driven;
+        let event_driven = move || event_driven && changes::watching();
+        let mut last_fetch = Instant::now();
+        // Draw the first frame straight away rather than leaving the module blank for a delay.
+        self.tick()?;
+        let mut blanked = false;
         loop {
-            sleep(Duration::from_millis(UPDATE_DELAY));
-            self.update_players()?;
-            self.update_message()?;
+            if state::terminated() {
+                self.save_state();
+                return Ok(());
+            }
+            if self.idle.as_ref().is_some_and(IdleMonitor::is_idle) {
+                if !blanked {
+                    println!();
+                    std::io::stdout().flush()?;
+                    self.last_output = Some(String::new());
+                    blanked = true;
+                }
+                std::thread::park_timeout(Duration::from_millis(IDLE_DELAY));
+                continue;
+            }
+            // Coming back from idle, redraw right away instead of after the usual delay.
+            let mut woken = true;
+            if !std::mem::take(&mut blanked) {
+                let on_battery = self.battery.as_mut().is_some_and(BatteryMonitor::is_on_battery);
+                let delay = if on_battery { UPDATE_DELAY * BATTERY_DELAY_FACTOR } else { UPDATE_DELAY };
+                // Timers for peeking, sticky players and resuming still need ticks to expire.
+                let waiting = self.peeking() || self.vanished.is_some() || self.resume_at.is_some();
+                let delay = if !event_driven() || self.is_scrolling() || waiting { delay } else { EVENT_FALLBACK_DELAY };
+                woken = refresh::sleep(Duration::from_millis(delay), control_fd);
+            }
+            if event_driven() && !woken && last_fetch.elapsed() < Duration::from_millis(EVENT_FALLBACK_DELAY) {
+                self.scroll_tick()?;
+                continue;
+            }
+            last_fetch = Instant::now();
+            self.tick()?;
         }
     }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut polybar_now_playing = PolybarNowPlaying::new()?;
+    let args = Args::parse()?;
+    VERBOSE.store(args.verbose, Ordering::Relaxed);
+    if args.toggle_detail {
+        return Ok(ctl::toggle_detail()?);
+    }
+    if args.version {
+        print_version();
+        return Ok(());
+    }
+    if let Some(command) = args.ctl {
+        let reply = ctl::send(&command)?;
+        println!("{}", reply);
+        if reply.starts_with("error:") {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let config = Config::load(args.config.as_deref(), &args.overrides)?;
+    if let Some(action) = &args.control {
+        let source = SessionMediaSource::new(config.use_playerctld)?;
+        return run_control(&source, action.parse()?, args.player.as_deref(), &config);
+    }
+    // Before the source and outputs start their threads, which must inherit the blocked mask.
+    if !args.one_shot && !args.test_output {
+        refresh::watch_sigusr1()?;
+    }
+    let source: Box<dyn MediaSource> = if args.test_output {
+        Box::new(MockMediaSource::test_track())
+    } else if let Some(path) = &args.dbus_mock_file {
+        Box::new(MockMediaSource::load(path)?)
+    } else {
+        Box::new(SessionMediaSource::new(config.use_playerctld)?)
+    };
+    let mut polybar_now_playing = PolybarNowPlaying::new(config, source)?;
+    if args.test_output {
+        return polybar_now_playing.tick();
+    }
+    // Done here rather than in `new`, so the tests don't pick up this machine's width or socket.
+    if let Some(width) = ctl::load_persisted_width() {
+        polybar_now_playing.display_len = width;
+    }
+    if args.one_shot {
+        return polybar_now_playing.tick();
+    }
+    polybar_now_playing.control = ControlServer::bind()
+        .map_err(|e| verbose!("control interface disabled: {}", e))
+        .ok();
     polybar_now_playing.run()?;
     Ok(())
 }
+
+/// The `DISPLAY_PLAYER_PREFIX` icon for the player's application.
+fn player_icon(player: &PlayerInfo) -> &'static str {
+    let app_name = player.app.to_lowercase();
+    DISPLAY_PLAYER_PREFIX
+        .iter()
+        .find(|(key, _)| app_name.contains(key))
+        .unwrap_or_else(|| DISPLAY_PLAYER_PREFIX.last().unwrap())
+        .1
+}
+
+/// The entry of a per-player table like `remap` whose key is part of the lowercased `app`.
+/// When several keys match, the longest, so most specific, wins, and among equally long
+/// ones the first in alphabetical order, so the choice never depends on the map's order.
+fn for_player<'a, T>(table: &'a HashMap<String, T>, app: &str) -> Option<&'a T> {
+    table
+        .iter()
+        .filter(|(name, _)| app.contains(&name.to_lowercase()))
+        .min_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)))
+        .map(|(_, value)| value)
+}
+
+/// Wraps `text` in polybar's `%{T<index>}` font tags, or leaves it in the bar's default font.
+fn with_font(text: &str, index: Option<u32>) -> String {
+    match index {
+        Some(index) => format!("%{{T{}}}{}%{{T-}}", index, text),
+        None => text.to_string(),
+    }
+}
+
+/// Polls the players until one's bus name contains `pattern` or `timeout` has passed, and
+/// returns the last list read either way.
+fn wait_for_player(source: &dyn MediaSource, pattern: &str, timeout: Duration) -> Result<Vec<PlayerInfo>, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    loop {
+        let players = source.players()?;
+        if players.iter().any(|player| player.name.contains(pattern)) || start.elapsed() >= timeout {
+            return Ok(players);
+        }
+        verbose!("waiting for a player matching {}", pattern);
+        sleep(Duration::from_millis(WAIT_POLL_INTERVAL));
+    }
+}
+
+/// Performs a `--control` action on `player_name`, or the first player, unless the same
+/// action was just performed. A named player is called as is, so buttons for playerctld work
+/// whether or not this run's config sets `use-playerctld`.
+fn run_control(source: &dyn MediaSource, action: Action, player_name: Option<&str>, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let player = match player_name {
+        Some(name) => PlayerInfo::new(name.to_string()),
+        None => source.players()?.into_iter().next().ok_or("no player available")?,
+    };
+    let window = config.control_debounce_ms.map_or(control::DEFAULT_DEBOUNCE, Duration::from_millis);
+    let key = format!("{} {}", action.method(), player.name);
+    if !control::debounce(&control::state_path(), &key, window, std::time::SystemTime::now()) {
+        verbose!("ignoring repeated {}", key);
+        return Ok(());
+    }
+    source.call(&player, action.method())
+}
+
+/// Prints the version, then probes what the module depends on at runtime so the output
+/// doubles as a check that the environment is set up.
+fn print_version() {
+    let players = SessionMediaSource::new(false).and_then(|source| source.players());
+    print!("{}", version_report(players, find_in_path("playerctl")));
+}
+
+/// The `--version` output for the players found on the session bus, or the error reaching
+/// it, and where `playerctl` is installed.
+fn version_report(players: Result<Vec<PlayerInfo>, Box<dyn std::error::Error>>, playerctl: Option<std::path::PathBuf>) -> String {
+    let mut report = format!("{} {}\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    match players {
+        Ok(players) => {
+            report += "session bus: reachable\n";
+            report += &format!("players: {}\n", players.len());
+            for player in players {
+                match player.identity.as_str() {
+                    "" => report += &format!("  {}\n", player.name),
+                    identity => report += &format!("  {} ({})\n", player.name, identity),
+                }
+            }
+        }
+        Err(e) => report += &format!("session bus: unreachable ({})\n", e),
+    }
+    // The buttons call the players through `--control`, so this only matters to e.g. keybindings.
+    match playerctl {
+        Some(path) => report += &format!("playerctl: {}\n", path.display()),
+        None => report += "playerctl: not found on PATH, the buttons don't need it\n",
+    }
+    report
+}
+
+fn find_in_path(program: &str) -> Option<std::path::PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).map(|dir| dir.join(program)).find(|candidate| candidate.is_file())
+}
+
+/// Polybar markup running `command` when `label` is clicked with mouse `button` (1 is the
+/// left button). Colons end the command in polybar's syntax, so those in it are escaped.
+fn polybar_action(button: u8, command: &str, label: &str) -> String {
+    format!("%{{A{}:{}:}}{}%{{A}}", button, command.replace(':', "\\:"), label)
+}
+
+/// Parses a template from the config, which may only use the known `PLACEHOLDERS`.
+fn parse_format(format: &str) -> Result<Template, String> {
+    check_placeholders(Template::parse(format)?)
+}
+
+fn check_placeholders(template: Template) -> Result<Template, String> {
+    match template.placeholders().into_iter().find(|name| !PLACEHOLDERS.contains(name)) {
+        Some(name) => Err(format!("unknown placeholder {{{}}} in format", name)),
+        None => Ok(template),
+    }
+}
+
+/// Renders microseconds in `format`, with hours if `length` (also in microseconds) is an
+/// hour or more, so a position and its track's length look alike. Whole seconds are counted
+/// in integers, so a position is never rounded up past what has been played.
+fn format_time(microseconds: i64, length: i64, format: TimeFormat) -> String {
+    let seconds = microseconds.max(0) / 1_000_000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if format == TimeFormat::Hours || hours > 0 || length >= 3_600_000_000 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else if format == TimeFormat::PaddedMinutes {
+        format!("{:02}:{:02}", minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// `smart-mode`'s pick for a track: the scrolling text if `scroll-mode` is set, otherwise the
+/// progress bar when the player reports the position and the track's length, otherwise the
+/// text, held still and centred if it is no longer than the display and scrolled if it is.
+/// Takes the cleaned-up metadata, where `mpris:length` is text like every other field.
+fn choose_display_mode(metadata: &HashMap<String, String>, config: &DisplayConfig) -> DisplayMode {
+    let length = metadata.get("mpris:length").and_then(|length| length.parse::<i64>().ok());
+    if config.scroll_mode.is_some() {
+        DisplayMode::Scroll
+    } else if config.position.is_some() && length.is_some_and(|length| length > 0) {
+        DisplayMode::Progress
+    } else if config.text_length <= config.display_len {
+        DisplayMode::Static
+    } else {
+        DisplayMode::Scroll
+    }
+}
+
+/// Renders a playback rate as e.g. `1.5×`, or nothing at normal speed.
+fn format_rate(rate: f64) -> String {
+    if (rate - 1.0).abs() < 1e-6 {
+        String::new()
+    } else {
+        format!("{}×", (rate * 100.0).round() / 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    fn with_source(source: MockMediaSource) -> PolybarNowPlaying {
+        PolybarNowPlaying::new(Config::default(), Box::new(source)).unwrap()
+    }
+
+    #[test]
+    fn update_detects_the_mock_track() {
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Bohemian Rhapsody"));
+        instance.update_message().unwrap();
+        let track = instance.last_track.clone().unwrap();
+        assert_eq!((track.title.as_str(), track.artist.as_str()), ("Bohemian Rhapsody", "Queen"));
+        assert_eq!(track.player, instance.players[0].name);
+    }
+
+    #[test]
+    fn track_ids_tell_apart_tracks_of_the_same_name() {
+        let track = |id: Option<&str>, title: &str| Track { player: "mpv".to_string(), id: id.map(String::from), title: title.to_string(), artist: "Tool".to_string() };
+        assert!(!track(Some("/io/mpv/tracklist/1"), "Parabol").is_same(&track(Some("/io/mpv/tracklist/2"), "Parabol")));
+        assert!(track(Some("/io/mpv/tracklist/1"), "Parabol").is_same(&track(Some("/io/mpv/tracklist/1"), "Parabola")));
+        assert!(track(None, "Parabol").is_same(&track(Some("/io/mpv/tracklist/1"), "Parabol")));
+
+        let metadata = [
+            ("mpris:trackid".to_string(), MetadataValue::Text(NO_TRACK.to_string())),
+            ("xesam:title".to_string(), MetadataValue::Text(String::new())),
+        ];
+        let state = source::DisplayState { metadata: metadata.into_iter().collect(), ..source::DisplayState::default() };
+        let mut instance = with_source(MockMediaSource::new(state));
+        instance.last_track = Some(track(None, "Parabol"));
+        instance.update_state().unwrap();
+        assert_eq!(instance.last_track.unwrap().title, "Parabol");
+    }
+
+    #[test]
+    fn wait_for_player_gives_up_after_the_timeout() {
+        let source = MockMediaSource::playing("Queen", "Bohemian Rhapsody");
+        let name = source.players().unwrap()[0].name.clone();
+        assert_eq!(wait_for_player(&source, "mock", Duration::from_secs(60)).unwrap()[0].name, name);
+        let start = Instant::now();
+        assert_eq!(wait_for_player(&source, "spotify", Duration::ZERO).unwrap().len(), 1);
+        assert!(start.elapsed() < Duration::from_millis(WAIT_POLL_INTERVAL));
+    }
+
+    #[test]
+    fn blank_artists_count_as_missing_without_a_dangling_separator() {
+        let mut instance = with_source(MockMediaSource::playing("  ", "Cat"));
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Cat - No artist");
+        let config: Config = toml::from_str("[missing-field-text]\nartist = \"\"").unwrap();
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("", "Cat video"))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Cat video");
+    }
+
+    #[test]
+    fn format_leaves_out_the_missing_artist() {
+        let config = Config { format: Some("{title}{? - {artist}}".to_string()), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("", "Cat video"))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Cat video");
+        let config = Config { format: Some("{title}{? - {artist}}".to_string()), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("", ""))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), NO_PLAYER_TEXT);
+        let config = Config { format: Some("{title}{? - {artist}}".to_string()), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing(" ", "Cat video"))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Cat video");
+    }
+
+    #[test]
+    fn format_fills_in_what_the_player_reports() {
+        let config = Config { format: Some("{title} [{position}/{length}] on {player}, {status}".to_string()), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::test_track())).unwrap();
+        instance.display_len = 60;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_text(), "The Quick Brown Fox [1:30/3:33] on mock, Playing");
+        assert_eq!(format_time(3_723_000_000, 3_723_000_000, TimeFormat::Minutes), "1:02:03");
+    }
+
+    #[test]
+    fn rate_placeholder_is_empty_at_the_normal_rate() {
+        let with_rate = |rate: f64| {
+            let metadata = [("xesam:title".to_string(), MetadataValue::Text("Chapter 1".to_string()))];
+            let state = source::DisplayState { metadata: metadata.into_iter().collect(), rate: Some(rate), ..source::DisplayState::default() };
+            let config = Config { format: Some("{title}{? ({rate})}".to_string()), ..Config::default() };
+            let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::new(state))).unwrap();
+            instance.update_state().unwrap();
+            instance.scroller.text().to_string()
+        };
+        assert_eq!(with_rate(1.5), "Chapter 1 (1.5×)");
+        assert_eq!(with_rate(1.0), "Chapter 1");
+    }
+
+    #[test]
+    fn progress_placeholder_is_empty_without_a_position() {
+        let config = || Config { format: Some("{title}{? [{progress}]}".to_string()), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config(), Box::new(MockMediaSource::test_track())).unwrap();
+        instance.display_len = 40;
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "The Quick Brown Fox [████░░░░░░]");
+
+        let metadata = [
+            ("xesam:title".to_string(), MetadataValue::Text("Live Stream".to_string())),
+            ("mpris:length".to_string(), MetadataValue::Int(213_000_000)),
+        ];
+        let state = source::DisplayState { metadata: metadata.into_iter().collect(), position: None, ..source::DisplayState::default() };
+        let mut instance = PolybarNowPlaying::new(config(), Box::new(MockMediaSource::new(state))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Live Stream");
+    }
+
+    #[test]
+    fn format_rejects_unknown_placeholders() {
+        let config = Config { format: Some("{title} - {artsit}".to_string()), ..Config::default() };
+        let error = PolybarNowPlaying::new(config, Box::new(MockMediaSource::test_track())).err().unwrap();
+        assert_eq!(error.to_string(), "unknown placeholder {artsit} in format");
+        let config = Config { format: Some("{{{albumArtist}}}".to_string()), ..Config::default() };
+        assert!(PolybarNowPlaying::new(config, Box::new(MockMediaSource::test_track())).is_ok());
+    }
+
+    #[test]
+    fn format_shows_year_and_first_genre_when_present() {
+        let with_metadata = |created: &str, genres: &[&str]| {
+            let metadata = [
+                ("xesam:title".to_string(), MetadataValue::Text("Innuendo".to_string())),
+                ("xesam:contentCreated".to_string(), MetadataValue::Text(created.to_string())),
+                ("xesam:genre".to_string(), MetadataValue::List(genres.iter().map(|genre| genre.to_string()).collect())),
+            ];
+            let state = source::DisplayState { metadata: metadata.into_iter().collect(), ..source::DisplayState::default() };
+            let config = Config { format: Some("{title}{? ({year})}{? [{genre}]}".to_string()), ..Config::default() };
+            let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::new(state))).unwrap();
+            instance.display_len = 40;
+            instance.update_state().unwrap();
+            instance.scroller.text().to_string()
+        };
+        assert_eq!(with_metadata("1991-02-04T00:00:00Z", &["Rock", "Progressive"]), "Innuendo (1991) [Rock]");
+        assert_eq!(with_metadata("1991", &[]), "Innuendo (1991)");
+        assert_eq!(with_metadata("sometime", &["", "Rock"]), "Innuendo [Rock]");
+    }
+
+    #[test]
+    fn format_shows_decoded_paths_with_literal_percent_signs() {
+        let metadata = [("xesam:url".to_string(), MetadataValue::Text("file:///home/me/Music/Caf%C3%A9/100%25.mp3".to_string()))];
+        let state = source::DisplayState { metadata: metadata.into_iter().collect(), ..source::DisplayState::default() };
+        let config = Config { format: Some("{url}".to_string()), library_roots: vec!["/home/me/Music/".to_string()], ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::new(state))).unwrap();
+        instance.display_len = 40;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_text(), "Café/100%.mp3");
+        assert!(instance.render().contains("%{T1}Café/100%.mp3"));
+    }
+
+    #[test]
+    fn stream_titles_lose_their_station_clutter() {
+        let stream = |url: &str, length: Option<i64>| {
+            let mut metadata: source::MprisMetadata = [
+                ("xesam:title".to_string(), MetadataValue::Text("CoolFM 101.1 | Daft Punk - Around the World | www.coolfm.example".to_string())),
+                ("xesam:url".to_string(), MetadataValue::Text(url.to_string())),
+            ]
+            .into_iter()
+            .collect();
+            if let Some(length) = length {
+                metadata.insert("mpris:length".to_string(), MetadataValue::Int(length));
+            }
+            let instance = with_source(MockMediaSource::new(source::DisplayState { metadata, ..source::DisplayState::default() }));
+            let player = instance.players[0].clone();
+            let metadata = instance.get_metadata(&player).unwrap();
+            (metadata.get("xesam:artist").cloned(), metadata["xesam:title"].clone())
+        };
+        let cleaned = (Some("Daft Punk".to_string()), "Around the World".to_string());
+        assert_eq!(stream("https://stream.coolfm.example/live", None), cleaned);
+        assert_eq!(stream("http://stream.coolfm.example/live", Some(0)), cleaned);
+        assert_eq!(stream("https://example.com/episode.mp3", Some(213_000_000)).1, "CoolFM 101.1 | Daft Punk - Around the World | www.coolfm.example");
+        assert_eq!(stream("file:///tmp/stream.mp3", None).0, None);
+    }
+
+    #[test]
+    fn show_url_names_the_stream_host_in_the_text_or_the_suffix() {
+        let state = || {
+            let metadata = [
+                ("xesam:title".to_string(), MetadataValue::Text("Live".to_string())),
+                ("xesam:url".to_string(), MetadataValue::Text("https://stream.radio.example/live.mp3".to_string())),
+            ];
+            source::DisplayState { metadata: metadata.into_iter().collect(), ..source::DisplayState::default() }
+        };
+        let config = Config { show_url: true, missing_field_text: [("artist".to_string(), String::new())].into(), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::new(state()))).unwrap();
+        instance.display_len = 40;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_text(), "Live (stream.radio.example)");
+        let config = Config { show_url: true, url_in_suffix: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::new(state()))).unwrap();
+        instance.update_state().unwrap();
+        assert!(!instance.display_text().contains("radio"));
+        assert!(instance.display_suffix.ends_with("%{A} stream.radio.example"));
+    }
+
+    #[test]
+    fn show_comment_appends_the_truncated_comment_to_the_suffix() {
+        let notes = "II. Allegretto, from the Symphony No. 7 in A major, Op. 92";
+        let metadata = [
+            ("xesam:title".to_string(), MetadataValue::Text("Symphony No. 7".to_string())),
+            ("xesam:comment".to_string(), MetadataValue::List(vec![notes.to_string()])),
+        ];
+        let state = || source::DisplayState { metadata: metadata.clone().into_iter().collect(), ..source::DisplayState::default() };
+        let config = Config { show_comment: true, comment_max_len: Some(16), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::new(state()))).unwrap();
+        instance.update_state().unwrap();
+        assert!(instance.display_suffix.ends_with("%{A} II. Allegretto,…"));
+        assert!(!instance.display_text().contains("Allegretto"));
+        let mut instance = with_source(MockMediaSource::new(state()));
+        instance.update_state().unwrap();
+        assert!(!instance.display_suffix.contains("Allegretto"));
+    }
+
+    #[test]
+    fn wraps_prefix_and_controls_in_their_fonts() {
+        let config = Config { prefix_font_index: Some(2), control_font_index: Some(3), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Bohemian Rhapsody"))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_prefix, format!("%{{T2}}{}%{{T-}}", DISPLAY_PLAYER_PREFIX[2].1));
+        assert!(instance.display_suffix.starts_with("%{T3}%{A1:") && instance.display_suffix.ends_with("%{A}%{T-}"));
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Bohemian Rhapsody"));
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_prefix, DISPLAY_PLAYER_PREFIX[2].1);
+    }
+
+    #[test]
+    fn separators_around_the_controls_are_configurable() {
+        let config = Config { text_separator: Some(" :: ".to_string()), textless_separator: Some(" · ".to_string()), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Bohemian Rhapsody"))).unwrap();
+        instance.update_state().unwrap();
+        assert!(instance.compose_output().ends_with(&format!("%{{T-}} :: {}", instance.display_suffix)));
+        instance.scroller.set_text("");
+        assert_eq!(instance.compose_output(), format!("{} · {}", instance.display_prefix, instance.display_suffix));
+    }
+
+    #[test]
+    fn labels_spotify_ads_without_controls() {
+        let config = Config { ad_hide_controls: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("", "Advertisement"))).unwrap();
+        instance.last_track = Some(Track { player: "mock".to_string(), id: None, title: "Song".to_string(), artist: "Band".to_string() });
+        instance.update_state().unwrap();
+        assert!(instance.showing_ad && instance.last_track.is_none());
+        assert_eq!(instance.render(), format!("{} %{{T1}}{}{}%{{T-}}", instance.display_prefix, AD_TEXT, " ".repeat(7)));
+    }
+
+    #[test]
+    fn static_format_replaces_text_that_fits() {
+        let config = || Config {
+            format: Some("{title}{? - {artist}}".to_string()),
+            static_when_short: true,
+            format_static: Some("{artist}: {title}".to_string()),
+            ..Config::default()
+        };
+        let mut instance = PolybarNowPlaying::new(config(), Box::new(MockMediaSource::playing("Queen", "Innuendo"))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Queen: Innuendo");
+        let mut instance = PolybarNowPlaying::new(config(), Box::new(MockMediaSource::playing("Queen", "Bohemian Rhapsody"))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Bohemian Rhapsody - Queen - ");
+    }
+
+    #[test]
+    fn fixed_format_stays_put_while_the_body_scrolls() {
+        let config = Config { fixed_format: Some("{artist}: ".to_string()), format: Some("{title}".to_string()), ..Config::default() };
+        let source = MockMediaSource::playing("Queen", "Bohemian Rhapsody, the full six minutes");
+        let mut instance = PolybarNowPlaying::new(config, Box::new(source)).unwrap();
+        instance.update_state().unwrap();
+        let first = instance.render();
+        let second = instance.render();
+        assert!(first.contains("%{T1}Queen: Bohemian Rhapsody, t%{T-}"));
+        assert!(second.contains("%{T1}Queen: ohemian Rhapsody, th%{T-}"));
+    }
+
+    #[test]
+    fn many_artists_are_capped_then_fitted_to_the_artist_budget() {
+        let performers = ["Gundula Janowitz", "Christa Ludwig", "Waldemar Kmentt", "Walter Berry", "Wiener Singverein", "Herbert von Karajan"];
+        let metadata = [
+            ("xesam:title".to_string(), MetadataValue::Text("Missa solemnis".to_string())),
+            ("xesam:artist".to_string(), MetadataValue::List(performers.map(String::from).to_vec())),
+        ];
+        let state = source::DisplayState { metadata: metadata.into_iter().collect(), ..source::DisplayState::default() };
+        let config = Config { max_artists: Some(2), artist_max: Some(30), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::new(state))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Missa solemnis - Gundula Janowitz, Christa Lud… - ");
+
+        let metadata = [
+            ("xesam:title".to_string(), MetadataValue::Text("Missa solemnis".to_string())),
+            ("xesam:artist".to_string(), MetadataValue::List(Vec::new())),
+            ("xesam:albumArtist".to_string(), MetadataValue::List(vec!["Herbert von Karajan".to_string()])),
+        ];
+        let state = source::DisplayState { metadata: metadata.into_iter().collect(), ..source::DisplayState::default() };
+        let instance = with_source(MockMediaSource::new(state));
+        let player = instance.players[0].clone();
+        assert_eq!(instance.get_metadata(&player).unwrap()["xesam:artist"], "Herbert von Karajan");
+    }
+
+    #[test]
+    fn version_report_lists_the_players_and_playerctl() {
+        let mut spotify = PlayerInfo::new("org.mpris.MediaPlayer2.spotify".to_string());
+        spotify.identity = "Spotify".to_string();
+        let players = vec![spotify, PlayerInfo::new("org.mpris.MediaPlayer2.mpv".to_string())];
+        let report = version_report(Ok(players), Some("/usr/bin/playerctl".into()));
+        let expected = "session bus: reachable\nplayers: 2\n  org.mpris.MediaPlayer2.spotify (Spotify)\n  org.mpris.MediaPlayer2.mpv\nplayerctl: /usr/bin/playerctl\n";
+        assert_eq!(report, format!("{} {}\n{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), expected));
+        let report = version_report(Err("no bus".into()), None);
+        assert!(report.ends_with("session bus: unreachable (no bus)\nplayerctl: not found on PATH, the buttons don't need it\n"));
+    }
+
+    #[test]
+    fn dbus_direct_buttons_run_the_helper_binary() {
+        let config = Config { use_dbus_direct: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Innuendo"))).unwrap();
+        instance.update_state().unwrap();
+        assert!(instance.display_suffix.contains("polybar-now-playing-cmd --player org.mpris.MediaPlayer2.mock next:}"));
+        assert!(!instance.display_suffix.contains("--control"));
+    }
+
+    #[test]
+    fn buttons_go_through_the_debounced_control_mode() {
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Innuendo"));
+        instance.update_state().unwrap();
+        assert!(instance.display_suffix.contains(" --control next --player org.mpris.MediaPlayer2.mock:}"));
+        assert!(!instance.display_suffix.contains("playerctl"));
+        instance.update_prefix_suffix(None, PlaybackStatus::Stopped, None);
+        assert!(instance.display_suffix.contains(" --control previous --player org.mpris.MediaPlayer2.playerctld:}"));
+    }
+
+    #[test]
+    fn an_unchanged_fetch_is_neither_rebuilt_nor_printed() {
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Innuendo"));
+        instance.update_message().unwrap();
+        instance.update_message().unwrap();
+        assert_eq!(instance.handle_command(&Command::Status), Ok("rendered 1 skipped 1".to_string()));
+        instance.fixed_text = "stale".to_string();
+        instance.update_state().unwrap();
+        assert_eq!(instance.fixed_text, "stale");
+        instance.display_len = 10;
+        instance.update_message().unwrap();
+        assert!(instance.fixed_text.is_empty());
+        assert_eq!(instance.handle_command(&Command::Status), Ok("rendered 2 skipped 1".to_string()));
+    }
+
+    #[test]
+    fn field_output_renders_one_placeholder_alone() {
+        let config = Config { field: Some("artist".to_string()), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Bohemian Rhapsody"))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.render(), format!("%{{T1}}Queen{}%{{T-}}", " ".repeat(15)));
+        let config = Config { field: Some("album".to_string()), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Bohemian Rhapsody"))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.render(), "");
+    }
+
+    #[test]
+    fn restores_the_saved_offset_for_the_same_track() {
+        let restore = |player: &str| {
+            let mut instance = with_source(MockMediaSource::load(std::path::Path::new("tests/fixtures/playing.json")).unwrap());
+            let track_id = "/com/spotify/track/4u7EnebtmKWzUH433cf5Qv".to_string();
+            instance.pending_restore = Some(SavedState { player: player.to_string(), track_id, offset: 4 });
+            instance.update_state().unwrap();
+            assert!(instance.pending_restore.is_none());
+            instance.scroller.offset()
+        };
+        assert_eq!(restore("org.mpris.MediaPlayer2.spotify"), 4);
+        assert_eq!(restore("org.mpris.MediaPlayer2.vlc"), 0);
+    }
+
+    #[test]
+    fn renders_metadata_into_a_padded_window() {
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Bohemian Rhapsody"));
+        instance.display_len = 30;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_text(), "Bohemian Rhapsody - Queen");
+        let expected = format!("{} %{{T1}}Bohemian Rhapsody - Queen     %{{T-}} | {}", instance.display_prefix, instance.display_suffix);
+        assert_eq!(instance.render(), expected);
+        assert_eq!(instance.render(), expected);
+    }
+
+    #[test]
+    fn scrolls_long_metadata_one_step_per_render() {
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Bohemian Rhapsody"));
+        instance.display_len = 10;
+        let mut windows = Vec::new();
+        for _ in 0..3 {
+            instance.update_state().unwrap();
+            instance.render();
+            windows.push(instance.scroller.window().to_string());
+        }
+        assert_eq!(windows, ["Bohemian R", "ohemian Rh", "hemian Rha"]);
+        assert_eq!(instance.display_text(), "Bohemian Rhapsody - Queen - ");
+    }
+
+    #[test]
+    fn the_longest_matching_player_key_wins() {
+        let table: HashMap<String, u32> = [("fire", 1), ("firefox", 2), ("Firefox.instance", 3), ("fox", 4)]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        assert_eq!(for_player(&table, "firefox.instance_1_42"), Some(&3));
+        assert_eq!(for_player(&table, "firefox"), Some(&2));
+        assert_eq!(for_player(&table, "foxtrot"), Some(&4));
+        assert_eq!(for_player(&table, "spotify"), None);
+        // Equally long keys fall back to alphabetical order.
+        let table: HashMap<String, u32> = [("vlc", 1), ("mpv", 2)].into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+        assert_eq!(for_player(&table, "mpv-vlc"), Some(&2));
+    }
+
+    #[test]
+    fn player_scroll_speed_slows_matching_players() {
+        let config: Config = toml::from_str("[player-scroll-speed]\nMock = 2\nspotify = 1\nmo = 3").unwrap();
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Bohemian Rhapsody"))).unwrap();
+        instance.display_len = 10;
+        let mut windows = Vec::new();
+        for _ in 0..4 {
+            instance.update_state().unwrap();
+            instance.render();
+            windows.push(instance.scroller.window().to_string());
+        }
+        assert_eq!(instance.scroll_ticks_per_step, 2);
+        assert_eq!(windows, ["Bohemian R", "Bohemian R", "ohemian Rh", "ohemian Rh"]);
+    }
+
+    #[test]
+    fn min_display_width_pads_the_whole_line() {
+        let config = Config { min_display_width: Some(60), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Innuendo"))).unwrap();
+        instance.update_state().unwrap();
+        let output = instance.render();
+        let output = instance.pad_to_min_width(output);
+        assert_eq!(text::visual_width_of_polybar_string(&output), 60);
+        assert!(output.ends_with(' ') && output.trim_end().ends_with("%{A}"));
+        assert_eq!(instance.pad_to_min_width(String::new()), "");
+        let long = "x".repeat(70);
+        assert_eq!(instance.pad_to_min_width(long.clone()), long);
+    }
+
+    #[test]
+    fn scroll_ticks_move_the_marquee_without_fetching() {
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Bohemian Rhapsody"));
+        instance.display_len = 10;
+        instance.update_state().unwrap();
+        assert!(instance.is_scrolling());
+        instance.scroll_tick().unwrap();
+        instance.scroll_tick().unwrap();
+        assert_eq!(instance.last_output.as_deref().map(|line| line.contains("%{T1}ohemian Rh%{T-}")), Some(true));
+        instance.status_paused = true;
+        assert!(!instance.is_scrolling());
+        instance.config.scroll_when_paused = true;
+        assert!(instance.is_scrolling());
+    }
+
+    #[test]
+    fn test_output_renders_the_fake_track() {
+        let mut instance = with_source(MockMediaSource::test_track());
+        instance.display_len = 40;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_text(), "The Quick Brown Fox - Test Artist");
+    }
+
+    #[test]
+    fn toggle_mute_restores_the_previous_volume() {
+        let state = source::DisplayState { volume: Some(0.6), ..source::DisplayState::default() };
+        let mut instance = with_source(MockMediaSource::new(state));
+        let player = instance.players[0].clone();
+        instance.toggle_mute(&player.name).unwrap();
+        assert_eq!(instance.get_volume(&player), Some(0.0));
+        instance.toggle_mute(&player.name).unwrap();
+        assert_eq!(instance.get_volume(&player), Some(0.6));
+        assert!(instance.toggle_mute("org.mpris.MediaPlayer2.nobody").is_err());
+    }
+
+    #[test]
+    fn scrolling_over_the_icon_changes_the_volume_within_bounds() {
+        let state = source::DisplayState { volume: Some(0.98), ..source::DisplayState::default() };
+        let config = Config { scroll_volume_on_icon: true, volume_step: Some(0.1), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::new(state))).unwrap();
+        instance.update_state().unwrap();
+        let player = instance.players[0].clone();
+        assert!(instance.display_prefix.starts_with("%{A4:"));
+        assert!(instance.display_prefix.contains(&format!(" ctl volume {} 0.1:}}%{{A5:", player.name)));
+        assert!(instance.display_prefix.ends_with(&format!(" ctl volume {} -0.1:}}{}%{{A}}%{{A}}", player.name, DISPLAY_PLAYER_PREFIX[2].1)));
+        instance.handle_command(&Command::ChangeVolume { player: player.name.clone(), delta: 0.1 }).unwrap();
+        assert_eq!(instance.get_volume(&player), Some(1.0));
+    }
+
+    #[test]
+    fn raw_status_is_the_bare_status_word() {
+        let state = source::DisplayState { status: PlaybackStatus::Paused, ..source::DisplayState::default() };
+        let mut instance = PolybarNowPlaying::new(Config { raw_status: true, ..Config::default() }, Box::new(MockMediaSource::new(state))).unwrap();
+        assert_eq!(instance.raw_status(), "Paused");
+        instance.players.clear();
+        assert_eq!(instance.raw_status(), "None");
+    }
+
+    #[test]
+    fn summary_counts_players_and_shows_whether_any_plays() {
+        let players = MockPlayers(vec![
+            mock_player("org.mpris.MediaPlayer2.spotify", "Song A", PlaybackStatus::Paused),
+            mock_player("org.mpris.MediaPlayer2.vlc", "Film", PlaybackStatus::Playing),
+        ]);
+        let instance = PolybarNowPlaying::new(Config { summary: true, ..Config::default() }, Box::new(players)).unwrap();
+        assert_eq!(instance.summary(), "♫2");
+        let paused = MockPlayers(vec![mock_player("org.mpris.MediaPlayer2.spotify", "Song A", PlaybackStatus::Paused)]);
+        let mut instance = PolybarNowPlaying::new(Config { summary: true, ..Config::default() }, Box::new(paused)).unwrap();
+        assert_eq!(instance.summary(), "⏸1");
+        instance.players.clear();
+        assert_eq!(instance.summary(), "");
+    }
+
+    #[test]
+    fn progress_in_text_replaces_the_text_when_the_position_is_known() {
+        let config = Config { progress_in_text: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::test_track())).unwrap();
+        instance.display_len = 10;
+        instance.update_state().unwrap();
+        assert!(instance.render().contains("%{T1}████░░░░░░%{T-}"));
+        let config = Config { progress_in_text: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Innuendo"))).unwrap();
+        instance.update_state().unwrap();
+        assert!(instance.render().contains("Innuendo - Queen"));
+    }
+
+    #[test]
+    fn smart_mode_picks_the_bar_then_still_then_scrolling_text() {
+        let length = |value: &str| HashMap::from([("mpris:length".to_string(), value.to_string())]);
+        let display = |position, text_length| DisplayConfig { position, text_length, display_len: 30, scroll_mode: None };
+        assert_eq!(choose_display_mode(&length("213000000"), &display(Some(0), 80)), DisplayMode::Progress);
+        assert_eq!(choose_display_mode(&length("0"), &display(Some(0), 30)), DisplayMode::Static);
+        assert_eq!(choose_display_mode(&length("213000000"), &display(None, 31)), DisplayMode::Scroll);
+        assert_eq!(choose_display_mode(&HashMap::new(), &display(Some(0), 10)), DisplayMode::Static);
+    }
+
+    #[test]
+    fn a_set_scroll_mode_overrides_smart_mode() {
+        let forced = DisplayConfig { position: Some(0), text_length: 10, display_len: 30, scroll_mode: Some(ScrollMode::Once) };
+        let length = HashMap::from([("mpris:length".to_string(), "213000000".to_string())]);
+        assert_eq!(choose_display_mode(&length, &forced), DisplayMode::Scroll);
+
+        let config: Config = toml::from_str("smart-mode = true\nscroll-mode = \"loop\"").unwrap();
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::test_track())).unwrap();
+        instance.display_len = 10;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_mode, Some(DisplayMode::Scroll));
+        assert!(!instance.render().contains("░"));
+    }
+
+    #[test]
+    fn smart_mode_centres_text_that_fits() {
+        let config = Config { smart_mode: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Innuendo"))).unwrap();
+        instance.display_len = 20;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_mode, Some(DisplayMode::Static));
+        assert!(instance.render().contains("%{T1}  Innuendo - Queen  %{T-}"));
+        let config = Config { smart_mode: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::test_track())).unwrap();
+        instance.display_len = 10;
+        instance.update_state().unwrap();
+        assert!(instance.render().contains("%{T1}████░░░░░░%{T-}"));
+    }
+
+    #[test]
+    fn seek_on_scroll_wraps_the_text_in_seek_actions() {
+        let config = Config { seek_on_scroll: true, seek_scroll_step_seconds: Some(5), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Innuendo"))).unwrap();
+        instance.update_state().unwrap();
+        let output = instance.render();
+        let player = &instance.players[0].name;
+        assert!(output.contains(&format!("polybar-now-playing-cmd --player {} seek 5000000:}}%{{A5:", player)));
+        assert!(output.contains(&format!("polybar-now-playing-cmd --player {} seek -5000000:}}%{{T1}}Innuendo - Queen", player)));
+        assert!(output.contains("%{T-}%{A}%{A} | "));
+    }
+
+    #[test]
+    fn a_player_whose_position_stands_still_counts_as_paused() {
+        let config = Config { stale_check: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::test_track())).unwrap();
+        instance.update_players().unwrap();
+        instance.update_state().unwrap();
+        assert!(!instance.status_paused);
+        for _ in 0..3 {
+            instance.update_players().unwrap();
+        }
+        instance.update_state().unwrap();
+        assert!(instance.status_paused);
+    }
+
+    /// Several mock players, each answering for its own bus name.
+    struct MockPlayers(Vec<MockMediaSource>);
+
+    impl MockPlayers {
+        /// Fails like a player that left the bus for names none of them has.
+        fn source(&self, player: &PlayerInfo) -> Result<&MockMediaSource, Box<dyn std::error::Error>> {
+            let source = self.0.iter().find(|source| source.players().unwrap()[0].name == player.name);
+            source.ok_or_else(|| format!("{} is not on the bus", player.name).into())
+        }
+    }
+
+    impl MediaSource for MockPlayers {
+        fn players(&self) -> Result<Vec<PlayerInfo>, Box<dyn std::error::Error>> {
+            Ok(self.0.iter().flat_map(|source| source.players().unwrap()).collect())
+        }
+        fn status(&self, player: &PlayerInfo) -> Result<PlaybackStatus, Box<dyn std::error::Error>> {
+            self.source(player)?.status(player)
+        }
+        fn metadata(&self, player: &PlayerInfo) -> Result<source::MprisMetadata, Box<dyn std::error::Error>> {
+            self.source(player)?.metadata(player)
+        }
+        fn rate(&self, player: &PlayerInfo) -> Result<f64, Box<dyn std::error::Error>> {
+            self.source(player)?.rate(player)
+        }
+        fn position(&self, player: &PlayerInfo) -> Result<i64, Box<dyn std::error::Error>> {
+            self.source(player)?.position(player)
+        }
+        fn volume(&self, player: &PlayerInfo) -> Result<f64, Box<dyn std::error::Error>> {
+            self.source(player)?.volume(player)
+        }
+        fn set_volume(&self, player: &PlayerInfo, volume: f64) -> Result<(), Box<dyn std::error::Error>> {
+            self.source(player)?.set_volume(player, volume)
+        }
+        fn desktop_entry(&self, player: &PlayerInfo) -> Result<String, Box<dyn std::error::Error>> {
+            self.source(player)?.desktop_entry(player)
+        }
+        fn call(&self, player: &PlayerInfo, method: &str) -> Result<(), Box<dyn std::error::Error>> {
+            self.source(player)?.call(player, method)
+        }
+    }
+
+    /// Forwards to the `active` one of its players like playerctld, remembering the players
+    /// that lack `Position` the way the bus backends do.
+    struct MockPlayerctld {
+        players: MockPlayers,
+        active: Rc<Cell<usize>>,
+        cache: RefCell<source::PlayerCache>,
+    }
+
+    impl MockPlayerctld {
+        fn names(&self) -> source::BusNames {
+            let names = self.players.players().unwrap().into_iter().map(|player| player.name);
+            let players = names.enumerate().map(|(i, name)| (name, format!(":1.{}", i + 1)));
+            std::iter::once((PLAYERCTLD_BUS_NAME.to_string(), ":1.0".to_string())).chain(players).collect()
+        }
+
+        /// The player playerctld forwards `player`'s calls to.
+        fn target<'a>(&'a self, player: &PlayerInfo) -> Result<&'a MockMediaSource, Box<dyn std::error::Error>> {
+            self.players.source(&PlayerInfo::new(player.app.clone()))
+        }
+    }
+
+    impl MediaSource for MockPlayerctld {
+        fn players(&self) -> Result<Vec<PlayerInfo>, Box<dyn std::error::Error>> {
+            let app = self.players.players()?.swap_remove(self.active.get()).name;
+            Ok(vec![PlayerInfo { app, ..PlayerInfo::new(PLAYERCTLD_BUS_NAME.to_string()) }])
+        }
+        fn status(&self, player: &PlayerInfo) -> Result<PlaybackStatus, Box<dyn std::error::Error>> {
+            self.target(player)?.status(player)
+        }
+        fn metadata(&self, player: &PlayerInfo) -> Result<source::MprisMetadata, Box<dyn std::error::Error>> {
+            self.target(player)?.metadata(player)
+        }
+        fn rate(&self, player: &PlayerInfo) -> Result<f64, Box<dyn std::error::Error>> {
+            self.target(player)?.rate(player)
+        }
+        fn position(&self, player: &PlayerInfo) -> Result<i64, Box<dyn std::error::Error>> {
+            if self.cache.borrow().lacks_position(player) {
+                return Err("Position is not supported".into());
+            }
+            self.target(player)?.position(player).inspect_err(|_| self.cache.borrow_mut().set_lacks_position(player, &self.names()))
+        }
+        fn volume(&self, player: &PlayerInfo) -> Result<f64, Box<dyn std::error::Error>> {
+            self.target(player)?.volume(player)
+        }
+        fn set_volume(&self, player: &PlayerInfo, volume: f64) -> Result<(), Box<dyn std::error::Error>> {
+            self.target(player)?.set_volume(player, volume)
+        }
+        fn desktop_entry(&self, player: &PlayerInfo) -> Result<String, Box<dyn std::error::Error>> {
+            self.target(player)?.desktop_entry(player)
+        }
+        fn call(&self, player: &PlayerInfo, method: &str) -> Result<(), Box<dyn std::error::Error>> {
+            self.target(player)?.call(player, method)
+        }
+    }
+
+    fn mock_player(name: &str, title: &str, status: PlaybackStatus) -> MockMediaSource {
+        let metadata = [("xesam:title".to_string(), MetadataValue::Text(title.to_string()))].into_iter().collect();
+        MockMediaSource::new(source::DisplayState { player: name.to_string(), status, metadata, ..source::DisplayState::default() })
+    }
+
+    #[test]
+    fn playerctld_shows_progress_again_after_switching_to_a_player_with_a_position() {
+        let player = |name: &str, title: &str, position| {
+            let metadata = [
+                ("xesam:title".to_string(), MetadataValue::Text(title.to_string())),
+                ("mpris:length".to_string(), MetadataValue::Int(200_000_000)),
+            ];
+            let metadata = metadata.into_iter().collect();
+            MockMediaSource::new(source::DisplayState { player: name.to_string(), metadata, position, ..source::DisplayState::default() })
+        };
+        let players = MockPlayers(vec![
+            player("org.mpris.MediaPlayer2.chromium", "Radio", None),
+            player("org.mpris.MediaPlayer2.spotify", "Song A", Some(100_000_000)),
+        ]);
+        let active = Rc::new(Cell::new(0));
+        let source = MockPlayerctld { players, active: Rc::clone(&active), cache: RefCell::default() };
+        let config = Config { format: Some("{title}{? [{progress}]}".to_string()), use_playerctld: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(source)).unwrap();
+        instance.display_len = 40;
+        instance.update_players().unwrap();
+        instance.update_state().unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Radio");
+
+        active.set(1);
+        instance.update_players().unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Song A [█████░░░░░]");
+    }
+
+    #[test]
+    fn a_sticky_player_stays_on_display_while_briefly_gone() {
+        let spotify = || mock_player("org.mpris.MediaPlayer2.spotify", "Song A", PlaybackStatus::Playing);
+        let vlc = || mock_player("org.mpris.MediaPlayer2.vlc", "Film", PlaybackStatus::Playing);
+        let config = Config { sticky_timeout_ms: Some(60_000), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockPlayers(vec![spotify(), vlc()]))).unwrap();
+        instance.update_players().unwrap();
+        instance.update_state().unwrap();
+        instance.source = Box::new(MockPlayers(vec![vlc()]));
+        instance.update_players().unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Song A - No artist");
+        assert!(instance.status_paused);
+        instance.source = Box::new(MockPlayers(vec![vlc(), spotify()]));
+        instance.update_players().unwrap();
+        assert_eq!(instance.players[instance.current_player].name, "org.mpris.MediaPlayer2.spotify");
+        assert!(instance.vanished.is_none());
+
+        instance.config.sticky_timeout_ms = Some(0);
+        instance.source = Box::new(MockPlayers(vec![vlc()]));
+        instance.update_players().unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.scroller.text(), "Film - No artist");
+    }
+
+    #[test]
+    fn combined_selection_joins_every_playing_player() {
+        let players = MockPlayers(vec![
+            mock_player("org.mpris.MediaPlayer2.spotify", "Song A", PlaybackStatus::Playing),
+            mock_player("org.mpris.MediaPlayer2.vlc", "Paused Film", PlaybackStatus::Paused),
+            mock_player("org.mpris.MediaPlayer2.firefox.instance_1_42", "Video B", PlaybackStatus::Playing),
+        ]);
+        let config = Config { selection: Selection::Combined, combined_hide_controls: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(players)).unwrap();
+        instance.update_state().unwrap();
+        let expected = format!("{} Song A | {} Video B", DISPLAY_PLAYER_PREFIX[0].1, DISPLAY_PLAYER_PREFIX[1].1);
+        assert_eq!(instance.scroller.text(), expected);
+        assert!(instance.display_prefix.is_empty() && instance.display_suffix.is_empty());
+
+        let players = MockPlayers(vec![mock_player("org.mpris.MediaPlayer2.spotify", "Song A", PlaybackStatus::Playing)]);
+        let config = Config { selection: Selection::Combined, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(players)).unwrap();
+        instance.update_state().unwrap();
+        assert!(instance.scroller.text().starts_with("Song A"));
+    }
+
+    #[test]
+    fn combined_selection_leaves_out_players_that_fail() {
+        let players = MockPlayers(vec![
+            mock_player("org.mpris.MediaPlayer2.spotify", "Song A", PlaybackStatus::Playing),
+            mock_player("org.mpris.MediaPlayer2.firefox.instance_1_42", "Video B", PlaybackStatus::Playing),
+        ]);
+        let config = Config { selection: Selection::Combined, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(players)).unwrap();
+        instance.players.insert(1, PlayerInfo::new("org.mpris.MediaPlayer2.vlc".to_string()));
+        assert!(instance.show_combined().unwrap());
+        let expected = format!("{} Song A | {} Video B", DISPLAY_PLAYER_PREFIX[0].1, DISPLAY_PLAYER_PREFIX[1].1);
+        assert_eq!(instance.scroller.text(), expected);
+    }
+
+    #[test]
+    fn screen_lock_pauses_and_resumes_only_what_it_paused() {
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Bohemian Rhapsody"));
+        instance.apply_screen_lock(false);
+        assert!(!instance.auto_paused);
+        instance.apply_screen_lock(true);
+        assert!(instance.auto_paused);
+        instance.apply_screen_lock(false);
+        assert!(!instance.auto_paused && instance.resume_at.is_none());
+
+        let config = Config { session_unlock_resume: true, resume_delay_ms: Some(60_000), ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::playing("Queen", "Bohemian Rhapsody"))).unwrap();
+        instance.apply_screen_lock(true);
+        instance.apply_screen_lock(false);
+        assert!(instance.auto_paused && instance.resume_at.is_some());
+        instance.resume_at = Some(Instant::now());
+        instance.apply_screen_lock(false);
+        assert!(!instance.auto_paused && instance.resume_at.is_none());
+    }
+
+    #[test]
+    fn position_detail_shows_position_and_length() {
+        let instance = with_source(MockMediaSource::test_track());
+        let player = instance.players[0].clone();
+        let mut metadata = instance.get_metadata(&player).unwrap();
+        assert_eq!(instance.position_detail(&player, &metadata).as_deref(), Some("1:30/3:33"));
+        metadata.remove("mpris:length");
+        assert_eq!(instance.position_detail(&player, &metadata).as_deref(), Some("1:30"));
+        let instance = with_source(MockMediaSource::playing("Queen", "Bohemian Rhapsody"));
+        assert_eq!(instance.position_detail(&player, &metadata), None);
+        assert_eq!(format_time(-5, 0, TimeFormat::Minutes), "0:00");
+    }
+
+    #[test]
+    fn formats_times_in_each_time_format() {
+        let track = 240_000_000;
+        assert_eq!(format_time(5_999_999, track, TimeFormat::Minutes), "0:05");
+        assert_eq!(format_time(187_000_000, track, TimeFormat::Minutes), "3:07");
+        assert_eq!(format_time(187_000_000, track, TimeFormat::PaddedMinutes), "03:07");
+        assert_eq!(format_time(187_000_000, track, TimeFormat::Hours), "0:03:07");
+        let audiobook = 4 * 3_600_000_000;
+        assert_eq!(format_time(3_750_000_000, audiobook, TimeFormat::Minutes), "1:02:30");
+        assert_eq!(format_time(5_000_000, audiobook, TimeFormat::PaddedMinutes), "0:00:05");
+    }
+
+    #[test]
+    fn podcasts_get_their_own_format_and_a_countdown() {
+        let episode = |genre: &str, length: i64| {
+            let metadata = [
+                ("xesam:title".to_string(), MetadataValue::Text("Episode 12".to_string())),
+                ("xesam:album".to_string(), MetadataValue::Text("The Show".to_string())),
+                ("xesam:artist".to_string(), MetadataValue::Text("Host".to_string())),
+                ("xesam:genre".to_string(), MetadataValue::List(vec![genre.to_string()])),
+                ("mpris:length".to_string(), MetadataValue::Int(length)),
+            ];
+            MockMediaSource::new(source::DisplayState { metadata: metadata.into_iter().collect(), position: Some(83_000_000), ..source::DisplayState::default() })
+        };
+        let config = || Config {
+            podcast: config::PodcastConfig { format: Some("{album}: {title}".to_string()), min_length_minutes: Some(45), ..Default::default() },
+            ..Config::default()
+        };
+        let mut instance = PolybarNowPlaying::new(config(), Box::new(episode("Podcasts", 213_000_000))).unwrap();
+        instance.display_len = 40;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_text(), "The Show: Episode 12");
+        let player = instance.players[0].clone();
+        let metadata = instance.get_metadata(&player).unwrap();
+        assert_eq!(instance.position_detail(&player, &metadata).as_deref(), Some("-2:10/3:33"));
+
+        let mut instance = PolybarNowPlaying::new(config(), Box::new(episode("Rock", 213_000_000))).unwrap();
+        instance.display_len = 40;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_text(), "Episode 12 - Host");
+        let mut instance = PolybarNowPlaying::new(config(), Box::new(episode("Rock", 3_000_000_000))).unwrap();
+        instance.display_len = 40;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_text(), "The Show: Episode 12");
+        let players = Config { podcast: config::PodcastConfig { players: vec!["MOCK".to_string()], ..config().podcast }, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(players, Box::new(episode("Rock", 213_000_000))).unwrap();
+        instance.display_len = 40;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_text(), "The Show: Episode 12");
+    }
+
+    #[test]
+    fn players_that_cannot_be_controlled_get_no_buttons() {
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Innuendo"));
+        instance.players[0].can_control = false;
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_suffix, "");
+        assert!(!instance.render().contains("%{A1:"));
+    }
+
+    #[test]
+    fn desktop_entry_prefix_falls_back_to_the_player_glyph() {
+        let config = || Config { prefix_source: PrefixSource::DesktopEntry, ..Config::default() };
+        let state = source::DisplayState { desktop_entry: Some("polybar-now-playing-no-such-app".to_string()), ..source::DisplayState::default() };
+        let mut instance = PolybarNowPlaying::new(config(), Box::new(MockMediaSource::new(state))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_prefix, DISPLAY_PLAYER_PREFIX[2].1);
+        assert_eq!(instance.desktop_icons.get("org.mpris.MediaPlayer2.mock"), Some(&None));
+        let mut instance = PolybarNowPlaying::new(config(), Box::new(MockMediaSource::playing("Queen", "Innuendo"))).unwrap();
+        instance.update_state().unwrap();
+        assert_eq!(instance.display_prefix, DISPLAY_PLAYER_PREFIX[2].1);
+    }
+
+    #[test]
+    fn buttons_escape_colons_in_player_names() {
+        let mut instance = with_source(MockMediaSource::playing("Queen", "Innuendo"));
+        let player = PlayerInfo::new(":1.42".to_string());
+        instance.update_prefix_suffix(Some(&player), PlaybackStatus::Playing, None);
+        assert!(instance.display_suffix.contains(" --control next --player \\:1.42:}"));
+    }
+
+    #[test]
+    fn rendered_markup_has_no_doubled_percent() {
+        let config = Config { show_mute: true, scroll_volume_on_icon: true, seek_on_scroll: true, ..Config::default() };
+        let mut instance = PolybarNowPlaying::new(config, Box::new(MockMediaSource::test_track())).unwrap();
+        instance.update_state().unwrap();
+        let output = instance.render();
+        assert!(output.contains("%{A1:") && !output.contains("%%"));
+    }
+
+    #[test]
+    fn polybar_action_escapes_colons() {
+        assert_eq!(polybar_action(1, "playerctl next", "▶"), "%{A1:playerctl next:}▶%{A}");
+        assert_eq!(polybar_action(3, "notify-send a:b", "x"), "%{A3:notify-send a\\:b:}x%{A}");
+    }
+
+    #[test]
+    fn format_rate_only_shows_non_default_rates() {
+        assert_eq!(format_rate(1.0), "");
+        assert_eq!(format_rate(1.5), "1.5×");
+        assert_eq!(format_rate(2.0), "2×");
+        assert_eq!(format_rate(0.75), "0.75×");
+    }
+}
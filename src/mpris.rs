@@ -0,0 +1,11 @@
+//! The MPRIS names and call timeout, shared with `polybar-now-playing-cmd`, which includes
+//! this file as a module of its own.
+
+use std::time::Duration;
+
+pub const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+pub const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+pub const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+/// playerctld's own bus name, which stands for whichever player was used last.
+pub const PLAYERCTLD_BUS_NAME: &str = "org.mpris.MediaPlayer2.playerctld";
+pub const TIMEOUT: Duration = Duration::from_millis(5000);
@@ -0,0 +1,122 @@
+use std::thread;
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, ConnectionError, Event, LastWill, Packet, QoS};
+
+/// Seconds the broker waits without hearing from us before firing the Last Will.
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// Payloads queued while the broker is unreachable; past that new ones are dropped.
+const QUEUE_CAPACITY: usize = 16;
+/// MQTT prefixes topics, client ids and will payloads with a 16-bit length.
+const MAX_STRING_LEN: usize = u16::MAX as usize;
+
+/// Where and how `MqttPublisher` connects.
+pub struct MqttOptions {
+    /// `host:port` of the broker.
+    pub broker: String,
+    pub topic: String,
+    pub client_id: String,
+    /// Topic and payload the broker publishes if we disconnect without saying goodbye.
+    pub will: Option<(String, String)>,
+}
+
+/// Publishes payloads to an MQTT broker as retained QoS 0 messages, so subscribers that
+/// connect later still see the current track. The connection is run by rumqttc on a
+/// background thread, so a slow or unreachable broker never holds up the display, and is
+/// re-made with exponential backoff when it drops.
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topic: String,
+}
+
+impl MqttPublisher {
+    pub fn spawn(options: MqttOptions) -> Result<Self, String> {
+        check_length("mqtt-topic", &options.topic)?;
+        check_length("mqtt-client-id", &options.client_id)?;
+        let (host, port) = options
+            .broker
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+            .ok_or_else(|| format!("mqtt-broker must be host:port, not {}", options.broker))?;
+        let mut mqtt_options = rumqttc::MqttOptions::new(options.client_id, host, port);
+        mqtt_options.set_keep_alive(KEEP_ALIVE);
+        if let Some((topic, payload)) = options.will {
+            check_length("mqtt-will-topic", &topic)?;
+            check_length("mqtt-will-payload", &payload)?;
+            mqtt_options.set_last_will(LastWill::new(topic, payload, QoS::AtMostOnce, false));
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().map_err(|e| e.to_string())?;
+        let (client, mut eventloop) = AsyncClient::new(mqtt_options, QUEUE_CAPACITY);
+        let broker = options.broker;
+        thread::spawn(move || {
+            runtime.block_on(async {
+                let mut retry_delay = FIRST_RETRY_DELAY;
+                loop {
+                    match eventloop.poll().await {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => retry_delay = FIRST_RETRY_DELAY,
+                        Ok(_) => {}
+                        Err(ConnectionError::RequestsDone) => return,
+                        Err(e) => {
+                            verbose!("mqtt: lost connection to {}: {}", broker, e);
+                            tokio::time::sleep(retry_delay).await;
+                            retry_delay = next_retry_delay(retry_delay);
+                        }
+                    }
+                }
+            })
+        });
+        Ok(MqttPublisher { client, topic: options.topic })
+    }
+
+    pub fn publish(&self, payload: String) {
+        if let Err(e) = self.client.try_publish(self.topic.as_str(), QoS::AtMostOnce, true, payload) {
+            verbose!("mqtt: dropped a payload: {}", e);
+        }
+    }
+}
+
+/// Refuses strings MQTT can't carry, which would otherwise go out with a wrapped length.
+fn check_length(option: &str, value: &str) -> Result<(), String> {
+    if value.len() > MAX_STRING_LEN {
+        return Err(format!("{} is {} bytes, but MQTT allows at most {}", option, value.len(), MAX_STRING_LEN));
+    }
+    Ok(())
+}
+
+fn next_retry_delay(delay: Duration) -> Duration {
+    (delay * 2).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(topic: String) -> MqttOptions {
+        MqttOptions { broker: "localhost:1883".to_string(), topic, client_id: "bar".to_string(), will: None }
+    }
+
+    #[test]
+    fn refuses_strings_longer_than_mqtt_allows() {
+        assert!(MqttPublisher::spawn(options("t".repeat(MAX_STRING_LEN))).is_ok());
+        let error = MqttPublisher::spawn(options("t".repeat(MAX_STRING_LEN + 1))).err().unwrap();
+        assert_eq!(error, "mqtt-topic is 65536 bytes, but MQTT allows at most 65535");
+        let will = Some(("np/status".to_string(), "x".repeat(70_000)));
+        assert!(MqttPublisher::spawn(MqttOptions { will, ..options("np".to_string()) }).is_err());
+    }
+
+    #[test]
+    fn needs_a_port_for_the_broker() {
+        assert!(MqttPublisher::spawn(MqttOptions { broker: "localhost".to_string(), ..options("np".to_string()) }).is_err());
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_a_minute() {
+        let delays: Vec<u64> = std::iter::successors(Some(FIRST_RETRY_DELAY), |&delay| Some(next_retry_delay(delay)))
+            .take(8)
+            .map(|delay| delay.as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+}
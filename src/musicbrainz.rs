@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+const API_URL: &str = "https://musicbrainz.org/ws/2/recording";
+const USER_AGENT: &str = concat!("polybar-now-playing-rust/", env!("CARGO_PKG_VERSION"), " ( https://github.com/wthrajat/polybar-now-playing-rust )");
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// MusicBrainz allows one request per second per client.
+const MIN_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+type Track = (String, String);
+type Supplement = HashMap<String, String>;
+
+/// Fills in `xesam:album` and `xesam:contentCreated` for players that don't report them,
+/// using the first MusicBrainz recording matching the artist and title. Lookups run on a
+/// background thread and are throttled to MusicBrainz's rate limit.
+#[derive(Default)]
+pub struct MusicBrainz {
+    cache: HashMap<Track, Supplement>,
+    current: Option<Track>,
+    queued: Option<Track>,
+    pending: Option<(Track, Receiver<Option<Supplement>>)>,
+    last_request: Option<Instant>,
+}
+
+impl MusicBrainz {
+    /// Notes the track now playing; a lookup is queued if it changed and isn't cached.
+    pub fn set_track(&mut self, artist: &str, title: &str) {
+        let track = (artist.to_string(), title.to_string());
+        if self.current.as_ref() == Some(&track) {
+            return;
+        }
+        self.current = Some(track.clone());
+        self.queued = (!self.cache.contains_key(&track)).then_some(track);
+    }
+
+    /// Collects finished lookups, starts a queued one once the rate limit allows, and
+    /// returns what is known about the current track.
+    pub fn supplement(&mut self) -> Supplement {
+        if let Some((track, receiver)) = &self.pending {
+            match receiver.try_recv() {
+                Ok(supplement) => {
+                    self.cache.insert(track.clone(), supplement.unwrap_or_default());
+                    self.pending = None;
+                }
+                Err(TryRecvError::Disconnected) => self.pending = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        let throttled = self.last_request.is_some_and(|last| last.elapsed() < MIN_REQUEST_INTERVAL);
+        if self.pending.is_none() && !throttled {
+            if let Some(track) = self.queued.take().filter(|track| !self.cache.contains_key(track)) {
+                self.start_lookup(track);
+            }
+        }
+        self.current.as_ref().and_then(|track| self.cache.get(track)).cloned().unwrap_or_default()
+    }
+
+    fn start_lookup(&mut self, track: Track) {
+        let (sender, receiver) = mpsc::channel();
+        let (artist, title) = track.clone();
+        thread::spawn(move || {
+            let supplement = fetch_recording(&artist, &title)
+                .map_err(|e| verbose!("musicbrainz lookup for {} - {} failed: {}", artist, title, e))
+                .ok();
+            let _ = sender.send(supplement);
+        });
+        self.last_request = Some(Instant::now());
+        self.pending = Some((track, receiver));
+    }
+}
+
+fn fetch_recording(artist: &str, title: &str) -> Result<Supplement, Box<dyn Error>> {
+    let query = format!("recording:{} AND artist:{}", quote(title), quote(artist));
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).user_agent(USER_AGENT).build();
+    let response: Value = agent
+        .get(API_URL)
+        .query("query", &query)
+        .query("fmt", "json")
+        .query("limit", "1")
+        .call()?
+        .into_json()?;
+    Ok(parse_recording(&response))
+}
+
+/// Quotes a value as a Lucene phrase.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn parse_recording(response: &Value) -> Supplement {
+    let recording = &response["recordings"][0];
+    let release = &recording["releases"][0];
+    let mut supplement = Supplement::new();
+    if let Some(album) = release["title"].as_str() {
+        supplement.insert("xesam:album".to_string(), album.to_string());
+    }
+    if let Some(date) = recording["first-release-date"].as_str().or_else(|| release["date"].as_str()) {
+        supplement.insert("xesam:contentCreated".to_string(), date.to_string());
+    }
+    supplement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_album_and_date_from_first_recording() {
+        let response = serde_json::json!({
+            "recordings": [{
+                "title": "Bohemian Rhapsody",
+                "first-release-date": "1975-10-31",
+                "releases": [{ "title": "A Night at the Opera", "date": "1975-11-21" }],
+            }],
+        });
+        let supplement = parse_recording(&response);
+        assert_eq!(supplement.get("xesam:album").map(String::as_str), Some("A Night at the Opera"));
+        assert_eq!(supplement.get("xesam:contentCreated").map(String::as_str), Some("1975-10-31"));
+        assert!(parse_recording(&serde_json::json!({ "recordings": [] })).is_empty());
+    }
+
+    #[test]
+    fn quotes_lucene_phrases() {
+        assert_eq!(quote(r#"Say "Hi" \o/"#), r#""Say \"Hi\" \\o/""#);
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::config::{Case, FieldRemap, FieldTransform};
+
+/// Markers that start a featuring credit in an artist name. Matching ignores ASCII case.
+const FEAT_PATTERNS: [&str; 8] = [
+    " (feat.", " [feat.", " feat.", " (ft.", " [ft.", " ft.", " (featuring", " featuring",
+];
+
+/// How `normalize_artist()` cleans up artist names for display.
+#[derive(Debug, Default)]
+pub struct ArtistNormConfig {
+    patterns: Vec<String>,
+}
+
+impl ArtistNormConfig {
+    /// With `truncate_feat` off no patterns apply. `patterns` replaces the built-in list;
+    /// `ampersand` additionally cuts at ` & `.
+    pub fn new(truncate_feat: bool, ampersand: bool, patterns: Option<Vec<String>>) -> Self {
+        if !truncate_feat {
+            return ArtistNormConfig::default();
+        }
+        let mut patterns = patterns.unwrap_or_else(|| FEAT_PATTERNS.iter().map(|p| p.to_string()).collect());
+        if ampersand {
+            patterns.push(" & ".to_string());
+        }
+        let patterns = patterns.into_iter().map(|p| p.to_ascii_lowercase()).collect();
+        ArtistNormConfig { patterns }
+    }
+}
+
+/// Cuts the artist at the earliest featuring marker, e.g. `Daft Punk feat. Pharrell Williams`
+/// becomes `Daft Punk`. A name that would be cut to nothing is returned unchanged.
+pub fn normalize_artist(artist: &str, config: &ArtistNormConfig) -> String {
+    let lowercase = artist.to_ascii_lowercase();
+    let cut = config.patterns.iter().filter_map(|pattern| lowercase.find(pattern.as_str())).min();
+    match cut {
+        Some(index) if !artist[..index].trim().is_empty() => artist[..index].trim_end().to_string(),
+        _ => artist.to_string(),
+    }
+}
+
+/// Remaster and edition suffixes, in parentheses/brackets or after ` - `.
+const REMASTER_PATTERNS: [&str; 2] = [
+    r"(?i)\s*[(\[][^()\[\]]*\b(remaster(ed)?|edition|bonus track|deluxe)\b[^()\[\]]*[)\]]\s*$",
+    r"(?i)\s+-\s+[^-]*\b(remaster(ed)?|edition|bonus track)\b[^-]*$",
+];
+
+/// How `normalize_title()` cleans up titles for display.
+#[derive(Debug, Default)]
+pub struct TitleNormConfig {
+    patterns: Vec<Regex>,
+}
+
+impl TitleNormConfig {
+    /// `truncate_remaster` enables the built-in suffix patterns; `extra` regexes apply either way.
+    pub fn new(truncate_remaster: bool, extra: &[String]) -> Result<Self, regex::Error> {
+        let builtin = REMASTER_PATTERNS.iter().filter(|_| truncate_remaster).copied();
+        let patterns = builtin.chain(extra.iter().map(String::as_str)).map(Regex::new).collect::<Result<_, _>>()?;
+        Ok(TitleNormConfig { patterns })
+    }
+}
+
+/// Removes every pattern match from the title, repeating until nothing changes so stacked
+/// suffixes like `(Deluxe Edition) [Remastered]` all go. A title that would end up empty is
+/// returned unchanged.
+pub fn normalize_title(title: &str, config: &TitleNormConfig) -> String {
+    let mut normalized = title.to_string();
+    loop {
+        let stripped = config.patterns.iter().fold(normalized.clone(), |text, pattern| pattern.replace_all(&text, "").into_owned());
+        let stripped = stripped.trim().to_string();
+        if stripped == normalized {
+            break;
+        }
+        normalized = stripped;
+    }
+    if normalized.is_empty() { title.to_string() } else { normalized }
+}
+
+/// Rewrites of the clutter YouTube and other video sites add to titles and channel names,
+/// as pattern and replacement.
+const BUILTIN_CLEANUP_RULES: [(&str, &str); 7] = [
+    // (Official Music Video), [Official Audio], (Official Lyric Video), ...
+    (r"(?i)\s*[(\[][^()\[\]]*\bofficial\b[^()\[\]]*[)\]]", ""),
+    // [HD], (Lyrics), (Audio), (4K Remaster), (Visualizer), ...
+    (r"(?i)\s*[(\[]\s*((with\s+)?lyrics?|audio|(music\s+|lyric\s+)?video|visuali[sz]er|hd|hq|4k|1080p|720p)(\s+(remaster(ed)?|version))?\s*[)\]]", ""),
+    // - Official Video
+    (r"(?i)\s+-\s+official\s+(music\s+|lyric\s+)?(video|audio)\s*$", ""),
+    // | Lyrics, | Official Video, ...
+    (r"(?i)\s*[|｜]\s*(official|lyrics?|audio|video|hd|hq)\b.*$", ""),
+    // Trailing #hashtags.
+    (r"(\s+#[\p{L}\p{N}_]+)+\s*$", ""),
+    // Auto-generated "Queen - Topic" channels.
+    (r"(?i)\s+-\s+topic$", ""),
+    // "QueenVEVO" channels.
+    (r"(?i)\s*vevo$", ""),
+];
+
+/// Regex replacements applied, in order, to the title and artist before anything else.
+#[derive(Debug, Default)]
+pub struct CleanupRules {
+    rules: Vec<(Regex, String)>,
+}
+
+impl CleanupRules {
+    /// `builtin` puts the built-in rules first; `extra` are (pattern, replacement) pairs.
+    pub fn new(builtin: bool, extra: &[(String, String)]) -> Result<Self, regex::Error> {
+        let builtin = BUILTIN_CLEANUP_RULES.iter().filter(|_| builtin).map(|(pattern, replace)| (*pattern, *replace));
+        let rules = builtin
+            .chain(extra.iter().map(|(pattern, replace)| (pattern.as_str(), replace.as_str())))
+            .map(|(pattern, replace)| Ok((Regex::new(pattern)?, replace.to_string())))
+            .collect::<Result<_, _>>()?;
+        Ok(CleanupRules { rules })
+    }
+}
+
+/// Applies each rule in turn. A rule that would leave nothing is skipped, keeping the text
+/// from before it.
+pub fn apply_cleanup(text: &str, rules: &CleanupRules) -> String {
+    rules.rules.iter().fold(text.to_string(), |text, (pattern, replace)| {
+        let replaced = pattern.replace_all(&text, replace.as_str()).trim().to_string();
+        if replaced.is_empty() { text } else { replaced }
+    })
+}
+
+/// Segments of an internet radio title that name the station rather than the track: web
+/// addresses, frequencies like `101.1`, and names with `FM` or `Radio` in them. A bare
+/// address is one ending in a dotted name under any top-level domain, which starts with a
+/// letter so that `No.5` isn't taken for one.
+const STATION_PATTERNS: [&str; 4] = [
+    r"(?i)^(https?://|www\.)|\b[\w-]+(\.[\w-]+)*\.[a-z][\w-]+(/|$)",
+    r"\b\d{2,3}[.,]\d\b",
+    r"(?i)fm\b",
+    r"(?i)\bradio\b",
+];
+
+/// The station patterns `clean_stream_title()` drops segments by.
+#[derive(Debug, Default)]
+pub struct StationPatterns {
+    patterns: Vec<Regex>,
+}
+
+impl StationPatterns {
+    /// `patterns` replaces the built-in list.
+    pub fn new(patterns: Option<&[String]>) -> Result<Self, regex::Error> {
+        let patterns = match patterns {
+            Some(patterns) => patterns.iter().map(|pattern| Regex::new(pattern)).collect::<Result<_, _>>()?,
+            None => STATION_PATTERNS.iter().map(|pattern| Regex::new(pattern)).collect::<Result<_, _>>()?,
+        };
+        Ok(StationPatterns { patterns })
+    }
+}
+
+/// Cleans up a stream title like `CoolFM 101.1 | Artist - Title | www.coolfm.de`: the
+/// `|`-separated segments that match a station pattern are dropped, and the first remaining
+/// `Artist - Title` segment is split. Segments with ` - ` in them are always kept, so
+/// `Queen - Radio Ga Ga` survives. Returns the artist, if one was split off, and the title,
+/// or `None` when every segment was station clutter.
+pub fn clean_stream_title(title: &str, stations: &StationPatterns) -> Option<(Option<String>, String)> {
+    let kept: Vec<&str> = title
+        .split('|')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .filter(|segment| segment.contains(" - ") || !stations.patterns.iter().any(|pattern| pattern.is_match(segment)))
+        .collect();
+    let split = kept.iter().find_map(|segment| segment.split_once(" - ")).map(|(artist, title)| (artist.trim(), title.trim()));
+    match split {
+        Some((artist, title)) if !artist.is_empty() && !title.is_empty() => Some((Some(artist.to_string()), title.to_string())),
+        _ if kept.is_empty() => None,
+        _ => Some((None, kept.join(" | "))),
+    }
+}
+
+/// Applies a `transforms` entry: each replacement in order, then the case change.
+pub fn transform(text: &str, transform: &FieldTransform) -> String {
+    let text = transform.replace.iter().fold(text.to_string(), |text, (from, to)| if from.is_empty() { text } else { text.replace(from, to) });
+    match transform.case {
+        Some(Case::Uppercase) => text.to_uppercase(),
+        Some(Case::Lowercase) => text.to_lowercase(),
+        Some(Case::Titlecase) => titlecase(&text),
+        None => text,
+    }
+}
+
+/// Applies a `remap` entry to metadata keyed by MPRIS name. Fields are copied from the
+/// values as read, so two can be swapped.
+pub fn remap(metadata: &mut HashMap<String, String>, remap: &FieldRemap) {
+    let copies: Vec<(&str, String)> = [("xesam:title", &remap.title_from), ("xesam:artist", &remap.artist_from), ("xesam:album", &remap.album_from)]
+        .into_iter()
+        .filter_map(|(key, from)| Some((key, metadata.get(from.as_ref()?)?.clone())))
+        .collect();
+    for (key, value) in copies {
+        metadata.insert(key.to_string(), value);
+    }
+    let split = remap.split_title.as_deref().filter(|separator| !separator.is_empty());
+    let halves = split.and_then(|separator| metadata.get("xesam:title")?.split_once(separator));
+    if let Some((artist, title)) = halves.map(|(artist, title)| (artist.trim().to_string(), title.trim().to_string())) {
+        if !artist.is_empty() && !title.is_empty() {
+            metadata.insert("xesam:artist".to_string(), artist);
+            metadata.insert("xesam:title".to_string(), title);
+        }
+    }
+}
+
+/// Upper-cases the first letter of each word, splitting on whitespace only, so `don't`
+/// becomes `Don't` and already-capitalised words like `ÆON` are left alone.
+fn titlecase(text: &str) -> String {
+    let mut titled = String::with_capacity(text.len());
+    let mut word_start = true;
+    for ch in text.chars() {
+        if word_start && !ch.is_whitespace() {
+            titled.extend(ch.to_uppercase());
+        } else {
+            titled.push(ch);
+        }
+        word_start = ch.is_whitespace();
+    }
+    titled
+}
+
+/// Joins `artists` with `separator`. With `max`, only that many are listed and the rest are
+/// counted, as in `A, B +4 more`.
+pub fn join_artists(artists: &[String], separator: &str, max: Option<usize>) -> String {
+    match max {
+        Some(max) if artists.len() > max => format!("{} +{} more", artists[..max].join(separator), artists.len() - max),
+        _ => artists.join(separator),
+    }
+}
+
+/// The year of an ISO 8601 `xesam:contentCreated`, such as `1975` from `1975-10-31T00:00:00Z`
+/// or a bare `1975`. `None` for anything not starting with a four-digit year.
+pub fn year_from_date(date: &str) -> Option<String> {
+    let date = date.trim();
+    let year = date.get(..4).filter(|year| year.chars().all(|ch| ch.is_ascii_digit()))?;
+    // More digits would be something else, like a Unix timestamp.
+    match date[4..].starts_with(|ch: char| ch.is_ascii_digit()) {
+        true => None,
+        false => Some(year.to_string()),
+    }
+}
+
+/// A display name for a track with no title, from its `xesam:url`: the file name without
+/// extension for `file://` URLs, or the URL without scheme, query and fragment for
+/// `http(s)://`. Percent escapes are decoded, with `�` for bytes that aren't valid UTF-8.
+pub fn title_from_url(url: &str) -> Option<String> {
+    let title = if let Some(path) = url.strip_prefix("file://") {
+        let path = percent_decode(path);
+        let path = std::path::Path::new(&path);
+        path.file_stem()?.to_string_lossy().into_owned()
+    } else {
+        let rest = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"))?;
+        let end = rest.find(['?', '#']).unwrap_or(rest.len());
+        percent_decode(rest[..end].trim_end_matches('/'))
+    };
+    (!title.trim().is_empty()).then_some(title)
+}
+
+/// `xesam:url` as `{url}` shows it: `file://` URLs as their decoded path, less the first of
+/// `library_roots` it starts with, and other URLs as they are.
+pub fn display_url(url: &str, library_roots: &[String]) -> String {
+    let Some(path) = url.strip_prefix("file://") else { return url.to_string() };
+    let path = percent_decode(path);
+    match library_roots.iter().find_map(|root| path.strip_prefix(root.as_str())) {
+        Some(rest) if !rest.is_empty() => rest.to_string(),
+        _ => path,
+    }
+}
+
+/// A short name for where a track comes from, for `show-url`: the host of an http(s) URL,
+/// such as `stream.radio.example`, or the file name of a `file://` one.
+pub fn url_label(url: &str) -> Option<String> {
+    let url = url::Url::parse(url).ok()?;
+    let label = match url.scheme() {
+        "http" | "https" => url.host_str()?.to_string(),
+        "file" => percent_decode(url.path_segments()?.next_back()?),
+        _ => return None,
+    };
+    (!label.is_empty()).then_some(label)
+}
+
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%').then(|| tail.get(..2)).flatten().and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_featuring_credits() {
+        let config = ArtistNormConfig::new(true, false, None);
+        assert_eq!(normalize_artist("Daft Punk feat. Pharrell Williams", &config), "Daft Punk");
+        assert_eq!(normalize_artist("The Weeknd (ft. Doja Cat)", &config), "The Weeknd");
+        assert_eq!(normalize_artist("Mark Ronson Featuring Bruno Mars", &config), "Mark Ronson");
+        assert_eq!(normalize_artist("Simon & Garfunkel", &config), "Simon & Garfunkel");
+        assert_eq!(normalize_artist("Björk [FT. Someone]", &config), "Björk");
+    }
+
+    #[test]
+    fn ampersand_and_disabled_modes() {
+        let ampersand = ArtistNormConfig::new(true, true, None);
+        assert_eq!(normalize_artist("Simon & Garfunkel", &ampersand), "Simon");
+        let disabled = ArtistNormConfig::new(false, true, None);
+        assert_eq!(normalize_artist("Daft Punk feat. Pharrell Williams", &disabled), "Daft Punk feat. Pharrell Williams");
+        let custom = ArtistNormConfig::new(true, false, Some(vec![" x ".to_string()]));
+        assert_eq!(normalize_artist("Skrillex x Diplo feat. Justin", &custom), "Skrillex");
+    }
+
+    #[test]
+    fn strips_remaster_suffixes() {
+        let config = TitleNormConfig::new(true, &[]).unwrap();
+        assert_eq!(normalize_title("Bohemian Rhapsody (2011 Remaster)", &config), "Bohemian Rhapsody");
+        assert_eq!(normalize_title("Hotel California - 2013 Remaster", &config), "Hotel California");
+        assert_eq!(normalize_title("Let It Be - Remastered 2009", &config), "Let It Be");
+        assert_eq!(normalize_title("Kids [Deluxe Edition] (Bonus Track)", &config), "Kids");
+        assert_eq!(normalize_title("Song - Live", &config), "Song - Live");
+        assert_eq!(normalize_title("(Remastered)", &config), "(Remastered)");
+    }
+
+    #[test]
+    fn applies_custom_title_patterns() {
+        let config = TitleNormConfig::new(false, &[r"\s*\(Live\)".to_string()]).unwrap();
+        assert_eq!(normalize_title("Song (Live)", &config), "Song");
+        assert_eq!(normalize_title("Song (2011 Remaster)", &config), "Song (2011 Remaster)");
+        assert!(TitleNormConfig::new(false, &["(".to_string()]).is_err());
+    }
+
+    #[test]
+    fn builtin_cleanup_handles_real_world_titles() {
+        let rules = CleanupRules::new(true, &[]).unwrap();
+        let corpus = [
+            ("Queen – Bohemian Rhapsody (Official Video Remastered)", "Queen – Bohemian Rhapsody"),
+            ("Rick Astley - Never Gonna Give You Up (Official Music Video)", "Rick Astley - Never Gonna Give You Up"),
+            ("Billie Eilish - bad guy [Official Audio]", "Billie Eilish - bad guy"),
+            ("Lewis Capaldi - Someone You Loved (Lyrics)", "Lewis Capaldi - Someone You Loved"),
+            ("Darude - Sandstorm [HD]", "Darude - Sandstorm"),
+            ("a-ha - Take On Me (4K Remastered)", "a-ha - Take On Me"),
+            ("Tame Impala - Borderline (Official Visualizer)", "Tame Impala - Borderline"),
+            ("The Weeknd - Blinding Lights | Lyrics", "The Weeknd - Blinding Lights"),
+            ("Daft Punk - Get Lucky - Official Video", "Daft Punk - Get Lucky"),
+            ("Lofi beats to study to #lofi #chill", "Lofi beats to study to"),
+            ("Queen - Topic", "Queen"),
+            ("TaylorSwiftVEVO", "TaylorSwift"),
+            ("Video Killed the Radio Star", "Video Killed the Radio Star"),
+            ("Bohemian Rhapsody (Live Aid 1985)", "Bohemian Rhapsody (Live Aid 1985)"),
+        ];
+        for (ugly, clean) in corpus {
+            assert_eq!(apply_cleanup(ugly, &rules), clean, "{}", ugly);
+        }
+    }
+
+    #[test]
+    fn cleanup_rules_apply_in_order_and_never_empty_the_text() {
+        let extra = [("Foo".to_string(), "Bar".to_string()), ("Bar".to_string(), "Baz".to_string()), (".*".to_string(), String::new())];
+        let rules = CleanupRules::new(false, &extra).unwrap();
+        assert_eq!(apply_cleanup("Foo (Official Video)", &rules), "Baz (Official Video)");
+        assert_eq!(apply_cleanup("(Official Video)", &CleanupRules::new(true, &[]).unwrap()), "(Official Video)");
+        assert!(CleanupRules::new(false, &[("(".to_string(), String::new())]).is_err());
+    }
+
+    #[test]
+    fn derives_titles_from_urls() {
+        assert_eq!(title_from_url("file:///home/me/Music/01%20Song%20Name.flac").as_deref(), Some("01 Song Name"));
+        assert_eq!(title_from_url("file:///tmp/%E6%9D%B1%E4%BA%AC.mp3").as_deref(), Some("東京"));
+        assert_eq!(title_from_url("https://radio.example.com/live/stream.mp3?token=abc#x").as_deref(), Some("radio.example.com/live/stream.mp3"));
+        assert_eq!(title_from_url("http://example.com/").as_deref(), Some("example.com"));
+        // Bytes that aren't valid UTF-8 once decoded become U+FFFD; a stray % is left alone.
+        assert_eq!(title_from_url("file:///tmp/bad%FF%FE.ogg").as_deref(), Some("bad\u{fffd}\u{fffd}"));
+        assert_eq!(title_from_url("file:///tmp/100%.ogg").as_deref(), Some("100%"));
+        assert_eq!(title_from_url("spotify:track:4u7EnebtmKWzUH433cf5Qv"), None);
+        assert_eq!(title_from_url("file:///"), None);
+    }
+
+    #[test]
+    fn labels_urls_by_host_or_file_name() {
+        assert_eq!(url_label("https://stream.radio.example:8000/live.mp3?token=x").as_deref(), Some("stream.radio.example"));
+        assert_eq!(url_label("http://192.0.2.7/stream").as_deref(), Some("192.0.2.7"));
+        assert_eq!(url_label("file:///home/me/Music/Caf%C3%A9.mp3").as_deref(), Some("Café.mp3"));
+        assert_eq!(url_label("file:///home/me/Music/"), None);
+        assert_eq!(url_label("spotify:track:4u7EnebtmKWzUH433cf5Qv"), None);
+        assert_eq!(url_label("not a url"), None);
+    }
+
+    #[test]
+    fn shows_file_urls_as_paths_below_the_library_root() {
+        let roots = ["/home/me/Music/".to_string()];
+        assert_eq!(display_url("file:///home/me/M%C3%BAsica/Caf%C3%A9.mp3", &roots), "/home/me/Música/Café.mp3");
+        assert_eq!(display_url("file:///home/me/Music/Queen/100%25%20Queen.flac", &roots), "Queen/100% Queen.flac");
+        assert_eq!(display_url("file:///home/me/Music/", &roots), "/home/me/Music/");
+        assert_eq!(display_url("file:///tmp/%FFx.ogg", &roots), "/tmp/\u{fffd}x.ogg");
+        assert_eq!(display_url("https://example.com/a%20b", &roots), "https://example.com/a%20b");
+    }
+
+    #[test]
+    fn joins_artists_up_to_a_maximum() {
+        let artists: Vec<String> = ["Janowitz", "Ludwig", "Kmentt", "Berry", "Wiener Singverein", "Karajan"].map(String::from).to_vec();
+        assert_eq!(join_artists(&artists[..2], ", ", None), "Janowitz, Ludwig");
+        assert_eq!(join_artists(&artists, ", ", Some(2)), "Janowitz, Ludwig +4 more");
+        assert_eq!(join_artists(&artists[..2], " & ", Some(2)), "Janowitz & Ludwig");
+    }
+
+    #[test]
+    fn reads_years_from_dates() {
+        assert_eq!(year_from_date("1975-10-31T00:00:00Z").as_deref(), Some("1975"));
+        assert_eq!(year_from_date("1975-10-31").as_deref(), Some("1975"));
+        assert_eq!(year_from_date(" 1975 ").as_deref(), Some("1975"));
+        assert_eq!(year_from_date("1700000000"), None);
+        assert_eq!(year_from_date("unknown"), None);
+        assert_eq!(year_from_date("75"), None);
+        assert_eq!(year_from_date("١٩٧٥"), None);
+        assert_eq!(year_from_date(""), None);
+    }
+
+    #[test]
+    fn strips_station_clutter_from_stream_titles() {
+        let stations = StationPatterns::new(None).unwrap();
+        let clean = |title| clean_stream_title(title, &stations);
+        let track = |artist: &str, title: &str| Some((Some(artist.to_string()), title.to_string()));
+        assert_eq!(clean("CoolFM 101.1 | Daft Punk - Around the World | www.coolfm.de"), track("Daft Punk", "Around the World"));
+        assert_eq!(clean("Jazz24 | Miles Davis - So What | 88.5 KNKX"), track("Miles Davis", "So What"));
+        assert_eq!(clean("Queen - Radio Ga Ga | https://radio.example/listen"), track("Queen", "Radio Ga Ga"));
+        assert_eq!(clean("Kiss FM | The Breakfast Show"), Some((None, "The Breakfast Show".to_string())));
+        assert_eq!(clean("Kiss FM | kissfm.co.uk"), None);
+        assert_eq!(clean("Morning Show | radio-eins.de/live"), Some((None, "Morning Show".to_string())));
+        assert_eq!(clean("Mambo No.5 | deutschlandfunk.de"), Some((None, "Mambo No.5".to_string())));
+        let custom = StationPatterns::new(Some(&["^Jazz24$".to_string()])).unwrap();
+        assert_eq!(clean_stream_title("Jazz24 | 88.5 KNKX", &custom), Some((None, "88.5 KNKX".to_string())));
+        assert!(StationPatterns::new(Some(&["(".to_string()])).is_err());
+    }
+
+    #[test]
+    fn remaps_misused_fields() {
+        let fields = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
+        let radio = FieldRemap { split_title: Some(" - ".to_string()), ..FieldRemap::default() };
+        let mut metadata = fields(&[("xesam:title", "Daft Punk - One More Time - Radio Edit"), ("xesam:artist", "FIP")]);
+        remap(&mut metadata, &radio);
+        assert_eq!(metadata, fields(&[("xesam:title", "One More Time - Radio Edit"), ("xesam:artist", "Daft Punk")]));
+        let mut metadata = fields(&[("xesam:title", "Station ID"), ("xesam:artist", "FIP")]);
+        remap(&mut metadata, &radio);
+        assert_eq!(metadata["xesam:artist"], "FIP");
+
+        let audiobook = FieldRemap { title_from: Some("xesam:album".to_string()), album_from: Some("xesam:title".to_string()), ..FieldRemap::default() };
+        let mut metadata = fields(&[("xesam:title", "Chapter 3"), ("xesam:album", "The Hobbit")]);
+        remap(&mut metadata, &audiobook);
+        assert_eq!(metadata, fields(&[("xesam:title", "The Hobbit"), ("xesam:album", "Chapter 3")]));
+    }
+
+    #[test]
+    fn transforms_replace_then_change_case() {
+        let underscores = FieldTransform { case: Some(Case::Titlecase), replace: vec![("_".to_string(), " ".to_string())] };
+        assert_eq!(transform("dont_stop_me_now", &underscores), "Dont Stop Me Now");
+        let title = FieldTransform { case: Some(Case::Titlecase), replace: Vec::new() };
+        assert_eq!(transform("don't stop ÆON élan  ab", &title), "Don't Stop ÆON Élan  Ab");
+        let upper = FieldTransform { case: Some(Case::Uppercase), replace: Vec::new() };
+        assert_eq!(transform("straße", &upper), "STRASSE");
+        let lower = FieldTransform { case: Some(Case::Lowercase), replace: vec![(String::new(), "x".to_string())] };
+        assert_eq!(transform("ÆON", &lower), "æon");
+    }
+}
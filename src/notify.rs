@@ -0,0 +1,243 @@
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[cfg(not(feature = "zbus"))]
+use dbus::blocking::Connection;
+#[cfg(feature = "zbus")]
+use zbus::blocking::Connection;
+
+use crate::state;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const APP_NAME: &str = "polybar-now-playing";
+/// The bus name of the notification daemon, which is also its interface.
+const NOTIFICATIONS_BUS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+/// Lets the notification daemon pick how long notifications stay up.
+const DEFAULT_EXPIRY: i32 = -1;
+/// Cover art larger than this is not worth downloading for an icon.
+const MAX_ART_SIZE: u64 = 10 * 1024 * 1024;
+/// Cached art not touched for this long is removed.
+const CACHE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A track change to show a notification for.
+struct TrackNotification {
+    title: String,
+    artist: String,
+    art_url: Option<String>,
+}
+
+/// Shows track-change notifications from a background thread. With `download_art`, remote
+/// art URLs are fetched into a cache first, since notification daemons only show local
+/// icons, and a slow download never holds up the display.
+pub struct Notifier {
+    sender: Sender<TrackNotification>,
+}
+
+impl Notifier {
+    pub fn spawn(download_art: bool) -> Self {
+        let (sender, receiver) = mpsc::channel::<TrackNotification>();
+        thread::spawn(move || {
+            let mut client = match NotificationClient::new() {
+                Ok(client) => client,
+                Err(e) => return verbose!("notifications disabled: {}", e),
+            };
+            for track in receiver {
+                let icon = track.art_url.and_then(|url| resolve_art(&url, download_art));
+                let icon = icon.map_or(String::new(), |icon| icon.display().to_string());
+                if let Err(e) = client.notify(&track.title, &track.artist, &icon, DEFAULT_EXPIRY) {
+                    verbose!("could not show notification: {}", e);
+                }
+            }
+        });
+        Notifier { sender }
+    }
+
+    pub fn track_changed(&self, title: String, artist: String, art_url: Option<String>) {
+        let _ = self.sender.send(TrackNotification { title, artist, art_url });
+    }
+}
+
+/// Talks to any freedesktop notification daemon (dunst, mako, GNOME, ...) over
+/// `org.freedesktop.Notifications`, closing the previous notification before showing the
+/// next so they don't stack up.
+pub struct NotificationClient {
+    connection: Connection,
+    last_id: Option<u32>,
+}
+
+impl NotificationClient {
+    /// Shows a notification and returns the id the daemon gave it. `icon` is a path or
+    /// icon name, or empty for none; `timeout_ms` of -1 leaves the timeout to the daemon.
+    pub fn notify(&mut self, summary: &str, body: &str, icon: &str, timeout_ms: i32) -> Result<u32, Box<dyn Error>> {
+        if let Some(id) = self.last_id.take() {
+            // It may well have expired already, which is not worth reporting.
+            let _ = self.close(id);
+        }
+        let id = self.show(summary, body, icon, timeout_ms)?;
+        self.last_id = Some(id);
+        Ok(id)
+    }
+}
+
+#[cfg(not(feature = "zbus"))]
+impl NotificationClient {
+    pub fn new() -> Result<Self, dbus::Error> {
+        Ok(NotificationClient { connection: Connection::new_session()?, last_id: None })
+    }
+
+    fn proxy(&self) -> dbus::blocking::Proxy<'_, &Connection> {
+        self.connection.with_proxy(NOTIFICATIONS_BUS_NAME, NOTIFICATIONS_PATH, TIMEOUT)
+    }
+
+    fn close(&self, id: u32) -> Result<(), dbus::Error> {
+        self.proxy().method_call(NOTIFICATIONS_BUS_NAME, "CloseNotification", (id,))
+    }
+
+    fn show(&self, summary: &str, body: &str, icon: &str, timeout_ms: i32) -> Result<u32, dbus::Error> {
+        let actions: Vec<&str> = Vec::new();
+        let hints = dbus::arg::PropMap::new();
+        let (id,): (u32,) =
+            self.proxy().method_call(NOTIFICATIONS_BUS_NAME, "Notify", (APP_NAME, 0u32, icon, summary, body, actions, hints, timeout_ms))?;
+        Ok(id)
+    }
+}
+
+#[cfg(feature = "zbus")]
+impl NotificationClient {
+    pub fn new() -> Result<Self, zbus::Error> {
+        let connection = zbus::blocking::connection::Builder::session()?.method_timeout(TIMEOUT).build()?;
+        Ok(NotificationClient { connection, last_id: None })
+    }
+
+    fn call<B>(&self, method: &str, body: &B) -> Result<zbus::message::Message, zbus::Error>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        self.connection.call_method(Some(NOTIFICATIONS_BUS_NAME), NOTIFICATIONS_PATH, Some(NOTIFICATIONS_BUS_NAME), method, body)
+    }
+
+    fn close(&self, id: u32) -> Result<(), zbus::Error> {
+        self.call("CloseNotification", &(id,)).map(drop)
+    }
+
+    fn show(&self, summary: &str, body: &str, icon: &str, timeout_ms: i32) -> Result<u32, zbus::Error> {
+        let actions: Vec<&str> = Vec::new();
+        let hints: std::collections::HashMap<&str, zbus::zvariant::Value> = Default::default();
+        let reply = self.call("Notify", &(APP_NAME, 0u32, icon, summary, body, actions, hints, timeout_ms))?;
+        reply.body().deserialize()
+    }
+}
+
+/// A local path for the art at `url`: `file://` URLs as is, remote ones from the cache.
+fn resolve_art(url: &str, download: bool) -> Option<PathBuf> {
+    if url.starts_with("file://") {
+        // Decodes the percent escapes players put in paths with spaces or non-ASCII names.
+        return url::Url::parse(url).ok()?.to_file_path().ok();
+    }
+    if !download || !(url.starts_with("http://") || url.starts_with("https://")) {
+        return None;
+    }
+    let dir = cache_dir();
+    let path = dir.join(cache_name(url));
+    if path.is_file() {
+        return Some(path);
+    }
+    if !is_online() {
+        verbose!("offline, not downloading {}", url);
+        return None;
+    }
+    clean_cache(&dir);
+    download_art(url, &dir, &path).map_err(|e| verbose!("could not download {}: {}", url, e)).ok()?;
+    Some(path)
+}
+
+fn cache_dir() -> PathBuf {
+    state::runtime_dir().join("polybar-now-playing-art")
+}
+
+fn cache_name(url: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn download_art(url: &str, dir: &Path, path: &Path) -> Result<(), Box<dyn Error>> {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let art = read_art(agent.get(url).call()?.into_reader())?;
+    fs::create_dir_all(dir)?;
+    // Written under a temporary name so a half-written file is never picked up as cached.
+    let partial = path.with_extension("part");
+    fs::write(&partial, art)?;
+    fs::rename(partial, path)?;
+    Ok(())
+}
+
+/// Reads art of at most `MAX_ART_SIZE`, failing on larger art rather than caching a cut-off
+/// image.
+fn read_art(reader: impl Read) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut art = Vec::new();
+    reader.take(MAX_ART_SIZE + 1).read_to_end(&mut art)?;
+    if art.len() as u64 > MAX_ART_SIZE {
+        return Err(format!("art is larger than {} bytes", MAX_ART_SIZE).into());
+    }
+    Ok(art)
+}
+
+fn clean_cache(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let modified = entry.metadata().and_then(|metadata| metadata.modified());
+        if modified.is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() > CACHE_MAX_AGE) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Whether the kernel has a default route, as a cheap check for being online.
+fn is_online() -> bool {
+    fs::read_to_string("/proc/net/route").map_or(true, |routes| has_default_route(&routes))
+}
+
+fn has_default_route(routes: &str) -> bool {
+    routes.lines().skip(1).any(|line| line.split_whitespace().nth(1) == Some("00000000"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_local_art_and_skips_remote_without_download() {
+        assert_eq!(resolve_art("file:///tmp/cover.png", false), Some(PathBuf::from("/tmp/cover.png")));
+        assert_eq!(resolve_art("file:///home/me/M%C3%BAsica/cover%201.jpg", false), Some(PathBuf::from("/home/me/Música/cover 1.jpg")));
+        assert_eq!(resolve_art("https://i.scdn.co/image/ab67", false), None);
+        assert_eq!(resolve_art("data:image/png;base64,AAAA", true), None);
+        assert_eq!(cache_name("https://a/1"), cache_name("https://a/1"));
+        assert_ne!(cache_name("https://a/1"), cache_name("https://a/2"));
+    }
+
+    #[test]
+    fn refuses_art_over_the_size_limit() {
+        let limit = MAX_ART_SIZE as usize;
+        assert_eq!(read_art(&vec![1u8; limit][..]).unwrap().len(), limit);
+        assert!(read_art(&vec![1u8; limit + 1][..]).is_err());
+    }
+
+    #[test]
+    fn detects_default_route() {
+        let header = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n";
+        let lan = "wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\n";
+        let default = "wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\n";
+        assert!(has_default_route(&format!("{}{}{}", header, lan, default)));
+        assert!(!has_default_route(&format!("{}{}", header, lan)));
+    }
+}
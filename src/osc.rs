@@ -0,0 +1,102 @@
+use std::cell::Cell;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use rosc::{OscMessage, OscPacket, OscType};
+
+/// Sends `/nowplaying/*` OSC messages over UDP to one target, for controllers such as
+/// TouchOSC. The socket is bound once. Nobody may be listening, so the first failed send is
+/// a warning and later ones are only logged.
+pub struct OscSender {
+    socket: UdpSocket,
+    target: String,
+    position_interval: Option<Duration>,
+    last_position: Option<Instant>,
+    warned: Cell<bool>,
+}
+
+impl OscSender {
+    /// `target` is `host:port`. With `position_interval`, `/nowplaying/position` is also
+    /// sent between track changes, at most that often.
+    pub fn new(target: &str, position_interval: Option<Duration>) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(target)?;
+        Ok(OscSender { socket, target: target.to_string(), position_interval, last_position: None, warned: Cell::new(false) })
+    }
+
+    /// Sends title, artist, status and, if known, the position in seconds.
+    pub fn send_track(&mut self, title: &str, artist: &str, status: &str, position: Option<f32>) {
+        self.send("/nowplaying/title", OscType::String(title.to_string()));
+        self.send("/nowplaying/artist", OscType::String(artist.to_string()));
+        self.send("/nowplaying/status", OscType::String(status.to_string()));
+        if let Some(position) = position {
+            self.send("/nowplaying/position", OscType::Float(position));
+            self.last_position = Some(Instant::now());
+        }
+    }
+
+    /// Whether `position-interval` has passed since the position was last sent.
+    pub fn position_due(&self) -> bool {
+        self.position_interval.is_some_and(|interval| self.last_position.is_none_or(|last| last.elapsed() >= interval))
+    }
+
+    pub fn send_position(&mut self, position: f32) {
+        self.send("/nowplaying/position", OscType::Float(position));
+        self.last_position = Some(Instant::now());
+    }
+
+    fn send(&self, address: &str, arg: OscType) {
+        let sent = message(address, arg).and_then(|packet| self.socket.send(&packet));
+        if let Err(e) = sent {
+            if self.warned.replace(true) {
+                verbose!("osc: could not send {} to {}: {}", address, self.target, e);
+            } else {
+                warn!("osc: could not send {} to {}: {}", address, self.target, e);
+            }
+        }
+    }
+}
+
+/// Encodes an OSC message with a single argument.
+fn message(address: &str, arg: OscType) -> io::Result<Vec<u8>> {
+    let packet = OscPacket::Message(OscMessage { addr: address.to_string(), args: vec![arg] });
+    rosc::encoder::encode(&packet).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_string_and_float_messages() {
+        assert_eq!(message("/a", OscType::String("hey!".to_string())).unwrap(), b"/a\0\0,s\0\0hey!\0\0\0\0");
+        let position = message("/pos", OscType::Float(1.5)).unwrap();
+        assert_eq!(position, [&b"/pos\0\0\0\0,f\0\0"[..], &1.5f32.to_be_bytes()].concat());
+    }
+
+    #[test]
+    fn sends_track_messages_over_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut sender = OscSender::new(&receiver.local_addr().unwrap().to_string(), None).unwrap();
+        sender.send_track("Song", "Band", "Playing", None);
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], message("/nowplaying/title", OscType::String("Song".to_string())).unwrap().as_slice());
+        assert!(!sender.position_due());
+    }
+
+    #[test]
+    fn warns_about_a_failing_target_once() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = OscSender::new(&receiver.local_addr().unwrap().to_string(), None).unwrap();
+        drop(receiver);
+        // The port is closed, so the ICMP error fails a later send on the connected socket.
+        for _ in 0..3 {
+            sender.send("/nowplaying/title", OscType::String("Song".to_string()));
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(sender.warned.get());
+    }
+}
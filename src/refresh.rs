@@ -0,0 +1,143 @@
+use std::io;
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+/// The signalfd SIGUSR1 is read from once `watch_sigusr1()` has blocked it, or -1 before.
+static SIGNAL_FD: AtomicI32 = AtomicI32::new(-1);
+/// The eventfd `wake()` writes to and `sleep()` polls, or -1 before `watch_sigusr1()`.
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Makes SIGUSR1 cut the wait for the next update short, so a keybind that changes the track
+/// can follow up with `pkill -USR1 -f polybar-now-playing` and see the bar update at once.
+/// The signal is blocked and read from a signalfd that `sleep()` polls, so there is no
+/// handler to race with. Threads inherit the mask of the thread that starts them, and one
+/// that didn't block SIGUSR1 would take it and end the process, so this has to be called
+/// before any thread is spawned. It also sets up the eventfd `wake()` uses.
+pub fn watch_sigusr1() -> io::Result<()> {
+    if SIGNAL_FD.load(Ordering::Relaxed) >= 0 {
+        return Ok(());
+    }
+    // SAFETY: `set` is initialised by sigemptyset before use, and the calls only read it.
+    let fd = unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGUSR1);
+        let error = libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        if error != 0 {
+            return Err(io::Error::from_raw_os_error(error));
+        }
+        libc::signalfd(-1, &set, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC)
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    SIGNAL_FD.store(fd, Ordering::Relaxed);
+    // SAFETY: eventfd has no memory safety preconditions.
+    let wake_fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+    if wake_fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    WAKE_FD.store(wake_fd, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Asks for an update from another thread. The eventfd stays readable until `sleep()` reads
+/// it, so a wake that comes while the main loop is busy ends its next sleep instead of being
+/// lost. Does nothing before `watch_sigusr1()`.
+pub fn wake() {
+    let fd = WAKE_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let one = 1_u64.to_ne_bytes();
+        // SAFETY: `one` is eight readable bytes, the size an eventfd write takes.
+        unsafe { libc::write(fd, one.as_ptr().cast(), one.len()) };
+    }
+}
+
+/// Sleeps for `duration`, or until SIGUSR1 or `wake()` asks for a refresh or `fd` becomes
+/// readable. One that came since the last call, while the main loop was busy, ends it at
+/// once, as both stay pending until read. Returns whether it was cut short.
+pub fn sleep(duration: Duration, fd: Option<RawFd>) -> bool {
+    let deadline = Instant::now() + duration;
+    let (signal_fd, wake_fd) = (SIGNAL_FD.load(Ordering::Relaxed), WAKE_FD.load(Ordering::Relaxed));
+    let mut fds: Vec<libc::pollfd> = [signal_fd, wake_fd]
+        .into_iter()
+        .filter(|&fd| fd >= 0)
+        .chain(fd)
+        .map(|fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 })
+        .collect();
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now()).as_millis().min(i32::MAX as u128) as libc::c_int;
+        // SAFETY: `fds` is a live array of `fds.len()` pollfds.
+        match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } {
+            // Timed out, possibly a little early as the timeout is in whole milliseconds, or
+            // interrupted by another signal.
+            0 | -1 if Instant::now() < deadline => continue,
+            0 | -1 => return false,
+            _ => break,
+        }
+    }
+    if signal_fd >= 0 {
+        drain(signal_fd, std::mem::size_of::<libc::signalfd_siginfo>());
+        drain(wake_fd, std::mem::size_of::<u64>());
+    }
+    true
+}
+
+/// Reads what is pending off a signalfd or eventfd in reads of `size` bytes, so the next
+/// `sleep()` waits again.
+fn drain(fd: RawFd, size: usize) {
+    let mut buf = std::mem::MaybeUninit::<libc::signalfd_siginfo>::uninit();
+    // SAFETY: `buf` has room for a signalfd_siginfo, the larger of the two reads; the fd is
+    // non-blocking, so this stops once nothing is left.
+    while unsafe { libc::read(fd, buf.as_mut_ptr().cast(), size) } == size as isize {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn sigusr1_or_a_wake_ends_the_sleep_early() {
+        // The test harness runs other threads that don't block SIGUSR1, and a signal sent to
+        // the process could land on one of those, so this runs in a single-threaded child.
+        // SAFETY: the child only makes the calls below and then exits without unwinding.
+        match unsafe { libc::fork() } {
+            0 => {
+                let ok = watch_sigusr1().is_ok() && {
+                    let start = Instant::now();
+                    let slept = !sleep(Duration::from_millis(20), None) && start.elapsed() >= Duration::from_millis(20);
+                    // SAFETY: kill has no memory safety preconditions.
+                    unsafe { libc::kill(libc::getpid(), libc::SIGUSR1) };
+                    let start = Instant::now();
+                    let signalled = slept && sleep(Duration::from_secs(30), None) && start.elapsed() < Duration::from_secs(5);
+                    // A wake from another thread that comes before the sleep isn't lost.
+                    std::thread::spawn(wake).join().unwrap();
+                    let start = Instant::now();
+                    signalled && sleep(Duration::from_secs(30), None) && start.elapsed() < Duration::from_secs(5)
+                } && !sleep(Duration::from_millis(10), None);
+                // SAFETY: _exit skips the harness's atexit handlers, which the child mustn't run.
+                unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+            }
+            -1 => panic!("fork failed: {}", io::Error::last_os_error()),
+            child => {
+                let mut status = 0;
+                // SAFETY: `status` is a live int for waitpid to fill in.
+                assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+                assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0, "child failed with status {}", status);
+            }
+        }
+    }
+
+    #[test]
+    fn a_readable_fd_ends_the_sleep_early() {
+        let (mut writer, reader) = std::os::unix::net::UnixStream::pair().unwrap();
+        assert!(!sleep(Duration::from_millis(10), Some(reader.as_raw_fd())));
+        writer.write_all(b"x").unwrap();
+        let start = Instant::now();
+        assert!(sleep(Duration::from_secs(30), Some(reader.as_raw_fd())));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,118 @@
+use unicode_general_category::{get_general_category, GeneralCategory};
+use unicode_normalization::UnicodeNormalization;
+
+/// Runs a metadata string read from D-Bus through every cleanup stage, in order. HTML
+/// entities are decoded only with `decode_html`.
+pub fn clean_metadata(s: &str, decode_html: bool) -> String {
+    let s = strip_ansi_escapes(s);
+    let s = if decode_html { html_decode(&s) } else { s };
+    sanitize_for_display(&normalize_metadata(&s))
+}
+
+/// Composes metadata to NFC so visually identical strings (e.g. `a\u{301}` and `\u{e1}`)
+/// measure and compare the same.
+pub fn normalize_metadata(s: &str) -> String {
+    s.nfc().collect()
+}
+
+/// Removes ANSI/VT100 escape sequences that terminal players (cmus, MOC) leave in their tags.
+pub fn strip_ansi_escapes(s: &str) -> String {
+    strip_ansi_escapes::strip_str(s)
+}
+
+const HTML_ENTITIES: [(&str, char); 6] = [
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+    ("nbsp", '\u{a0}'),
+];
+
+/// Decodes the common named HTML entities and `&#N;`/`&#xN;` references that browser players
+/// pass through from page titles. Decoding is a single pass, so `&amp;amp;` becomes `&amp;`;
+/// anything that isn't a recognised entity is kept verbatim.
+pub fn html_decode(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].find(';').map(|end| (&rest[1..end + 1], end + 2));
+        match entity.and_then(|(name, len)| decode_entity(name).map(|ch| (ch, len))) {
+            Some((ch, len)) => {
+                decoded.push(ch);
+                rest = &rest[len..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix('x').or_else(|| number.strip_prefix('X')) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code).filter(|&ch| ch != '\0');
+    }
+    HTML_ENTITIES.iter().find(|(entity, _)| *entity == name).map(|&(_, ch)| ch)
+}
+
+/// Makes a string safe to print on a single polybar line: newlines, tabs and other
+/// whitespace become single spaces (runs collapse, ends are trimmed), and characters that
+/// take no space but still confuse width math are dropped: other control characters, and
+/// the Format (zero-width space, BOM, soft hyphen, word joiner, ...) and Surrogate
+/// categories.
+pub fn sanitize_for_display(s: &str) -> String {
+    let visible: String = s
+        .chars()
+        .filter(|&ch| ch.is_whitespace() || !ch.is_control())
+        .filter(|&ch| !matches!(get_general_category(ch), GeneralCategory::Format | GeneralCategory::Surrogate))
+        .collect();
+    visible.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_color_codes() {
+        assert_eq!(strip_ansi_escapes("\x1b[32mGreen Song\x1b[0m"), "Green Song");
+    }
+
+    #[test]
+    fn decodes_html_entities() {
+        assert_eq!(html_decode("Tom &amp; Jerry"), "Tom & Jerry");
+        assert_eq!(html_decode("Don&#39;t &quot;Stop&quot;"), "Don't \"Stop\"");
+        assert_eq!(html_decode("&lt;3 &#x263A;&nbsp;&apos;"), "<3 \u{263a}\u{a0}'");
+        assert_eq!(html_decode("&amp;amp;"), "&amp;");
+        assert_eq!(html_decode("R&B & Soul &bogus; &#xZZ; &"), "R&B & Soul &bogus; &#xZZ; &");
+    }
+
+    #[test]
+    fn html_decoding_can_be_turned_off() {
+        assert_eq!(clean_metadata("Rock &amp; Roll &notanentity;", true), "Rock & Roll &notanentity;");
+        assert_eq!(clean_metadata("Rock &amp; Roll", false), "Rock &amp; Roll");
+    }
+
+    #[test]
+    fn drops_non_printing_characters() {
+        assert_eq!(sanitize_for_display("Zero\u{200b}Width"), "ZeroWidth");
+        assert_eq!(sanitize_for_display("\u{feff}Title\u{ad}\u{2060}"), "Title");
+    }
+
+    #[test]
+    fn flattens_whitespace_and_control_characters() {
+        assert_eq!(sanitize_for_display("Line one\r\nLine two"), "Line one Line two");
+        assert_eq!(sanitize_for_display("a\u{7}b\t\tc\n"), "ab c");
+        assert_eq!(sanitize_for_display("  \t\r\n "), "");
+    }
+}
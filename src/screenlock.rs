@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+#[cfg(not(feature = "zbus"))]
+use std::time::Duration;
+
+#[cfg(not(feature = "zbus"))]
+use dbus::{blocking::Connection, message::MatchRule};
+#[cfg(feature = "zbus")]
+use zbus::{blocking::fdo::DBusProxy, blocking::Connection, blocking::MessageIterator, message::Type, MatchRule};
+
+/// Interfaces whose `ActiveChanged(bool)` signal reports the screen locking and unlocking.
+const SCREENSAVER_INTERFACES: [&str; 2] = ["org.freedesktop.ScreenSaver", "org.gnome.ScreenSaver"];
+
+/// Follows the screen saver's `ActiveChanged` signals on a background thread, for
+/// `screen-lock-pause`.
+pub struct ScreenLockMonitor {
+    locked: Arc<AtomicBool>,
+}
+
+impl ScreenLockMonitor {
+    pub fn spawn() -> Self {
+        let locked = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&locked);
+        thread::spawn(move || {
+            if let Err(e) = watch(&flag) {
+                verbose!("screen lock detection disabled: {}", e);
+            }
+        });
+        ScreenLockMonitor { locked }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(not(feature = "zbus"))]
+fn watch(locked: &Arc<AtomicBool>) -> Result<(), dbus::Error> {
+    let connection = Connection::new_session()?;
+    for interface in SCREENSAVER_INTERFACES {
+        let flag = Arc::clone(locked);
+        connection.add_match(MatchRule::new_signal(interface, "ActiveChanged"), move |(active,): (bool,), _, _| {
+            flag.store(active, Ordering::Relaxed);
+            true
+        })?;
+    }
+    loop {
+        connection.process(Duration::from_secs(60))?;
+    }
+}
+
+#[cfg(feature = "zbus")]
+fn watch(locked: &Arc<AtomicBool>) -> Result<(), zbus::Error> {
+    let connection = Connection::session()?;
+    let dbus = DBusProxy::new(&connection)?;
+    let mut rules = Vec::new();
+    for interface in SCREENSAVER_INTERFACES {
+        let rule = MatchRule::builder().msg_type(Type::Signal).interface(interface)?.member("ActiveChanged")?.build();
+        dbus.add_match_rule(rule.clone())?;
+        rules.push(rule);
+    }
+    for message in MessageIterator::from(&connection) {
+        let message = message?;
+        if rules.iter().any(|rule| rule.matches(&message).unwrap_or(false)) {
+            if let Ok(active) = message.body().deserialize::<bool>() {
+                locked.store(active, Ordering::Relaxed);
+            }
+        }
+    }
+    Err(zbus::Error::Failure("the session bus connection closed".to_string()))
+}
@@ -0,0 +1,293 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::config::{Align, LengthUnit, ScrollMode, ScrollUnit};
+use crate::text;
+
+/// The text being displayed: the source string built from the metadata, how many grapheme
+/// clusters it has been rotated by, and the fixed-width window last rendered from the two.
+/// `set_text()` replaces the source, `advance()` moves the offset and `render()` derives the
+/// window; nothing else changes them.
+#[derive(Debug, Default)]
+pub struct Scroller {
+    text: String,
+    offset: usize,
+    window: String,
+    unit: ScrollUnit,
+    mode: ScrollMode,
+    length_unit: LengthUnit,
+    /// Set once a `ScrollMode::Once` reveal has gone all the way round.
+    finished: bool,
+    /// Scroll towards the end of the text instead of its start, for right-to-left scripts.
+    reverse: bool,
+}
+
+impl Scroller {
+    pub fn new(unit: ScrollUnit, mode: ScrollMode, length_unit: LengthUnit) -> Self {
+        Scroller { unit, mode, length_unit, ..Scroller::default() }
+    }
+
+    /// Starts scrolling from the beginning again, re-arming a finished one-shot reveal.
+    pub fn restart(&mut self) {
+        self.offset = 0;
+        self.finished = false;
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the source text. A different text starts scrolling from its beginning; the
+    /// same text keeps its offset.
+    pub fn set_text(&mut self, text: &str) {
+        if self.text != text {
+            self.text = text.to_string();
+            self.restart();
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Resumes scrolling from `offset` grapheme clusters into the current text, or from its
+    /// beginning if the text is shorter than that.
+    pub fn set_offset(&mut self, offset: usize) {
+        self.offset = if offset < self.text.graphemes(true).count() { offset } else { 0 };
+    }
+
+    /// Renders the window for the current offset: exactly `width` units long, or the
+    /// beginning cut with an ellipsis once a one-shot reveal has finished.
+    pub fn render(&mut self, width: usize, align: Align) {
+        let visible = if self.finished { text::truncate_with_ellipsis(&self.text, width, self.length_unit) } else { self.rotated() };
+        self.window = fit_to_width(&visible, width, align, self.length_unit);
+    }
+
+    pub fn window(&self) -> &str {
+        &self.window
+    }
+
+    pub fn set_reverse(&mut self, reverse: bool) {
+        self.reverse = reverse;
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        self.reverse
+    }
+
+    /// The text rotated so the window starts at the current offset.
+    pub fn rotated(&self) -> String {
+        rotate_left(&self.text, self.offset)
+    }
+
+    /// Moves the window along by one step.
+    ///
+    /// In word mode the window jumps to the start of the next word, skipping runs of spaces.
+    /// While the rest of the current word is wider than `width` it advances a cell at a time
+    /// instead, so long words and scripts without spaces (CJK) still scroll smoothly.
+    ///
+    /// Reversed, the window moves the other way: back a cell, or to the start of the
+    /// previous word.
+    ///
+    /// In `ScrollMode::Once` the scroller stops for good when it wraps back to the start.
+    pub fn advance(&mut self, width: usize) {
+        let graphemes: Vec<&str> = self.text.graphemes(true).collect();
+        if graphemes.is_empty() || self.finished {
+            return;
+        }
+        let len = graphemes.len();
+        self.offset = match (self.unit, self.reverse) {
+            (ScrollUnit::Cell, false) => (self.offset + 1) % len,
+            (ScrollUnit::Cell, true) => (self.offset + len - 1) % len,
+            (ScrollUnit::Word, false) => next_word_start(&graphemes, self.offset, width, self.length_unit),
+            (ScrollUnit::Word, true) => previous_word_start(&graphemes, self.offset, width, self.length_unit),
+        };
+        if self.offset == 0 && self.mode == ScrollMode::Once {
+            self.finished = true;
+        }
+    }
+}
+
+fn next_word_start(graphemes: &[&str], offset: usize, width: usize, length_unit: LengthUnit) -> usize {
+    let len = graphemes.len();
+    let at = |i: usize| graphemes[i % len];
+    let word_end = (offset..offset + len).find(|&i| at(i) == " ").unwrap_or(offset + len);
+    let rest: String = (offset..word_end).map(at).collect();
+    if text::length(&rest, length_unit) > width {
+        return (offset + 1) % len;
+    }
+    match (word_end..offset + len).find(|&i| at(i) != " ") {
+        Some(start) => start % len,
+        None => (offset + 1) % len,
+    }
+}
+
+fn previous_word_start(graphemes: &[&str], offset: usize, width: usize, length_unit: LengthUnit) -> usize {
+    let len = graphemes.len();
+    // Index `back` positions before `offset`, wrapping around.
+    let at = |back: usize| graphemes[(offset + len - back % len) % len];
+    let step_back = (offset + len - 1) % len;
+    let mut back = 1;
+    while back < len && at(back) == " " {
+        back += 1;
+    }
+    while back < len && at(back + 1) != " " {
+        back += 1;
+    }
+    let start = (offset + len - back) % len;
+    let word: String = (0..back).rev().map(|i| at(i + 1)).collect();
+    if start == offset || text::length(word.trim_end(), length_unit) > width {
+        step_back
+    } else {
+        start
+    }
+}
+
+/// Fits `text` into exactly `width` units: longer text is cut between grapheme clusters,
+/// and a double-width cluster that would straddle the last cell is replaced by a space;
+/// shorter text is padded according to `align`.
+pub fn fit_to_width(text: &str, width: usize, align: Align, length_unit: LengthUnit) -> String {
+    let mut used = 0;
+    let mut fitted = String::new();
+
+    for grapheme in text.graphemes(true) {
+        let cells = text::grapheme_len(grapheme, length_unit);
+        if used + cells > width {
+            fitted.push_str(&" ".repeat(width - used));
+            return fitted;
+        }
+        used += cells;
+        fitted.push_str(grapheme);
+    }
+
+    let padding = width - used;
+    let left = match align {
+        Align::Left => 0,
+        Align::Center => padding / 2,
+        Align::Right => padding,
+    };
+    format!("{}{}{}", " ".repeat(left), fitted, " ".repeat(padding - left))
+}
+
+/// Moves the first `count` grapheme clusters of `text` to its end.
+pub fn rotate_left(text: &str, count: usize) -> String {
+    let split = text.grapheme_indices(true).nth(count).map_or(text.len(), |(index, _)| index);
+    format!("{}{}", &text[split..], &text[..split])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(text: &str, width: usize, unit: ScrollUnit, count: usize) -> Vec<String> {
+        let mut scroller = Scroller { text: text.to_string(), ..Scroller::new(unit, ScrollMode::Loop, LengthUnit::Columns) };
+        (0..count)
+            .map(|_| {
+                scroller.advance(width);
+                scroller.rotated()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn word_mode_jumps_between_words_and_wraps() {
+        assert_eq!(steps("one  two three | ", 8, ScrollUnit::Word, 4), ["two three | one  ", "three | one  two ", "| one  two three ", "one  two three | "]);
+    }
+
+    #[test]
+    fn word_mode_cell_scrolls_words_wider_than_the_window() {
+        assert_eq!(steps("abcdefgh ij ", 4, ScrollUnit::Word, 6)[..6], ["bcdefgh ij a", "cdefgh ij ab", "defgh ij abc", "efgh ij abcd", "ij abcdefgh ", "abcdefgh ij "]);
+        assert_eq!(steps("漢字かな漢字", 4, ScrollUnit::Word, 2), ["字かな漢字漢", "かな漢字漢字"]);
+    }
+
+    #[test]
+    fn once_mode_parks_after_a_full_cycle_until_restarted() {
+        let mut scroller = Scroller { text: "abc".to_string(), ..Scroller::new(ScrollUnit::Cell, ScrollMode::Once, LengthUnit::Columns) };
+        scroller.advance(2);
+        scroller.advance(2);
+        assert!(!scroller.finished);
+        scroller.advance(2);
+        assert!(scroller.finished);
+        scroller.advance(2);
+        scroller.render(2, Align::Left);
+        assert_eq!(scroller.window(), "a…");
+        scroller.restart();
+        scroller.advance(2);
+        scroller.render(2, Align::Left);
+        assert_eq!(scroller.window(), "bc");
+    }
+
+    #[test]
+    fn cell_mode_rotates_one_character() {
+        assert_eq!(steps("ab c", 2, ScrollUnit::Cell, 2), ["b ca", " cab"]);
+    }
+
+    #[test]
+    fn reversed_scrolls_backwards() {
+        let mut scroller = Scroller { text: "abc".to_string(), ..Scroller::new(ScrollUnit::Cell, ScrollMode::Loop, LengthUnit::Columns) };
+        scroller.set_reverse(true);
+        scroller.advance(2);
+        assert_eq!(scroller.rotated(), "cab");
+        let mut scroller = Scroller { text: "one two three ".to_string(), ..Scroller::new(ScrollUnit::Word, ScrollMode::Loop, LengthUnit::Columns) };
+        scroller.set_reverse(true);
+        let windows: Vec<String> = (0..3)
+            .map(|_| {
+                scroller.advance(8);
+                scroller.rotated()
+            })
+            .collect();
+        assert_eq!(windows, ["three one two ", "two three one ", "one two three "]);
+    }
+
+    #[test]
+    fn set_text_restarts_only_for_new_text() {
+        let mut scroller = Scroller::new(ScrollUnit::Cell, ScrollMode::Loop, LengthUnit::Columns);
+        scroller.set_text("abcdef");
+        scroller.advance(3);
+        scroller.set_text("abcdef");
+        scroller.render(3, Align::Left);
+        assert_eq!(scroller.window(), "bcd");
+        scroller.set_text("uvwxyz");
+        scroller.render(3, Align::Left);
+        assert_eq!(scroller.window(), "uvw");
+    }
+
+    #[test]
+    fn fit_to_width_blanks_a_straddling_wide_char() {
+        assert_eq!(fit_to_width("ab漢字", 5, Align::Left, LengthUnit::Columns), "ab漢 ");
+        assert_eq!(fit_to_width("a漢字", 4, Align::Left, LengthUnit::Columns), "a漢 ");
+        assert_eq!(fit_to_width("漢", 1, Align::Left, LengthUnit::Columns), " ");
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn scroll_window_is_always_the_configured_width(text in "[a-z 漢字かな한글]{1,40}", width in 1usize..30) {
+            for offset in 0..text.chars().count() {
+                let window = fit_to_width(&rotate_left(&text, offset), width, Align::Left, LengthUnit::Columns);
+                proptest::prop_assert_eq!(text::width(&window), width);
+            }
+        }
+    }
+
+    #[test]
+    fn fit_to_width_aligns_wide_text() {
+        let pad = |align| fit_to_width("漢字a", 8, align, LengthUnit::Columns);
+        assert_eq!(pad(Align::Left), "漢字a   ");
+        assert_eq!(pad(Align::Center), " 漢字a  ");
+        assert_eq!(pad(Align::Right), "   漢字a");
+        for align in [Align::Left, Align::Center, Align::Right] {
+            assert_eq!(text::width(&pad(align)), 8);
+        }
+    }
+
+    #[test]
+    fn fit_to_width_counts_chars_in_char_mode() {
+        assert_eq!(fit_to_width("漢字abc", 4, Align::Left, LengthUnit::Chars), "漢字ab");
+        assert_eq!(fit_to_width("漢字", 4, Align::Right, LengthUnit::Chars), "  漢字");
+    }
+
+    #[test]
+    fn combining_marks_move_with_their_base() {
+        assert_eq!(steps("กี่ab", 2, ScrollUnit::Cell, 1), ["abกี่"]);
+        assert_eq!(rotate_left("e\u{301}x", 1), "xe\u{301}");
+    }
+}
@@ -0,0 +1,372 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+pub use crate::mpris::{MPRIS_PATH, MPRIS_PREFIX, PLAYERCTLD_BUS_NAME, PLAYER_INTERFACE, TIMEOUT};
+
+const MOCK_BUS_NAME: &str = "org.mpris.MediaPlayer2.mock";
+/// Errors with which players say they don't implement a property, as opposed to failing to
+/// answer this time.
+pub const UNSUPPORTED_ERRORS: [&str; 3] = [
+    "org.freedesktop.DBus.Error.NotSupported",
+    "org.freedesktop.DBus.Error.UnknownProperty",
+    "org.freedesktop.DBus.Error.InvalidArgs",
+];
+
+/// One value of the `Metadata` property, which is an `a{sv}`: mostly strings, but e.g.
+/// `xesam:artist` is an array of strings and `mpris:length` an integer.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum MetadataValue {
+    Text(String),
+    List(Vec<String>),
+    Int(i64),
+    Float(f64),
+}
+
+impl MetadataValue {
+    /// The value as displayed, with the items of a list joined by `separator`. Blank items
+    /// are left out, so a list of only blanks comes out empty.
+    pub fn to_text(&self, separator: &str) -> String {
+        match self {
+            MetadataValue::Text(text) => text.clone(),
+            MetadataValue::List(_) => self.items().join(separator),
+            MetadataValue::Int(number) => number.to_string(),
+            MetadataValue::Float(number) => number.to_string(),
+        }
+    }
+
+    /// The non-blank strings of a list, or a non-blank text as its only item.
+    pub fn items(&self) -> Vec<String> {
+        match self {
+            MetadataValue::Text(text) if !text.trim().is_empty() => vec![text.clone()],
+            MetadataValue::List(items) => items.iter().filter(|item| !item.trim().is_empty()).cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+pub type MprisMetadata = HashMap<String, MetadataValue>;
+
+/// The MPRIS `PlaybackStatus`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub enum PlaybackStatus {
+    #[default]
+    Playing,
+    Paused,
+    Stopped,
+}
+
+impl PlaybackStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlaybackStatus::Playing => "Playing",
+            PlaybackStatus::Paused => "Paused",
+            PlaybackStatus::Stopped => "Stopped",
+        }
+    }
+}
+
+impl FromStr for PlaybackStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "Playing" => Ok(PlaybackStatus::Playing),
+            "Paused" => Ok(PlaybackStatus::Paused),
+            "Stopped" => Ok(PlaybackStatus::Stopped),
+            _ => Err(format!("unknown playback status: {}", status)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PlayerInfo {
+    pub name: String,
+    /// Bus name of the application behind the player, used to pick its icon. Differs from
+    /// `name` only for playerctld, which stands in for the most recently active player.
+    pub app: String,
+    /// The player's `CanControl`: when `false` it ignores every command, so it gets no buttons.
+    pub can_control: bool,
+    /// The player's `Identity`, e.g. `Spotify`, or empty if it didn't say.
+    pub identity: String,
+}
+
+impl PlayerInfo {
+    pub fn new(name: String) -> Self {
+        PlayerInfo { app: name.clone(), name, can_control: true, identity: String::new() }
+    }
+}
+
+/// The MPRIS names on the bus in the order they appeared, each with the unique name of its
+/// owner.
+pub type BusNames = Vec<(String, String)>;
+
+/// What has been found out about the players on the bus, so it isn't asked again each
+/// tick. It is kept per owner: a name that leaves the bus is forgotten, and one that gets a
+/// new owner is a different process, which is probed afresh.
+#[derive(Default)]
+pub struct PlayerCache {
+    /// Players by bus name, with the owner they were probed under.
+    known: HashMap<String, (String, PlayerInfo)>,
+    /// Players found not to implement `Position`, by the bus name of the application and its
+    /// owner.
+    no_position: HashMap<String, String>,
+}
+
+impl PlayerCache {
+    /// Forgets the players whose name has left the bus or changed owner since.
+    pub fn forget_stale(&mut self, names: &BusNames) {
+        let current = |name: &String, owner: &String| names.iter().any(|(n, o)| n == name && o == owner);
+        self.known.retain(|name, (owner, _)| current(name, owner));
+        self.no_position.retain(|name, owner| current(name, owner));
+    }
+
+    /// The player with `name` and `owner`, found out with `discover` if it is new.
+    pub fn player(&mut self, name: &str, owner: &str, discover: impl FnOnce(&str) -> PlayerInfo) -> PlayerInfo {
+        let (_, player) = self.known.entry(name.to_string()).or_insert_with(|| (owner.to_string(), discover(name)));
+        player.clone()
+    }
+
+    pub fn lacks_position(&self, player: &PlayerInfo) -> bool {
+        self.no_position.contains_key(&player.app)
+    }
+
+    /// Remembers that the current owner of the player's application doesn't implement
+    /// `Position`. Through playerctld that is the player it forwards to, as the next one it
+    /// switches to may well have a position; if that player is unknown nothing is remembered.
+    pub fn set_lacks_position(&mut self, player: &PlayerInfo, names: &BusNames) {
+        if player.app == PLAYERCTLD_BUS_NAME {
+            return;
+        }
+        if let Some((_, owner)) = names.iter().find(|(n, _)| *n == player.app) {
+            self.no_position.insert(player.app.clone(), owner.clone());
+        }
+    }
+}
+
+/// Where players and their state come from: the session bus, or a fixed state for tests and
+/// offline runs. All reads and writes of player properties go through here.
+pub trait MediaSource {
+    fn players(&self) -> Result<Vec<PlayerInfo>, Box<dyn Error>>;
+    fn status(&self, player: &PlayerInfo) -> Result<PlaybackStatus, Box<dyn Error>>;
+    /// Raw `Metadata` values; callers clean them up for display.
+    fn metadata(&self, player: &PlayerInfo) -> Result<MprisMetadata, Box<dyn Error>>;
+    fn rate(&self, player: &PlayerInfo) -> Result<f64, Box<dyn Error>>;
+    /// The playback position in microseconds.
+    fn position(&self, player: &PlayerInfo) -> Result<i64, Box<dyn Error>>;
+    fn volume(&self, player: &PlayerInfo) -> Result<f64, Box<dyn Error>>;
+    /// The `DesktopEntry` of the application behind the player, e.g. `vlc`.
+    fn desktop_entry(&self, player: &PlayerInfo) -> Result<String, Box<dyn Error>>;
+    fn set_volume(&self, player: &PlayerInfo, volume: f64) -> Result<(), Box<dyn Error>>;
+    /// Calls an argument-less `org.mpris.MediaPlayer2.Player` method such as `Next`.
+    fn call(&self, player: &PlayerInfo, method: &str) -> Result<(), Box<dyn Error>>;
+}
+
+/// The session bus backend: zbus with the `zbus` feature, libdbus otherwise.
+#[cfg(feature = "zbus")]
+pub type SessionMediaSource = crate::zbus_source::ZbusMediaSource;
+#[cfg(not(feature = "zbus"))]
+pub type SessionMediaSource = crate::dbus_source::DbusMediaSource;
+
+/// Applies a `NameOwnerChanged`: an MPRIS name with a new owner is added, in the order
+/// players appeared, or given its new owner, and one whose owner left is removed.
+pub fn update_names(names: &mut BusNames, name: String, new_owner: &str) {
+    if !name.starts_with(MPRIS_PREFIX) {
+        return;
+    }
+    match names.iter().position(|(known, _)| *known == name) {
+        Some(index) if new_owner.is_empty() => {
+            names.remove(index);
+        }
+        Some(index) => names[index].1 = new_owner.to_string(),
+        None if !new_owner.is_empty() => names.push((name, new_owner.to_string())),
+        None => {}
+    }
+}
+
+/// What a single player reports, as read from a `--dbus-mock-file`.
+#[derive(Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DisplayState {
+    pub player: String,
+    pub status: PlaybackStatus,
+    pub metadata: MprisMetadata,
+    pub rate: Option<f64>,
+    /// In microseconds, like `mpris:length`.
+    pub position: Option<i64>,
+    pub volume: Option<f64>,
+    pub desktop_entry: Option<String>,
+}
+
+impl Default for DisplayState {
+    fn default() -> Self {
+        DisplayState {
+            player: MOCK_BUS_NAME.to_string(),
+            status: PlaybackStatus::Playing,
+            metadata: MprisMetadata::new(),
+            rate: None,
+            position: None,
+            volume: None,
+            desktop_entry: None,
+        }
+    }
+}
+
+/// A single player whose state never changes, for running without a session bus.
+pub struct MockMediaSource {
+    state: DisplayState,
+    volume: Cell<Option<f64>>,
+}
+
+impl MockMediaSource {
+    pub fn new(state: DisplayState) -> Self {
+        MockMediaSource { volume: Cell::new(state.volume), state }
+    }
+
+    /// A player that is playing `title` by `artist`.
+    #[cfg(test)]
+    pub fn playing(artist: &str, title: &str) -> Self {
+        let metadata = [
+            ("xesam:artist", MetadataValue::List(vec![artist.to_string()])),
+            ("xesam:title", MetadataValue::Text(title.to_string())),
+        ];
+        let metadata = metadata.into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+        Self::new(DisplayState { metadata, ..DisplayState::default() })
+    }
+
+    /// The made-up track `--test-output` renders.
+    pub fn test_track() -> Self {
+        let metadata = [
+            ("xesam:title", MetadataValue::Text("The Quick Brown Fox".to_string())),
+            ("xesam:artist", MetadataValue::List(vec!["Test Artist".to_string()])),
+            ("xesam:album", MetadataValue::Text("Test Album".to_string())),
+            ("mpris:length", MetadataValue::Int(213_000_000)),
+        ];
+        let metadata = metadata.into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+        Self::new(DisplayState { metadata, rate: Some(1.0), position: Some(90_000_000), volume: Some(1.0), ..DisplayState::default() })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let state = serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self::new(state))
+    }
+}
+
+impl MediaSource for MockMediaSource {
+    fn players(&self) -> Result<Vec<PlayerInfo>, Box<dyn Error>> {
+        Ok(vec![PlayerInfo::new(self.state.player.clone())])
+    }
+
+    fn status(&self, _player: &PlayerInfo) -> Result<PlaybackStatus, Box<dyn Error>> {
+        Ok(self.state.status)
+    }
+
+    fn metadata(&self, _player: &PlayerInfo) -> Result<MprisMetadata, Box<dyn Error>> {
+        Ok(self.state.metadata.clone())
+    }
+
+    fn rate(&self, _player: &PlayerInfo) -> Result<f64, Box<dyn Error>> {
+        self.state.rate.ok_or_else(|| "no rate in mock file".into())
+    }
+
+    fn position(&self, _player: &PlayerInfo) -> Result<i64, Box<dyn Error>> {
+        self.state.position.ok_or_else(|| "no position in mock file".into())
+    }
+
+    fn volume(&self, _player: &PlayerInfo) -> Result<f64, Box<dyn Error>> {
+        self.volume.get().ok_or_else(|| "no volume in mock file".into())
+    }
+
+    fn set_volume(&self, _player: &PlayerInfo, volume: f64) -> Result<(), Box<dyn Error>> {
+        self.volume.set(Some(volume));
+        Ok(())
+    }
+
+    fn desktop_entry(&self, _player: &PlayerInfo) -> Result<String, Box<dyn Error>> {
+        self.state.desktop_entry.clone().ok_or_else(|| "no desktop entry in mock file".into())
+    }
+
+    fn call(&self, _player: &PlayerInfo, method: &str) -> Result<(), Box<dyn Error>> {
+        verbose!("mock player: {}", method);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_mpris_names_joining_and_leaving_the_bus() {
+        let mut names = Vec::new();
+        update_names(&mut names, "org.mpris.MediaPlayer2.spotify".to_string(), ":1.10");
+        update_names(&mut names, "org.freedesktop.Notifications".to_string(), ":1.11");
+        update_names(&mut names, "org.mpris.MediaPlayer2.vlc".to_string(), ":1.12");
+        update_names(&mut names, "org.mpris.MediaPlayer2.spotify".to_string(), ":1.13");
+        let listed: Vec<_> = names.iter().map(|(name, owner)| (name.as_str(), owner.as_str())).collect();
+        assert_eq!(listed, [("org.mpris.MediaPlayer2.spotify", ":1.13"), ("org.mpris.MediaPlayer2.vlc", ":1.12")]);
+        update_names(&mut names, "org.mpris.MediaPlayer2.spotify".to_string(), "");
+        update_names(&mut names, "org.mpris.MediaPlayer2.mpv".to_string(), "");
+        assert_eq!(names, [("org.mpris.MediaPlayer2.vlc".to_string(), ":1.12".to_string())]);
+    }
+
+    #[test]
+    fn probes_a_player_again_when_its_name_gets_a_new_owner() {
+        let probes = Cell::new(0);
+        let discover = |name: &str| {
+            probes.set(probes.get() + 1);
+            PlayerInfo { can_control: probes.get() > 1, ..PlayerInfo::new(name.to_string()) }
+        };
+        let mut cache = PlayerCache::default();
+        let spotify = "org.mpris.MediaPlayer2.spotify";
+        let mut names = vec![(spotify.to_string(), ":1.10".to_string())];
+        cache.forget_stale(&names);
+        let player = cache.player(spotify, ":1.10", discover);
+        assert!(!player.can_control);
+        cache.set_lacks_position(&player, &names);
+        cache.forget_stale(&names);
+        assert!(!cache.player(spotify, ":1.10", discover).can_control);
+        assert!(cache.lacks_position(&player));
+        assert_eq!(probes.get(), 1);
+
+        update_names(&mut names, spotify.to_string(), ":1.20");
+        cache.forget_stale(&names);
+        assert!(!cache.lacks_position(&player));
+        assert!(cache.player(spotify, ":1.20", discover).can_control);
+        assert_eq!(probes.get(), 2);
+
+        update_names(&mut names, spotify.to_string(), "");
+        cache.forget_stale(&names);
+        assert!(cache.known.is_empty());
+    }
+
+    #[test]
+    fn mock_serves_the_fixture() {
+        let state = serde_json::from_str(include_str!("../tests/fixtures/playing.json")).unwrap();
+        let source = MockMediaSource::new(state);
+        let players = source.players().unwrap();
+        assert_eq!(players.len(), 1);
+        assert_eq!(source.status(&players[0]).unwrap(), PlaybackStatus::Playing);
+        let metadata = source.metadata(&players[0]).unwrap();
+        assert_eq!(metadata.get("xesam:title"), Some(&MetadataValue::Text("Bohemian Rhapsody".to_string())));
+        assert_eq!(metadata.get("xesam:artist"), Some(&MetadataValue::List(vec!["Queen".to_string()])));
+        assert_eq!(metadata.get("mpris:length"), Some(&MetadataValue::Int(354_000_000)));
+        assert!(source.rate(&players[0]).is_err());
+        source.set_volume(&players[0], 0.0).unwrap();
+        assert_eq!(source.volume(&players[0]).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn parses_playback_status() {
+        assert_eq!("Paused".parse(), Ok(PlaybackStatus::Paused));
+        assert_eq!(PlaybackStatus::Stopped.as_str(), "Stopped");
+        assert!("Buffering".parse::<PlaybackStatus>().is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+const TRACKS_URL: &str = "https://api.spotify.com/v1/tracks/";
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Titles Spotify gives ads, for clients whose track id doesn't say.
+const AD_TITLES: [&str; 2] = ["Advertisement", "Spotify"];
+
+type Supplement = HashMap<String, String>;
+
+/// Fetches Web API details that Spotify's MPRIS interface leaves out, exposed as the
+/// `spotify_popularity` and `spotify_explicit` fields. Authenticates with the Client
+/// Credentials flow; the token is shared by lookups and requested again once it expires.
+pub struct Spotify {
+    client_id: String,
+    client_secret: String,
+    token: Arc<Mutex<Option<String>>>,
+    cache: HashMap<String, Supplement>,
+    current: Option<String>,
+    pending: Option<(String, Receiver<Option<Supplement>>)>,
+}
+
+impl Spotify {
+    pub fn new(client_id: String, client_secret: String) -> Self {
+        Spotify { client_id, client_secret, token: Arc::default(), cache: HashMap::new(), current: None, pending: None }
+    }
+
+    /// Notes the Spotify track id now playing, starting a lookup if it changed and isn't cached.
+    pub fn set_track(&mut self, track_id: &str) {
+        if self.current.as_deref() == Some(track_id) {
+            return;
+        }
+        self.current = Some(track_id.to_string());
+        if self.cache.contains_key(track_id) {
+            self.pending = None;
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let (client_id, client_secret, token) = (self.client_id.clone(), self.client_secret.clone(), Arc::clone(&self.token));
+        let id = track_id.to_string();
+        thread::spawn(move || {
+            let supplement = fetch_track(&client_id, &client_secret, &token, &id)
+                .map_err(|e| verbose!("spotify lookup for {} failed: {}", id, e))
+                .ok();
+            let _ = sender.send(supplement);
+        });
+        self.pending = Some((track_id.to_string(), receiver));
+    }
+
+    /// Collects a finished lookup and returns what is known about the current track.
+    pub fn supplement(&mut self) -> Supplement {
+        if let Some((id, receiver)) = &self.pending {
+            match receiver.try_recv() {
+                Ok(supplement) => {
+                    if let Some(supplement) = supplement {
+                        self.cache.insert(id.clone(), supplement);
+                    }
+                    self.pending = None;
+                }
+                Err(TryRecvError::Disconnected) => self.pending = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        self.current.as_ref().and_then(|id| self.cache.get(id)).cloned().unwrap_or_default()
+    }
+}
+
+/// The track id in `mpris:trackid`, which Spotify reports as `spotify:track:<id>` or, in
+/// newer clients, `/com/spotify/track/<id>`.
+pub fn track_id(mpris_track_id: &str) -> Option<&str> {
+    let id = mpris_track_id
+        .strip_prefix("spotify:track:")
+        .or_else(|| mpris_track_id.strip_prefix("/com/spotify/track/"))?;
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())).then_some(id)
+}
+
+/// Whether the metadata is one of the ads Spotify's free tier plays: a `/com/spotify/ad/`
+/// or `spotify:ad:` track id, or without a track id an artistless ad title.
+pub fn is_ad(metadata: &HashMap<String, String>) -> bool {
+    let field = |key| metadata.get(key).map_or("", String::as_str);
+    match field("mpris:trackid") {
+        "" => field("xesam:artist").is_empty() && AD_TITLES.contains(&field("xesam:title")),
+        id => id.starts_with("/com/spotify/ad/") || id.starts_with("spotify:ad:"),
+    }
+}
+
+fn request_token(client_id: &str, client_secret: &str) -> Result<String, Box<dyn Error>> {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let response: Value = agent
+        .post(TOKEN_URL)
+        .send_form(&[("grant_type", "client_credentials"), ("client_id", client_id), ("client_secret", client_secret)])?
+        .into_json()?;
+    let token = response["access_token"].as_str().ok_or("no access_token in response")?;
+    Ok(token.to_string())
+}
+
+/// Looks the track up with the shared token, fetching a fresh token first if there is none
+/// or the API rejects the current one with 401.
+fn fetch_track(client_id: &str, client_secret: &str, token: &Mutex<Option<String>>, id: &str) -> Result<Supplement, Box<dyn Error>> {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    for _ in 0..2 {
+        let current = token.lock().unwrap().clone();
+        let bearer = match current {
+            Some(bearer) => bearer,
+            None => {
+                let bearer = request_token(client_id, client_secret)?;
+                *token.lock().unwrap() = Some(bearer.clone());
+                bearer
+            }
+        };
+        match agent.get(&format!("{}{}", TRACKS_URL, id)).set("Authorization", &format!("Bearer {}", bearer)).call() {
+            Ok(response) => return Ok(parse_track(&response.into_json()?)),
+            Err(ureq::Error::Status(401, _)) => *token.lock().unwrap() = None,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err("token rejected".into())
+}
+
+fn parse_track(response: &Value) -> Supplement {
+    let mut supplement = Supplement::new();
+    if let Some(popularity) = response["popularity"].as_u64() {
+        supplement.insert("spotify_popularity".to_string(), popularity.to_string());
+    }
+    if let Some(explicit) = response["explicit"].as_bool() {
+        supplement.insert("spotify_explicit".to_string(), explicit.to_string());
+    }
+    supplement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_track_ids() {
+        assert_eq!(track_id("spotify:track:4uLU6hMCjMI75M1A2tKUQC"), Some("4uLU6hMCjMI75M1A2tKUQC"));
+        assert_eq!(track_id("/com/spotify/track/4uLU6hMCjMI75M1A2tKUQC"), Some("4uLU6hMCjMI75M1A2tKUQC"));
+        assert_eq!(track_id("spotify:ad:000000000000000000000"), None);
+        assert_eq!(track_id("/org/mpris/MediaPlayer2/Track/1"), None);
+    }
+
+    #[test]
+    fn detects_ads() {
+        let metadata = |fields: &[(&str, &str)]| fields.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        assert!(is_ad(&metadata(&[("mpris:trackid", "/com/spotify/ad/7e4d1a0c"), ("xesam:title", "Listen ad-free")])));
+        assert!(is_ad(&metadata(&[("mpris:trackid", "spotify:ad:000000000000000000000")])));
+        assert!(is_ad(&metadata(&[("xesam:title", "Advertisement")])));
+        assert!(!is_ad(&metadata(&[("mpris:trackid", "/com/spotify/track/4u7EnebtmKWzUH433cf5Qv"), ("xesam:title", "Spotify")])));
+        assert!(!is_ad(&metadata(&[("xesam:title", "Spotify"), ("xesam:artist", "The Band")])));
+    }
+
+    #[test]
+    fn parses_popularity_and_explicit() {
+        let response = serde_json::json!({ "name": "Never Gonna Give You Up", "popularity": 77, "explicit": false });
+        let supplement = parse_track(&response);
+        assert_eq!(supplement.get("spotify_popularity").map(String::as_str), Some("77"));
+        assert_eq!(supplement.get("spotify_explicit").map(String::as_str), Some("false"));
+        assert!(parse_track(&serde_json::json!({ "error": { "status": 404 } })).is_empty());
+    }
+}
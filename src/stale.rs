@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+
+/// Readings in a row with the same position before a "Playing" player counts as stale. More
+/// than one, since some players only update their position every second or so.
+const STALE_READINGS: u32 = 3;
+
+/// Spots players that report "Playing" while their position stands still, as web players do
+/// with the metadata of a video that has ended, for `stale-check`.
+#[derive(Debug, Default)]
+pub struct StaleTracker {
+    /// Last position per bus name, and how many readings in a row it has been the same.
+    positions: HashMap<String, (i64, u32)>,
+}
+
+impl StaleTracker {
+    /// Records a position read from a "Playing" player and returns whether it is stale.
+    pub fn observe(&mut self, player: &str, position: i64) -> bool {
+        let entry = self.positions.entry(player.to_string()).or_insert((position, 0));
+        if entry.0 == position {
+            entry.1 += 1;
+        } else {
+            *entry = (position, 1);
+        }
+        entry.1 > STALE_READINGS
+    }
+
+    /// Forgets a player that isn't playing, so it starts afresh when it resumes.
+    pub fn reset(&mut self, player: &str) {
+        self.positions.remove(player);
+    }
+
+    pub fn is_stale(&self, player: &str) -> bool {
+        self.positions.get(player).is_some_and(|&(_, same)| same > STALE_READINGS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_standing_position_becomes_stale() {
+        let mut tracker = StaleTracker::default();
+        assert!(!tracker.observe("web", 5));
+        assert!(!tracker.observe("web", 5));
+        assert!(!tracker.observe("web", 5));
+        assert!(tracker.observe("web", 5));
+        assert!(tracker.is_stale("web"));
+        assert!(!tracker.observe("web", 6));
+        tracker.observe("web", 6);
+        tracker.observe("web", 6);
+        tracker.observe("web", 6);
+        tracker.reset("web");
+        assert!(!tracker.is_stale("web"));
+    }
+}
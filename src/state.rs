@@ -0,0 +1,95 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// Larger state files are not written: track IDs are short, so anything bigger is junk.
+const MAX_STATE_SIZE: usize = 512;
+
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+/// Where the display was when the last instance exited, for `state-restore`.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct SavedState {
+    pub player: String,
+    pub track_id: String,
+    pub offset: usize,
+}
+
+/// Where the socket, flags and other files that only last for the login go:
+/// `$XDG_RUNTIME_DIR`, or without one a private per-user directory in the temp dir.
+pub fn runtime_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return PathBuf::from(dir);
+    }
+    let user = std::env::var("USER").unwrap_or_default();
+    let dir = std::env::temp_dir().join(format!("polybar-now-playing-{}", user));
+    // A failure shows up when the file in it is written.
+    let _ = fs::DirBuilder::new().recursive(true).mode(0o700).create(&dir);
+    dir
+}
+
+/// `polybar-now-playing-<session>.state` in the `runtime_dir()`, with the login session from
+/// `$XDG_SESSION_ID` so concurrent sessions don't restore each other's state.
+pub fn path() -> PathBuf {
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "default".to_string());
+    runtime_dir().join(format!("polybar-now-playing-{}.state", session))
+}
+
+pub fn load(path: &Path) -> Option<SavedState> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Writes the state to a temporary file and renames it over `path`, so a reader never sees
+/// a partial file.
+pub fn save(path: &Path, state: &SavedState) -> io::Result<()> {
+    let contents = serde_json::to_string(state)?;
+    if contents.len() > MAX_STATE_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "state too large"));
+    }
+    let partial = path.with_extension("state.part");
+    fs::write(&partial, contents)?;
+    fs::rename(partial, path)
+}
+
+extern "C" fn on_sigterm(_signal: libc::c_int) {
+    TERMINATED.store(true, Ordering::Relaxed);
+}
+
+/// Makes SIGTERM set a flag for the main loop instead of killing the process, so it can save
+/// its state before exiting.
+pub fn watch_sigterm() {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(libc::SIGTERM, on_sigterm as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+pub fn terminated() -> bool {
+    TERMINATED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_and_loads_state() {
+        let path = std::env::temp_dir().join(format!("polybar-now-playing-test-{}.state", std::process::id()));
+        let state = SavedState {
+            player: "org.mpris.MediaPlayer2.spotify".to_string(),
+            track_id: "spotify:track:4u7EnebtmKWzUH433cf5Qv".to_string(),
+            offset: 7,
+        };
+        save(&path, &state).unwrap();
+        assert_eq!(load(&path), Some(state));
+        let huge = SavedState { player: "p".repeat(MAX_STATE_SIZE), track_id: String::new(), offset: 0 };
+        assert!(save(&path, &huge).is_err());
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(load(&path), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+/// A parsed `format` string: text with `{field}` placeholders and `{? ...}` groups that are
+/// left out unless every placeholder directly inside them has a value, e.g.
+/// `{title}{? - {artist}}`. Groups nest; a nested group is judged on its own placeholders.
+/// `{{` writes a literal `{`, and outside groups `}}` a literal `}`.
+#[derive(Debug, PartialEq)]
+pub struct Template(Vec<Segment>);
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(String),
+    Group(Vec<Segment>),
+}
+
+/// What rendering a run of segments produced.
+struct Rendered {
+    text: String,
+    /// Some placeholder, at any depth, had a value.
+    any_value: bool,
+    /// Every placeholder directly in the run had a value.
+    complete: bool,
+}
+
+impl Template {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        let mut chars = format.chars().peekable();
+        let segments = parse_segments(&mut chars, false)?;
+        Ok(Template(segments))
+    }
+
+    /// The names of its placeholders, at any depth, for checking them against the known ones.
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        collect_placeholders(&self.0, &mut names);
+        names
+    }
+
+    /// A template that is just the one placeholder, for `field`.
+    pub fn field(name: &str) -> Self {
+        Template(vec![Segment::Field(name.to_string())])
+    }
+
+    /// Fills in the placeholders with `lookup`, where `None` or an empty string means the
+    /// field is missing. Returns `None` when the template has placeholders but none of them
+    /// had a value, so the caller can show its idle text instead of bare separators.
+    pub fn render(&self, lookup: impl Fn(&str) -> Option<String>) -> Option<String> {
+        let rendered = render_segments(&self.0, &lookup);
+        (rendered.any_value || !has_fields(&self.0)).then_some(rendered.text)
+    }
+}
+
+fn parse_segments(chars: &mut std::iter::Peekable<std::str::Chars>, in_group: bool) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.next_if_eq(&'{').is_some() => literal.push('{'),
+            '{' => {
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                if chars.next_if_eq(&'?').is_some() {
+                    segments.push(Segment::Group(parse_segments(chars, true)?));
+                } else {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') if !name.is_empty() => break,
+                            Some(ch) if ch != '{' && ch != '}' => name.push(ch),
+                            _ => return Err(format!("invalid placeholder {{{} in format", name)),
+                        }
+                    }
+                    segments.push(Segment::Field(name));
+                }
+            }
+            '}' if in_group => {
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(literal));
+                }
+                return Ok(segments);
+            }
+            '}' if chars.next_if_eq(&'}').is_some() => literal.push('}'),
+            '}' => return Err("unmatched } in format".to_string()),
+            _ => literal.push(ch),
+        }
+    }
+    if in_group {
+        return Err("unclosed {? group in format".to_string());
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+fn render_segments(segments: &[Segment], lookup: &impl Fn(&str) -> Option<String>) -> Rendered {
+    let mut rendered = Rendered { text: String::new(), any_value: false, complete: true };
+    for segment in segments {
+        match segment {
+            Segment::Literal(text) => rendered.text += text,
+            Segment::Field(name) => match lookup(name).filter(|value| !value.is_empty()) {
+                Some(value) => {
+                    rendered.text += &value;
+                    rendered.any_value = true;
+                }
+                None => rendered.complete = false,
+            },
+            Segment::Group(inner) => {
+                let group = render_segments(inner, lookup);
+                if group.complete && (group.any_value || !has_fields(inner)) {
+                    rendered.text += &group.text;
+                    rendered.any_value |= group.any_value;
+                }
+            }
+        }
+    }
+    rendered
+}
+
+fn collect_placeholders<'a>(segments: &'a [Segment], names: &mut Vec<&'a str>) {
+    for segment in segments {
+        match segment {
+            Segment::Literal(_) => {}
+            Segment::Field(name) => names.push(name),
+            Segment::Group(inner) => collect_placeholders(inner, names),
+        }
+    }
+}
+
+fn has_fields(segments: &[Segment]) -> bool {
+    segments.iter().any(|segment| match segment {
+        Segment::Literal(_) => false,
+        Segment::Field(_) => true,
+        Segment::Group(inner) => has_fields(inner),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(format: &str, fields: &[(&str, &str)]) -> Option<String> {
+        let template = Template::parse(format).unwrap();
+        template.render(|name| fields.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string()))
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        assert_eq!(render("{artist} - {title}", &[("title", "Song"), ("artist", "Band")]).as_deref(), Some("Band - Song"));
+        assert_eq!(render("{title} ({album})", &[("title", "Song")]).as_deref(), Some("Song ()"));
+    }
+
+    #[test]
+    fn drops_groups_with_missing_fields() {
+        let format = "{title}{? - {artist}}";
+        assert_eq!(render(format, &[("title", "Song"), ("artist", "Band")]).as_deref(), Some("Song - Band"));
+        assert_eq!(render(format, &[("title", "Video"), ("artist", "")]).as_deref(), Some("Video"));
+        assert_eq!(render(format, &[("title", "Video")]).as_deref(), Some("Video"));
+    }
+
+    #[test]
+    fn handles_nested_and_adjacent_groups() {
+        let format = "{title}{? - {artist}{? ({album})}}{? [{year}]}";
+        let all = [("title", "T"), ("artist", "A"), ("album", "B"), ("year", "1975")];
+        assert_eq!(render(format, &all).as_deref(), Some("T - A (B) [1975]"));
+        assert_eq!(render(format, &[("title", "T"), ("artist", "A")]).as_deref(), Some("T - A"));
+        // The outer group only depends on its own placeholder, not the nested group's.
+        assert_eq!(render(format, &[("title", "T"), ("album", "B"), ("year", "1975")]).as_deref(), Some("T [1975]"));
+    }
+
+    #[test]
+    fn returns_none_when_every_field_is_missing() {
+        assert_eq!(render("{title}{? - {artist}}", &[]), None);
+        assert_eq!(render("{? {artist} - }{title}", &[("title", ""), ("artist", "")]), None);
+        assert_eq!(render("now playing", &[]).as_deref(), Some("now playing"));
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render("{{{title}}}", &[("title", "T")]).as_deref(), Some("{T}"));
+        assert_eq!(render("{{{title}", &[("title", "T")]).as_deref(), Some("{T"));
+        assert_eq!(render("{title}{? {{{artist}}", &[("title", "T"), ("artist", "A")]).as_deref(), Some("T {A"));
+        assert!(Template::parse("{{a}").is_err());
+    }
+
+    #[test]
+    fn lists_placeholders_in_groups_too() {
+        let template = Template::parse("{title}{? - {artist}{? ({album})}}").unwrap();
+        assert_eq!(template.placeholders(), ["title", "artist", "album"]);
+    }
+
+    #[test]
+    fn rejects_unbalanced_formats() {
+        assert!(Template::parse("{title").is_err());
+        assert!(Template::parse("{title}{? - {artist}").is_err());
+        assert!(Template::parse("{title}}").is_err());
+        assert!(Template::parse("{}").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+use crate::config::LengthUnit;
+
+pub const ELLIPSIS: &str = "…";
+
+/// Cells taken by one grapheme cluster: the sum of its characters' widths, so combining
+/// marks, joiners and variation selectors add nothing and a lone mark is 0 wide.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.chars().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+/// Cells taken by `text` once NFC-normalized, counted per grapheme cluster. Scrolling,
+/// truncation and padding all measure with this so they agree on where cells fall.
+pub fn width(text: &str) -> usize {
+    let text: String = text.nfc().collect();
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+/// Length of one grapheme cluster in `unit`.
+pub fn grapheme_len(grapheme: &str, unit: LengthUnit) -> usize {
+    match unit {
+        LengthUnit::Columns => grapheme_width(grapheme),
+        LengthUnit::Chars => 1,
+    }
+}
+
+/// Length of `text` in `unit`, measured like `width()`.
+pub fn length(text: &str, unit: LengthUnit) -> usize {
+    match unit {
+        LengthUnit::Columns => width(text),
+        LengthUnit::Chars => text.nfc().collect::<String>().graphemes(true).count(),
+    }
+}
+
+/// Letters of the right-to-left scripts: Hebrew, Arabic, Syriac, Thaana, N'Ko and their
+/// presentation forms, plus the historic RTL blocks of the supplementary planes.
+fn is_rtl_letter(c: char) -> bool {
+    c.is_alphabetic()
+        && matches!(c, '\u{590}'..='\u{8ff}' | '\u{fb1d}'..='\u{fdff}' | '\u{fe70}'..='\u{feff}' | '\u{10800}'..='\u{10fff}' | '\u{1e800}'..='\u{1efff}')
+}
+
+/// Whether more of the letters in `text` belong to right-to-left scripts than not. A cheap
+/// stand-in for the bidi algorithm that is good enough to pick a scroll direction.
+pub fn is_predominantly_rtl(text: &str) -> bool {
+    let (rtl, ltr) = text.chars().filter(|c| c.is_alphabetic()).fold((0, 0), |(rtl, ltr), c| {
+        if is_rtl_letter(c) { (rtl + 1, ltr) } else { (rtl, ltr + 1) }
+    });
+    rtl > ltr
+}
+
+/// Cuts `text` to at most `max` units on a grapheme boundary, ending it with `ELLIPSIS`.
+/// Text that already fits is returned unchanged.
+pub fn truncate_with_ellipsis(text: &str, max: usize, unit: LengthUnit) -> String {
+    if length(text, unit) <= max {
+        return text.to_string();
+    }
+    let ellipsis = length(ELLIPSIS, unit);
+    let budget = max.saturating_sub(ellipsis);
+    let mut truncated = String::new();
+    let mut used = 0;
+    for grapheme in text.graphemes(true) {
+        let cells = grapheme_len(grapheme, unit);
+        if used + cells > budget {
+            break;
+        }
+        used += cells;
+        truncated.push_str(grapheme);
+    }
+    if max >= ellipsis {
+        truncated.truncate(truncated.trim_end().len());
+        truncated.push_str(ELLIPSIS);
+    }
+    truncated
+}
+
+/// Splits `budget` cells between fields of the given `widths` in proportion to `ratios`.
+/// A field narrower than its share keeps its width and the slack is shared out again among
+/// the rest, so cells are only taken from fields that actually need truncating.
+pub fn allocate_widths(widths: &[usize], ratios: &[u32], budget: usize) -> Vec<usize> {
+    let mut allocated: Vec<Option<usize>> = vec![None; widths.len()];
+    let mut remaining = budget;
+    loop {
+        let open: Vec<usize> = (0..widths.len()).filter(|&i| allocated[i].is_none()).collect();
+        let total_ratio: u64 = open.iter().map(|&i| u64::from(ratios[i])).sum();
+        if open.is_empty() || total_ratio == 0 {
+            break;
+        }
+        let share = |i: usize| (remaining as u64 * u64::from(ratios[i]) / total_ratio) as usize;
+        let fitting: Vec<usize> = open.iter().copied().filter(|&i| widths[i] <= share(i)).collect();
+        if fitting.is_empty() {
+            for &i in &open {
+                allocated[i] = Some(share(i));
+            }
+            break;
+        }
+        for i in fitting {
+            allocated[i] = Some(widths[i]);
+            remaining -= widths[i];
+        }
+    }
+    allocated.into_iter().map(|width| width.unwrap_or(0)).collect()
+}
+
+/// A bar of `width` characters for `pos` out of `len`, filled from the left, e.g.
+/// `█████░░░░░` halfway through. Past the end, or with no length, it is full.
+pub fn render_text_progress(pos: u64, len: u64, width: usize, fill: char, empty: char) -> String {
+    let filled = if len == 0 { width } else { (u128::from(pos.min(len)) * width as u128 / u128::from(len)) as usize };
+    std::iter::repeat_n(fill, filled).chain(std::iter::repeat_n(empty, width - filled)).collect()
+}
+
+/// Takes the `%{...}` formatting and action tags out of polybar output, leaving what the bar
+/// draws. Braces inside a tag, as in an action's command, are matched so the tag ends at its
+/// own `}`. An unclosed `%{` is left as text.
+pub fn strip_polybar_markup(s: &str) -> String {
+    let mut visible = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("%{") {
+        visible += &rest[..start];
+        let mut depth = 0;
+        let end = rest[start + 1..].char_indices().find_map(|(i, ch)| {
+            match ch {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(start + 1 + i)
+        });
+        match end {
+            Some(end) => rest = &rest[end + 1..],
+            None => {
+                visible += &rest[start..];
+                return visible;
+            }
+        }
+    }
+    visible + rest
+}
+
+/// Cells a line of polybar output takes on the bar, measured like `width()` once the markup
+/// is stripped.
+pub fn visual_width_of_polybar_string(s: &str) -> usize {
+    width(&strip_polybar_markup(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_by_grapheme_and_width() {
+        assert_eq!(truncate_with_ellipsis("Short", 10, LengthUnit::Columns), "Short");
+        assert_eq!(truncate_with_ellipsis("Bohemian Rhapsody", 10, LengthUnit::Columns), "Bohemian…");
+        assert_eq!(truncate_with_ellipsis("漢字漢字", 6, LengthUnit::Columns), "漢字…");
+        assert_eq!(truncate_with_ellipsis("e\u{301}e\u{301}e\u{301}", 2, LengthUnit::Columns), "e\u{301}…");
+        assert_eq!(truncate_with_ellipsis("abc", 0, LengthUnit::Columns), "");
+    }
+
+    #[test]
+    fn measures_grapheme_clusters() {
+        // Arabic presentation forms are one cell each, harakat add nothing.
+        assert_eq!(width("ﺳﻼﻡ"), 3);
+        assert_eq!(width("مَرْحَبًا"), 5);
+        // Thai above/below vowels and tone marks combine with their consonant.
+        assert_eq!(width("กี่"), 1);
+        assert_eq!(width("ภาษาไทย"), 7);
+        // VS16 selects emoji presentation but adds no width of its own.
+        assert_eq!(width("\u{2764}\u{fe0f}"), width("\u{2764}"));
+        assert_eq!(width("\u{301}"), 0);
+        assert_eq!(width("e\u{301}"), width("\u{e9}"));
+        assert_eq!(truncate_with_ellipsis("กี่กี่กี่", 2, LengthUnit::Columns), "กี่…");
+    }
+
+    #[test]
+    fn counts_chars_regardless_of_width() {
+        assert_eq!(length("漢字a", LengthUnit::Chars), 3);
+        assert_eq!(length("e\u{301}กี่", LengthUnit::Chars), 2);
+        assert_eq!(truncate_with_ellipsis("漢字漢字", 3, LengthUnit::Chars), "漢字…");
+    }
+
+    #[test]
+    fn detects_predominantly_rtl_text() {
+        assert!(is_predominantly_rtl("שלום עולם"));
+        assert!(is_predominantly_rtl("أغنية طويلة - Fairuz"));
+        assert!(!is_predominantly_rtl("Bohemian Rhapsody - Queen"));
+        assert!(!is_predominantly_rtl("Nour El Ain - عمرو دياب"));
+        assert!(!is_predominantly_rtl("123 - ..."));
+    }
+
+    #[test]
+    fn mixed_direction_text_measures_and_truncates() {
+        let mixed = "سلام - Queen";
+        assert_eq!(width(mixed), 12);
+        assert_eq!(truncate_with_ellipsis(mixed, 6, LengthUnit::Columns), "سلام…");
+        assert_eq!(width(&truncate_with_ellipsis("שָׁלוֹם Bohemian", 7, LengthUnit::Columns)), 7);
+    }
+
+    #[test]
+    fn allocates_proportionally_and_redistributes_slack() {
+        assert_eq!(allocate_widths(&[30, 30], &[2, 1], 15), vec![10, 5]);
+        assert_eq!(allocate_widths(&[4, 30], &[1, 1], 20), vec![4, 16]);
+        assert_eq!(allocate_widths(&[30, 30], &[1, 0], 12), vec![12, 0]);
+    }
+
+    #[test]
+    fn renders_progress_as_filled_cells() {
+        assert_eq!(render_text_progress(90, 180, 10, '█', '░'), "█████░░░░░");
+        assert_eq!(render_text_progress(0, 180, 4, '#', '-'), "----");
+        assert_eq!(render_text_progress(200, 180, 4, '#', '-'), "####");
+        assert_eq!(render_text_progress(5, 0, 3, '#', '-'), "###");
+    }
+
+    #[test]
+    fn strips_nested_and_malformed_markup() {
+        assert_eq!(strip_polybar_markup("%{F#ABC}%{T1}Song%{T-}%{F-}"), "Song");
+        assert_eq!(strip_polybar_markup("%{A1:notify-send {hi}:}x%{A}y"), "xy");
+        assert_eq!(strip_polybar_markup("%{A1:echo }:}x%{A}"), ":}x");
+        assert_eq!(strip_polybar_markup("a %{T1 and %{b"), "a %{T1 and %{b");
+        assert_eq!(strip_polybar_markup("50% {off} %{T2}%"), "50% {off} %");
+        assert_eq!(strip_polybar_markup("%{}%{{}}ok"), "ok");
+    }
+
+    #[test]
+    fn measures_polybar_output_without_its_markup() {
+        assert_eq!(visual_width_of_polybar_string("%{T2}♫%{T-} %{T1}漢字 Song%{T-}"), 11);
+        assert_eq!(visual_width_of_polybar_string("%{A1:playerctl next:}⏭%{A}"), 1);
+        assert_eq!(visual_width_of_polybar_string("100% done %{unclosed"), 20);
+    }
+}
@@ -0,0 +1,85 @@
+use unicode_general_category::{get_general_category, GeneralCategory};
+use unicode_normalization::char::decompose_compatible;
+
+use crate::config::Unmapped;
+
+/// Lower-case letters that don't come apart into ASCII under compatibility decomposition, and
+/// what they become. Checked before decomposing, so `й` reads `y` rather than a bare `i`.
+const MAPPINGS: [(char, &str); 83] = [
+    // Latin
+    ('ß', "ss"), ('æ', "ae"), ('œ', "oe"), ('ø', "o"), ('ł', "l"), ('đ', "d"), ('ð', "d"), ('þ', "th"), ('ı', "i"),
+    // Cyrillic, by the usual English romanisation of Russian, plus Ukrainian letters
+    ('а', "a"), ('б', "b"), ('в', "v"), ('г', "g"), ('д', "d"), ('е', "e"), ('ё', "yo"), ('ж', "zh"), ('з', "z"),
+    ('и', "i"), ('й', "y"), ('к', "k"), ('л', "l"), ('м', "m"), ('н', "n"), ('о', "o"), ('п', "p"), ('р', "r"),
+    ('с', "s"), ('т', "t"), ('у', "u"), ('ф', "f"), ('х', "kh"), ('ц', "ts"), ('ч', "ch"), ('ш', "sh"), ('щ', "shch"),
+    ('ъ', ""), ('ы', "y"), ('ь', ""), ('э', "e"), ('ю', "yu"), ('я', "ya"), ('і', "i"), ('ї', "yi"), ('є', "ye"),
+    ('ґ', "g"),
+    // Greek; accented vowels decompose to these first
+    ('α', "a"), ('β', "v"), ('γ', "g"), ('δ', "d"), ('ε', "e"), ('ζ', "z"), ('η', "i"), ('θ', "th"), ('ι', "i"),
+    ('κ', "k"), ('λ', "l"), ('μ', "m"), ('ν', "n"), ('ξ', "x"), ('ο', "o"), ('π', "p"), ('ρ', "r"), ('σ', "s"),
+    ('ς', "s"), ('τ', "t"), ('υ', "y"), ('φ', "f"), ('χ', "ch"), ('ψ', "ps"), ('ω', "o"),
+    // Punctuation
+    ('‘', "'"), ('’', "'"), ('‚', "'"), ('“', "\""), ('”', "\""), ('„', "\""), ('«', "\""), ('»', "\""), ('–', "-"),
+    ('—', "-"), ('•', "*"), ('×', "x"),
+];
+
+/// Converts `text` to ASCII for `transliterate`: accents are dropped, compatibility forms such
+/// as full-width letters and ligatures are unfolded, and Latin, Cyrillic and Greek letters
+/// outside ASCII are spelled out. Anything else, like CJK, is replaced by `?` or dropped.
+pub fn to_ascii(text: &str, unmapped: Unmapped) -> String {
+    let mut ascii = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if ch.is_ascii() {
+            ascii.push(ch);
+        } else if let Some(mapped) = lookup(ch) {
+            ascii += &mapped;
+        } else {
+            let mut parts = Vec::new();
+            decompose_compatible(ch, |part| parts.push(part));
+            for part in parts {
+                if part.is_ascii() {
+                    ascii.push(part);
+                } else if get_general_category(part) == GeneralCategory::NonspacingMark {
+                    continue;
+                } else if let Some(mapped) = lookup(part) {
+                    ascii += &mapped;
+                } else if unmapped == Unmapped::Replace {
+                    ascii.push('?');
+                }
+            }
+        }
+    }
+    ascii
+}
+
+/// Looks up a letter of either case, spelling an upper-case one with a capital, as `Zh`.
+fn lookup(ch: char) -> Option<String> {
+    let mut lower = ch.to_lowercase();
+    let (Some(lower_ch), None) = (lower.next(), lower.next()) else { return None };
+    let &(_, mapped) = MAPPINGS.iter().find(|(from, _)| *from == lower_ch)?;
+    if lower_ch == ch {
+        return Some(mapped.to_string());
+    }
+    let mut chars = mapped.chars();
+    Some(chars.next().map_or(String::new(), |first| first.to_ascii_uppercase().to_string() + chars.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spells_out_common_scripts_in_ascii() {
+        assert_eq!(to_ascii("Sigur Rós – Hoppípolla", Unmapped::Replace), "Sigur Ros - Hoppipolla");
+        assert_eq!(to_ascii("Земфира — Хочешь?", Unmapped::Replace), "Zemfira - Khochesh?");
+        assert_eq!(to_ascii("Чайковский", Unmapped::Replace), "Chaykovskiy");
+        assert_eq!(to_ascii("Μάνος Χατζιδάκις", Unmapped::Replace), "Manos Chatzidakis");
+        assert_eq!(to_ascii("Straße ＡＢＣ ﬁne", Unmapped::Replace), "Strasse ABC fine");
+    }
+
+    #[test]
+    fn replaces_or_drops_what_has_no_mapping() {
+        assert_eq!(to_ascii("宇多田ヒカル - First Love", Unmapped::Replace), "?????? - First Love");
+        assert_eq!(to_ascii("宇多田ヒカル - First Love", Unmapped::Drop), " - First Love");
+    }
+}
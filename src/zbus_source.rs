@@ -0,0 +1,266 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use zbus::blocking::fdo::DBusProxy;
+use zbus::blocking::{connection, Connection};
+use zbus::zvariant::{OwnedValue, Value};
+
+use crate::source::{
+    update_names, BusNames, MediaSource, MetadataValue, MprisMetadata, PlaybackStatus, PlayerCache, PlayerInfo, MPRIS_PATH,
+    MPRIS_PREFIX, PLAYERCTLD_BUS_NAME, PLAYER_INTERFACE, TIMEOUT, UNSUPPORTED_ERRORS,
+};
+
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// MPRIS players on the session bus, read through zbus rather than libdbus. Behaves like
+/// `DbusMediaSource`, which it replaces when built with the `zbus` feature.
+pub struct ZbusMediaSource {
+    connection: Connection,
+    /// The MPRIS players' bus names and owners, kept up to date by a thread that follows
+    /// `NameOwnerChanged`.
+    names: Arc<Mutex<BusNames>>,
+    /// Cleared when that thread stops, after which the names are listed again each tick.
+    watching: Arc<AtomicBool>,
+    use_playerctld: bool,
+    cache: RefCell<PlayerCache>,
+}
+
+impl ZbusMediaSource {
+    pub fn new(use_playerctld: bool) -> Result<Self, Box<dyn Error>> {
+        let connection = connection::Builder::session()?.method_timeout(TIMEOUT).build()?;
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let dbus = DBusProxy::new(&connection)?;
+        let changes = dbus.receive_name_owner_changed()?;
+        let watching = Arc::new(AtomicBool::new(true));
+        let (tracked, still_watching) = (Arc::clone(&names), Arc::clone(&watching));
+        std::thread::spawn(move || {
+            for change in changes {
+                if let Ok(args) = change.args() {
+                    let new_owner = args.new_owner.as_ref().map_or("", |owner| owner.as_str());
+                    update_names(&mut tracked.lock().unwrap(), args.name.to_string(), new_owner);
+                }
+            }
+            warn!("stopped following players joining and leaving the bus, listing them each time instead");
+            still_watching.store(false, Ordering::Relaxed);
+        });
+        // Listed after subscribing, so a player that starts in between isn't missed.
+        *names.lock().unwrap() = list_players(&dbus)?;
+        Ok(ZbusMediaSource { connection, names, watching, use_playerctld, cache: RefCell::default() })
+    }
+
+    /// Reads a property with `Properties.Get`. zbus proxies cache properties, which would go
+    /// stale between ticks, so the call is made by hand.
+    fn get<T>(&self, bus_name: &str, interface: &str, property: &str) -> Result<T, zbus::Error>
+    where
+        T: TryFrom<OwnedValue>,
+        T::Error: Into<zbus::Error>,
+    {
+        let reply = self.connection.call_method(Some(bus_name), MPRIS_PATH, Some(PROPERTIES_INTERFACE), "Get", &(interface, property))?;
+        let value: OwnedValue = reply.body().deserialize()?;
+        T::try_from(value).map_err(Into::into)
+    }
+
+    /// Reads the capabilities and identity of a player new to the bus. One that doesn't
+    /// answer is assumed to be controllable, as most are.
+    fn discover(&self, name: &str) -> PlayerInfo {
+        let mut player = PlayerInfo::new(name.to_string());
+        player.can_control = self.get(name, PLAYER_INTERFACE, "CanControl").unwrap_or(true);
+        player.identity = self.get(name, "org.mpris.MediaPlayer2", "Identity").unwrap_or_default();
+        player
+    }
+}
+
+impl MediaSource for ZbusMediaSource {
+    /// Lists the MPRIS players on the bus. With `use_playerctld` and playerctld running, it is
+    /// the only player, since it already follows whichever player was used last.
+    fn players(&self) -> Result<Vec<PlayerInfo>, Box<dyn Error>> {
+        if !self.watching.load(Ordering::Relaxed) {
+            *self.names.lock().unwrap() = list_players(&DBusProxy::new(&self.connection)?)?;
+        }
+        let names = self.names.lock().unwrap().clone();
+        let mut cache = self.cache.borrow_mut();
+        cache.forget_stale(&names);
+
+        if self.use_playerctld && names.iter().any(|(name, _)| name == PLAYERCTLD_BUS_NAME) {
+            let mut player = PlayerInfo::new(PLAYERCTLD_BUS_NAME.to_string());
+            let active: Result<Vec<String>, _> = self.get(PLAYERCTLD_BUS_NAME, "com.github.altdesktop.playerctld", "PlayerNames");
+            if let Some(app) = active.ok().and_then(|names| names.into_iter().next()) {
+                player.app = app;
+            }
+            return Ok(vec![player]);
+        }
+
+        let players = names.iter().filter(|(name, _)| name != PLAYERCTLD_BUS_NAME);
+        Ok(players.map(|(name, owner)| cache.player(name, owner, |name| self.discover(name))).collect())
+    }
+
+    fn status(&self, player: &PlayerInfo) -> Result<PlaybackStatus, Box<dyn Error>> {
+        let status: String = self.get(&player.name, PLAYER_INTERFACE, "PlaybackStatus")?;
+        Ok(status.parse()?)
+    }
+
+    fn metadata(&self, player: &PlayerInfo) -> Result<MprisMetadata, Box<dyn Error>> {
+        let props: HashMap<String, OwnedValue> = self.get(&player.name, PLAYER_INTERFACE, "Metadata")?;
+        Ok(props.iter().filter_map(|(key, value)| Some((key.clone(), metadata_value(value)?))).collect())
+    }
+
+    fn rate(&self, player: &PlayerInfo) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(&player.name, PLAYER_INTERFACE, "Rate")?)
+    }
+
+    /// Many players don't implement `Position`. The first time one says so it is logged and
+    /// remembered, and from then on reading it fails without calling the player.
+    fn position(&self, player: &PlayerInfo) -> Result<i64, Box<dyn Error>> {
+        if self.cache.borrow().lacks_position(player) {
+            return Err("Position is not supported".into());
+        }
+        self.get(&player.name, PLAYER_INTERFACE, "Position").map_err(|e| {
+            if is_unsupported(&e) {
+                verbose!("{} doesn't support Position, so its position and progress stay blank: {}", player.name, e);
+                self.cache.borrow_mut().set_lacks_position(player, &self.names.lock().unwrap());
+            }
+            e.into()
+        })
+    }
+
+    fn volume(&self, player: &PlayerInfo) -> Result<f64, Box<dyn Error>> {
+        Ok(self.get(&player.name, PLAYER_INTERFACE, "Volume")?)
+    }
+
+    fn set_volume(&self, player: &PlayerInfo, volume: f64) -> Result<(), Box<dyn Error>> {
+        let body = (PLAYER_INTERFACE, "Volume", Value::from(volume));
+        self.connection.call_method(Some(player.name.as_str()), MPRIS_PATH, Some(PROPERTIES_INTERFACE), "Set", &body)?;
+        Ok(())
+    }
+
+    /// Asked of the application itself, as playerctld doesn't pass on the root interface.
+    fn desktop_entry(&self, player: &PlayerInfo) -> Result<String, Box<dyn Error>> {
+        Ok(self.get(&player.app, "org.mpris.MediaPlayer2", "DesktopEntry")?)
+    }
+
+    fn call(&self, player: &PlayerInfo, method: &str) -> Result<(), Box<dyn Error>> {
+        self.connection.call_method(Some(player.name.as_str()), MPRIS_PATH, Some(PLAYER_INTERFACE), method, &())?;
+        Ok(())
+    }
+}
+
+/// The MPRIS names on the bus with their owners. A player that leaves between the two calls
+/// is left out.
+fn list_players(dbus: &DBusProxy) -> Result<BusNames, zbus::Error> {
+    let mut names = BusNames::new();
+    for name in dbus.list_names()?.into_iter().filter(|name| name.starts_with(MPRIS_PREFIX)) {
+        if let Ok(owner) = dbus.get_name_owner(name.as_ref()) {
+            update_names(&mut names, name.to_string(), owner.as_str());
+        }
+    }
+    Ok(names)
+}
+
+/// Reads one `Metadata` variant, or `None` for types no MPRIS field uses, like nested dicts.
+/// `mpris:trackid` is an object path, which is read as text like the strings.
+fn metadata_value(value: &Value) -> Option<MetadataValue> {
+    match value {
+        Value::Str(text) => Some(MetadataValue::Text(text.to_string())),
+        Value::ObjectPath(path) => Some(MetadataValue::Text(path.to_string())),
+        Value::Array(items) => Some(MetadataValue::List(
+            items.iter().filter_map(|item| if let Value::Str(text) = item { Some(text.to_string()) } else { None }).collect(),
+        )),
+        Value::I64(number) => Some(MetadataValue::Int(*number)),
+        Value::U64(number) => i64::try_from(*number).ok().map(MetadataValue::Int),
+        Value::I32(number) => Some(MetadataValue::Int((*number).into())),
+        Value::U32(number) => Some(MetadataValue::Int((*number).into())),
+        Value::I16(number) => Some(MetadataValue::Int((*number).into())),
+        Value::U16(number) => Some(MetadataValue::Int((*number).into())),
+        Value::U8(number) => Some(MetadataValue::Int((*number).into())),
+        Value::F64(number) => Some(MetadataValue::Float(*number)),
+        Value::Value(inner) => metadata_value(inner),
+        _ => None,
+    }
+}
+
+fn is_unsupported(e: &zbus::Error) -> bool {
+    matches!(e, zbus::Error::MethodError(name, _, _) if UNSUPPORTED_ERRORS.contains(&name.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "dbus")]
+    use crate::dbus_source::DbusMediaSource;
+    use zbus::zvariant::ObjectPath;
+
+    #[test]
+    fn reads_metadata_variants() {
+        let artists = Value::new(vec!["Queen", "David Bowie"]);
+        assert_eq!(metadata_value(&Value::from("Under Pressure")), Some(MetadataValue::Text("Under Pressure".to_string())));
+        assert_eq!(metadata_value(&artists), Some(MetadataValue::List(vec!["Queen".to_string(), "David Bowie".to_string()])));
+        let track_id = Value::from(ObjectPath::try_from("/com/spotify/track/1").unwrap());
+        assert_eq!(metadata_value(&track_id), Some(MetadataValue::Text("/com/spotify/track/1".to_string())));
+        assert_eq!(metadata_value(&Value::from(248_000_000_u64)), Some(MetadataValue::Int(248_000_000)));
+        assert_eq!(metadata_value(&Value::from(7_i32)), Some(MetadataValue::Int(7)));
+        assert_eq!(metadata_value(&Value::new(Value::from(0.5))), Some(MetadataValue::Float(0.5)));
+        assert_eq!(metadata_value(&Value::from(true)), None);
+    }
+
+    #[cfg(feature = "dbus")]
+    struct TestPlayer;
+
+    #[cfg(feature = "dbus")]
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl TestPlayer {
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            "Paused".to_string()
+        }
+
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            let track_id = ObjectPath::try_from("/org/mpris/MediaPlayer2/track/1").unwrap();
+            [
+                ("mpris:trackid", Value::from(track_id)),
+                ("mpris:length", Value::from(248_000_000_i64)),
+                ("xesam:title", Value::from("Under Pressure")),
+                ("xesam:artist", Value::new(vec!["Queen", "David Bowie"])),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.try_into().unwrap()))
+            .collect()
+        }
+
+        #[zbus(property)]
+        fn rate(&self) -> f64 {
+            1.0
+        }
+
+        #[zbus(property)]
+        fn can_control(&self) -> bool {
+            false
+        }
+    }
+
+    /// Serves a player on the real session bus and reads it through both backends, which
+    /// must agree. Needs a bus, so it is ignored by default; run it with
+    /// `dbus-run-session -- cargo test --features zbus -- --ignored`.
+    #[test]
+    #[cfg(feature = "dbus")]
+    #[ignore]
+    fn reads_a_player_on_the_session_bus_like_the_dbus_backend() {
+        let name = format!("org.mpris.MediaPlayer2.zbustest.instance{}", std::process::id());
+        let server = connection::Builder::session().unwrap().name(name.as_str()).unwrap().serve_at(MPRIS_PATH, TestPlayer).unwrap().build().unwrap();
+        let zbus = ZbusMediaSource::new(false).unwrap();
+        let dbus = DbusMediaSource::new(false).unwrap();
+        let find = |source: &dyn MediaSource| source.players().unwrap().into_iter().find(|player| player.name == name).unwrap();
+        let (player, expected) = (find(&zbus), find(&dbus));
+        let owner = server.unique_name().unwrap().to_string();
+        assert!(zbus.names.lock().unwrap().contains(&(name.clone(), owner)));
+        assert!(!player.can_control && !expected.can_control);
+        assert_eq!(zbus.status(&player).unwrap(), dbus.status(&expected).unwrap());
+        assert_eq!(zbus.metadata(&player).unwrap(), dbus.metadata(&expected).unwrap());
+        assert_eq!(zbus.metadata(&player).unwrap()["xesam:artist"].to_text(", "), "Queen, David Bowie");
+        assert_eq!(zbus.rate(&player).unwrap(), 1.0);
+        assert!(zbus.position(&player).is_err() && dbus.position(&expected).is_err());
+    }
+}